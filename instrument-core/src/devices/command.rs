@@ -0,0 +1,307 @@
+use crate::{devices::{Command, Configuration}, networking::Request};
+use std::collections::HashMap;
+
+/// Returns an SCPI command as string from all available arguments
+/// `inputs` holds the values entered for the placeholders like `<FREQ>`, keyed by the placeholder name.
+/// Errors if a placeholder has no value or it does not match the [`Parameter`](super::parameter::Parameter)
+/// declared for it.
+pub fn make_scpi_command(cmd: Command, channel: u8, arg: &str, inputs: &HashMap<String, String>) -> Result<String, String> {
+    let template = format!("{}{}", cmd.scpi, arg);
+    let mut values = HashMap::from([("CH".to_string(), channel.to_string())]);
+    for name in placeholders(&template) {
+        let label = placeholder_label(&cmd, &name);
+        let input = inputs.get(&name).map(|input| input.trim()).unwrap_or_default();
+        if input.is_empty() {
+            return Err(format!("{} requires a value", label));
+        }
+        let value = match cmd.parameters.get(&name) {
+            Some(parameter) => parameter.validate(&label, input)?,
+            None => input.to_string(),
+        };
+        values.insert(name, value);
+    }
+    // Filled in a single pass, so values containing something like `<AMP>` are sent as they are
+    let mut scpi: String = scan(&template).into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => text,
+            Piece::Placeholder(name) => values[name].as_str(),
+        })
+        .collect();
+    scpi.push('\n');
+
+    Ok(scpi)
+}
+
+/// Part of a template, as split by [`scan`]
+enum Piece<'a> {
+    /// Text sent as it is
+    Text(&'a str),
+    /// Name of a placeholder like `<FREQ>`, without the angle brackets
+    Placeholder(&'a str),
+}
+
+/// Split `template` into text and placeholders, angle brackets not enclosing a valid name are text
+fn scan(template: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut text_start = 0;
+    let mut position = 0;
+    while let Some(offset) = template[position..].find('<') {
+        let start = position + offset;
+        let end = match template[start + 1..].find('>') {
+            Some(length) => start + 1 + length,
+            None => break,
+        };
+        let name = &template[start + 1..end];
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_') {
+            if text_start < start {
+                pieces.push(Piece::Text(&template[text_start..start]));
+            }
+            pieces.push(Piece::Placeholder(name));
+            text_start = end + 1;
+            position = end + 1;
+        } else {
+            position = start + 1;
+        }
+    }
+    if text_start < template.len() {
+        pieces.push(Piece::Text(&template[text_start..]));
+    }
+    pieces
+}
+
+/// Returns the names of the placeholders like `<FREQ>` in `template` in order of appearance, without duplicates
+/// The channel placeholder `<CH>` is filled in from the channel selection and thus not included.
+pub fn placeholders(template: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for piece in scan(template) {
+        match piece {
+            Piece::Placeholder(name) if name != "CH" && !names.iter().any(|n| n == name) => names.push(name.into()),
+            _ => {}
+        }
+    }
+    names
+}
+
+/// Returns the [`Request`] for the command `name` of `config` with `argument` typed by the user, e.g. on the command line
+/// `argument` picks one of the values via [`match_value`]. It may be left out if the command has a single value or,
+/// for a `query`, a single value reading the setting back. `inputs` fill in the other placeholders.
+/// A `query` reads a reply even if the command is no query.
+pub fn build_request(config: &Configuration, name: &str, argument: Option<&str>, channel: u8, inputs: &HashMap<String, String>, query: bool) -> Result<Request, String> {
+    let cmd = config.command(name)
+        .ok_or_else(|| format!("Unknown command \"{}\"", name))?;
+    let choices = || cmd.values.iter().map(|value| format!("\"{}\"", value.trim())).collect::<Vec<String>>().join(", ");
+    let queries: Vec<&String> = cmd.values.iter().filter(|value| is_query(&format!("{}{}", cmd.scpi, value))).collect();
+    let (value, mut matched) = match argument {
+        Some(argument) => match_value(cmd, argument)
+            .ok_or_else(|| format!("\"{}\" is no argument of {}, expected one of {}", argument, cmd.name, choices()))?,
+        None if cmd.values.len() <= 1 => (cmd.values.first().cloned().unwrap_or_default(), HashMap::new()),
+        // Without an argument, a query asks for the one value that reads the setting back
+        None if query && queries.len() == 1 => (queries[0].clone(), HashMap::new()),
+        None => return Err(format!("{} requires an argument, one of {}", cmd.name, choices())),
+    };
+    matched.extend(inputs.iter().map(|(name, input)| (name.clone(), input.clone())));
+
+    if cmd.channel && !(1..=config.device.channels).contains(&channel) {
+        return Err(format!("Channel {} is not available, the device has {}", channel, config.device.channels));
+    }
+    let scpi = make_scpi_command(cmd.clone(), channel, &value, &matched)?;
+    let mut request = make_request(cmd, scpi);
+    if query && request.reply_lines == 0 {
+        request.reply_lines = cmd.lines.unwrap_or(1);
+    }
+    Ok(request)
+}
+
+/// Returns the value of `cmd` an `argument` typed on the command line stands for, and the placeholder inputs it contains
+/// `argument` is either one of the values itself, e.g. `on`, or a value with its placeholders filled in, e.g. `1000`
+/// for `" <TXT>"`. Values are compared case-insensitively, the most specific one matching wins.
+pub fn match_value(cmd: &Command, argument: &str) -> Option<(String, HashMap<String, String>)> {
+    let argument = argument.trim();
+    if let Some(value) = cmd.values.iter().find(|value| value.trim().eq_ignore_ascii_case(argument)) {
+        return Some((value.clone(), HashMap::new()));
+    }
+    let mut templates: Vec<&String> = cmd.values.iter().filter(|value| !placeholders(value).is_empty()).collect();
+    // Values with more fixed text are more specific, e.g. ":HIGH <TXT>" before "<TXT>"
+    templates.sort_by_key(|value| std::cmp::Reverse(literal_length(value)));
+    templates.into_iter()
+        .find_map(|value| match_template(value.trim(), argument).map(|inputs| (value.clone(), inputs)))
+}
+
+/// Returns the inputs for the placeholders of `template` if `text` is `template` with them filled in
+fn match_template(template: &str, text: &str) -> Option<HashMap<String, String>> {
+    let mut inputs = HashMap::new();
+    let (mut template, mut text) = (template, text);
+    loop {
+        let (start, name) = match next_placeholder(template) {
+            Some(placeholder) => placeholder,
+            None => return template.eq_ignore_ascii_case(text).then_some(inputs),
+        };
+        let literal = &template[..start];
+        if !text.is_char_boundary(literal.len()) || !text[..literal.len()].eq_ignore_ascii_case(literal) {
+            return None;
+        }
+        text = &text[literal.len()..];
+        template = &template[start + name.len() + 2..];
+        // The input runs up to the fixed text following the placeholder
+        let next = next_placeholder(template).map_or(template.len(), |(start, _)| start);
+        let literal = &template[..next];
+        let end = if template.is_empty() {
+            text.len()
+        } else if literal.is_empty() {
+            // Two placeholders in a row cannot be told apart
+            return None;
+        } else {
+            text.to_ascii_lowercase().find(&literal.to_ascii_lowercase())?
+        };
+        let input = text[..end].trim();
+        if input.is_empty() {
+            return None;
+        }
+        inputs.insert(name, input.to_string());
+        text = &text[end..];
+    }
+}
+
+/// Returns the position and name of the first placeholder like `<FREQ>` in `template`
+fn next_placeholder(template: &str) -> Option<(usize, String)> {
+    let mut position = 0;
+    for piece in scan(template) {
+        match piece {
+            Piece::Text(text) => position += text.len(),
+            Piece::Placeholder(name) => return Some((position, name.into())),
+        }
+    }
+    None
+}
+
+/// Returns the length of `template` without its placeholders
+fn literal_length(template: &str) -> usize {
+    let mut template = template.trim().to_string();
+    while let Some((start, name)) = next_placeholder(&template) {
+        template.replace_range(start..start + name.len() + 2, "");
+    }
+    template.len()
+}
+
+/// Returns the label of the input for placeholder `name` of `cmd`
+/// The generic `<TXT>` placeholder is labelled with the name of the command.
+pub fn placeholder_label(cmd: &Command, name: &str) -> String {
+    if name == "TXT" { cmd.name.clone() } else { name.into() }
+}
+
+/// Returns whether the SCPI command `scpi` is a query, i.e. whether a header contains a `?`
+/// Multiple commands joined by `;` are a query if any of them is.
+pub fn is_query(scpi: &str) -> bool {
+    scpi.split(';')
+        .filter_map(|command| command.split_whitespace().next())
+        .any(|header| header.contains('?'))
+}
+
+/// Returns a [`Request`] for the complete SCPI command `scpi` built from `cmd`
+/// A reply is expected if the command is a query or is marked as one in the config.
+pub fn make_request(cmd: &Command, scpi: String) -> Request {
+    let reply_lines = if cmd.query || is_query(&scpi) { cmd.lines.unwrap_or(1) } else { 0 };
+    Request { command: scpi, reply_lines, error_query: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::parameter::{Parameter, ParameterType};
+
+    #[test]
+    fn test_make_scpi_command() {
+
+        let cmd = Command {
+            channel: true,
+            name: "TestCommand".to_string(),
+            scpi: "OUT<CH> ".into(),
+            values: vec!["on".into(), "off".into()],
+            ..Default::default()
+        };
+
+        let res = make_scpi_command(cmd, 1, "on", &HashMap::new());
+        assert_eq!(res.unwrap(), "OUT1 on\n".to_string())
+    }
+
+    #[test]
+    fn test_values_are_not_substituted_again() {
+        let cmd = Command { name: "Text".into(), scpi: ":DISPlay:TEXT ".into(), values: vec!["\"<MSG>\",<ROW>".into()], ..Default::default() };
+        let inputs = HashMap::from([("MSG".to_string(), "<ROW> <CH>".to_string()), ("ROW".to_string(), "2".to_string())]);
+        assert_eq!(make_scpi_command(cmd, 1, "\"<MSG>\",<ROW>", &inputs), Ok(":DISPlay:TEXT \"<ROW> <CH>\",2\n".into()));
+    }
+
+    #[test]
+    fn test_make_scpi_command_validates_parameter() {
+        let cmd = Command {
+            channel: true,
+            name: "Voltage".to_string(),
+            scpi: ":SOURce<CH>:VOLTage".into(),
+            values: vec![" <TXT>".into()],
+            parameters: [("TXT".into(), Parameter { kind: ParameterType::Float, min: Some(0.0), max: Some(20.0), ..Default::default() })].into(),
+            ..Default::default()
+        };
+        let inputs = |value: &str| HashMap::from([("TXT".to_string(), value.to_string())]);
+        assert_eq!(make_scpi_command(cmd.clone(), 1, " <TXT>", &inputs("2.5")), Ok(":SOURce1:VOLTage 2.5\n".into()));
+        assert_eq!(make_scpi_command(cmd.clone(), 1, " <TXT>", &inputs("500")), Err("Voltage must be 0 to 20, got 500".into()));
+        // The parameter only applies to values with a placeholder
+        assert_eq!(make_scpi_command(cmd, 2, "?", &inputs("500")), Ok(":SOURce2:VOLTage?\n".into()));
+    }
+
+    #[test]
+    fn test_named_placeholders() {
+        assert_eq!(placeholders(":SOURce<CH>:APPLy:SINusoid <FREQ>,<AMP>,<OFFSET>,<FREQ>"), vec!["FREQ", "AMP", "OFFSET"]);
+        assert_eq!(placeholders(":OUTPut<CH> on"), Vec::<String>::new());
+        assert_eq!(placeholders("<lower> <TXT"), Vec::<String>::new());
+
+        let cmd = Command {
+            name: "Apply Sine".to_string(),
+            scpi: ":SOURce<CH>:APPLy:SINusoid ".into(),
+            parameters: [("AMP".into(), Parameter { kind: ParameterType::Float, max: Some(20.0), ..Default::default() })].into(),
+            ..Default::default()
+        };
+        let mut inputs = HashMap::from([("FREQ".to_string(), "1000".to_string())]);
+        assert_eq!(make_scpi_command(cmd.clone(), 2, "<FREQ>,<AMP>", &inputs), Err("AMP requires a value".into()));
+        inputs.insert("AMP".into(), "25".into());
+        assert_eq!(make_scpi_command(cmd.clone(), 2, "<FREQ>,<AMP>", &inputs), Err("AMP must be at most 20, got 25".into()));
+        inputs.insert("AMP".into(), "2.5".into());
+        assert_eq!(make_scpi_command(cmd, 2, "<FREQ>,<AMP>", &inputs), Ok(":SOURce2:APPLy:SINusoid 1000,2.5\n".into()));
+    }
+
+    #[test]
+    fn test_match_value() {
+        let cmd = Command {
+            name: "Voltage".to_string(),
+            scpi: ":SOURce<CH>:VOLTage".into(),
+            values: vec![" <TXT>".into(), ":HIGH <TXT>".into(), ":APPLy <FREQ>,<AMP>".into(), "?".into(), " ON".into()],
+            ..Default::default()
+        };
+        let inputs = |pairs: &[(&str, &str)]| pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        assert_eq!(match_value(&cmd, "?"), Some(("?".into(), HashMap::new())));
+        assert_eq!(match_value(&cmd, "on"), Some((" ON".into(), HashMap::new())));
+        assert_eq!(match_value(&cmd, "2.5"), Some((" <TXT>".into(), inputs(&[("TXT", "2.5")]))));
+        assert_eq!(match_value(&cmd, ":high 3"), Some((":HIGH <TXT>".into(), inputs(&[("TXT", "3")]))));
+        assert_eq!(match_value(&cmd, ":APPLy 1k, 2"), Some((":APPLy <FREQ>,<AMP>".into(), inputs(&[("FREQ", "1k"), ("AMP", "2")]))));
+        assert_eq!(match_value(&Command { values: vec!["on".into(), "off".into()], ..Default::default() }, "1"), None);
+    }
+
+    #[test]
+    fn test_is_query() {
+        assert!(is_query("*IDN?\n"));
+        assert!(is_query(":SOURce1:FREQ?"));
+        assert!(is_query(":OUTPut1 on;:SYSTem:ERRor?\n"));
+        assert!(!is_query(":OUTPut1 on\n"));
+        // A question mark in a string argument does not make a query
+        assert!(!is_query(":DISPlay:TEXT \"Ready?\"\n"));
+    }
+
+    #[test]
+    fn test_make_request() {
+        let mut cmd = Command { scpi: ":MEASure:ALL".into(), ..Default::default() };
+        assert_eq!(make_request(&cmd, ":MEASure:ALL\n".into()).reply_lines, 0);
+        cmd.query = true;
+        cmd.lines = Some(3);
+        assert_eq!(make_request(&cmd, ":MEASure:ALL\n".into()).reply_lines, 3);
+        assert_eq!(make_request(&Command::default(), "*IDN?\n".into()).reply_lines, 1);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,103 @@
+//! Completion of SCPI commands typed in the terminal from the commands of a [`Configuration`]
+use super::Configuration;
+
+/// Complete commands from the `scpi` templates and `values` of the configured commands
+#[derive(Debug, Clone, Default)]
+pub struct Completer {
+    /// Every command the config can produce, with `<CH>` replaced by each channel and other placeholders kept
+    candidates: Vec<String>,
+}
+
+/// Result of completing a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Completion {
+    /// No configured command starts with the input
+    None,
+    /// The input has been extended, as far as all matching commands agree
+    Extended(String),
+    /// Several commands match and the input cannot be extended, contains them
+    Ambiguous(Vec<String>),
+}
+
+impl Completer {
+    /// Collect the commands of `config` to complete from
+    pub fn from(config: &Configuration) -> Self {
+        let mut candidates: Vec<String> = Vec::new();
+        for cmd in &config.commands {
+            let channels: Vec<String> = if cmd.channel {
+                (1..=config.device.channels).map(|channel| channel.to_string()).collect()
+            } else {
+                vec![String::new()]
+            };
+            for channel in &channels {
+                for value in &cmd.values {
+                    let candidate = format!("{}{}", cmd.scpi, value).replace("<CH>", channel);
+                    let candidate = candidate.trim_end().to_string();
+                    if !candidate.is_empty() && !candidates.contains(&candidate) {
+                        candidates.push(candidate);
+                    }
+                }
+            }
+        }
+        Self { candidates }
+    }
+
+    /// Complete `input` to the longest text all matching commands start with, compared case-insensitively
+    /// Completion stops in front of a placeholder like `<FREQ>`, which has to be typed.
+    pub fn complete(&self, input: &str) -> Completion {
+        let matching: Vec<&String> = self.candidates.iter()
+            .filter(|candidate| starts_with_ignore_case(candidate, input))
+            .collect();
+        let first = match matching.first() {
+            Some(first) => *first,
+            None => return Completion::None,
+        };
+        // Longest prefix shared by all matches, counted in characters of the first one
+        let mut length = matching.iter().skip(1).fold(first.len(), |length, candidate| {
+            first.char_indices()
+                .zip(candidate.chars())
+                .take_while(|((index, a), b)| *index < length && a.eq_ignore_ascii_case(b))
+                .last()
+                .map_or(0, |((index, a), _)| index + a.len_utf8())
+        });
+        if let Some(placeholder) = first[input.len().min(length)..length].find('<') {
+            length = input.len().min(length) + placeholder;
+        }
+        if length > input.len() {
+            // Keep what has been typed and append the rest as configured
+            return Completion::Extended(format!("{}{}", input, &first[input.len()..length]));
+        }
+        if matching.len() == 1 {
+            return Completion::Extended(input.to_string());
+        }
+        Completion::Ambiguous(matching.into_iter().cloned().collect())
+    }
+}
+
+/// Whether `text` starts with `prefix`, ignoring the case of ASCII letters
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.len() >= prefix.len() && text.is_char_boundary(prefix.len()) && text[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete() {
+        let config = Configuration::from("../config/config.toml").unwrap();
+        let completer = Completer::from(&config);
+
+        assert_eq!(completer.complete(":OUTPut1 o"), Completion::Ambiguous(vec![":OUTPut1 on".into(), ":OUTPut1 off".into()]));
+        assert_eq!(completer.complete(":OUTPut1 of"), Completion::Extended(":OUTPut1 off".into()));
+        // Typed text is kept as it is, whatever its case
+        assert_eq!(completer.complete(":outp2 "), Completion::None);
+        assert_eq!(completer.complete(":output2 on"), Completion::Extended(":output2 on".into()));
+        assert_eq!(completer.complete("*ID"), Completion::Extended("*IDN?".into()));
+        // Completion stops at placeholders
+        assert_eq!(completer.complete(":SOURce1:APPLy:SIN"), Completion::Extended(":SOURce1:APPLy:SINusoid".into()));
+        assert_eq!(completer.complete(":SOURce1:APPLy:SINusoid "), Completion::Extended(":SOURce1:APPLy:SINusoid ".into()));
+        assert_eq!(completer.complete(":SOURce1:FREQ "), Completion::Extended(":SOURce1:FREQ ".into()));
+        assert_eq!(completer.complete(":BOGus"), Completion::None);
+    }
+}
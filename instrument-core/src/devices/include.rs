@@ -0,0 +1,192 @@
+//! Command sets shared between config files via `include = [...]`, either built into the app or loaded from a file
+use super::{validation::{self, Diagnostic}, Command};
+use config::{Config, File, FileFormat};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Command sets shipped with the app, by name
+const BUILTIN: [(&str, &str); 1] = [
+    ("ieee488.2-common", include_str!("common/ieee488.2-common.toml")),
+];
+
+/// Contents of an include file: commands and the sets they extend
+#[derive(Debug, Deserialize)]
+struct CommandSet {
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    commands: Vec<Command>,
+}
+
+/// Load the commands of all `includes` of `file` and extend `commands` by them
+/// `line` is the line of the `include` key, used for problems that concern an include as a whole.
+pub fn resolve(includes: &[String], commands: Vec<Command>, file: &str, line: Option<usize>) -> Result<Vec<Command>, Vec<Diagnostic>> {
+    let mut stack = vec![file.to_string()];
+    let base = load_all(includes, file, line, &mut stack)?;
+    Ok(extend(commands, base))
+}
+
+/// Load the commands of every set in `includes`, later sets override earlier ones
+/// `stack` holds the files currently being loaded to detect includes that go in circles.
+fn load_all(includes: &[String], file: &str, line: Option<usize>, stack: &mut Vec<String>) -> Result<Vec<Command>, Vec<Diagnostic>> {
+    let mut commands = Vec::new();
+    let mut diagnostics = Vec::new();
+    for name in includes {
+        match load(name, file, line, stack) {
+            Ok(loaded) => commands = merge(commands, loaded),
+            Err(errors) => diagnostics.extend(errors),
+        }
+    }
+    if diagnostics.is_empty() { Ok(commands) } else { Err(diagnostics) }
+}
+
+/// Load the command set `name` included by `file`, a built-in set or a path relative to `file`
+fn load(name: &str, file: &str, line: Option<usize>, stack: &mut Vec<String>) -> Result<Vec<Command>, Vec<Diagnostic>> {
+    let (path, source) = match BUILTIN.iter().find(|(builtin, _)| *builtin == name) {
+        Some((builtin, source)) => (builtin.to_string(), source.to_string()),
+        None => {
+            let path = path_of(name, file);
+            let source = std::fs::read_to_string(&path).map_err(|e| {
+                vec![Diagnostic::new(file, line, format!("Include \"{}\" could not be read: {}", name, e))]
+            })?;
+            (path, source)
+        }
+    };
+    if stack.contains(&path) {
+        let chain = stack.iter().chain(std::iter::once(&path)).map(String::as_str).collect::<Vec<_>>().join(" -> ");
+        return Err(vec![Diagnostic::new(file, line, format!("Include \"{}\" is circular: {}", name, chain))]);
+    }
+
+    let mut config = Config::new();
+    let set: CommandSet = config.merge(File::from_str(&source, FileFormat::Toml))
+        .and_then(|config| config.clone().try_into())
+        .map_err(|e| vec![Diagnostic::new(&path, None, e.to_string())])?;
+    let diagnostics = validation::validate_commands(&set.commands, &path, &source);
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+
+    stack.push(path.clone());
+    let line = validation::top_level_line(&source, "include");
+    let base = load_all(&set.include, &path, line, stack);
+    stack.pop();
+    Ok(extend(set.commands, base?))
+}
+
+/// Paths of the files loaded for the `includes` of `file` and the sets they include in turn, built-in sets are left out
+/// Files that cannot be read are listed without looking for further includes in them.
+pub fn files(includes: &[String], file: &str) -> Vec<String> {
+    let mut files = Vec::new();
+    collect_files(includes, file, &mut files);
+    files
+}
+
+/// Add the paths of the `includes` of `file` and of the files they include to `files`, each path once
+fn collect_files(includes: &[String], file: &str, files: &mut Vec<String>) {
+    for name in includes {
+        if BUILTIN.iter().any(|(builtin, _)| builtin == name) {
+            continue;
+        }
+        let path = path_of(name, file);
+        if files.contains(&path) {
+            continue;
+        }
+        files.push(path.clone());
+        let set = std::fs::read_to_string(&path).ok().and_then(|source| {
+            let mut config = Config::new();
+            config.merge(File::from_str(&source, FileFormat::Toml)).and_then(|config| config.clone().try_into::<CommandSet>()).ok()
+        });
+        if let Some(set) = set {
+            collect_files(&set.include, &path, files);
+        }
+    }
+}
+
+/// Path of the include file `name`, which is relative to the file `file` including it
+fn path_of(name: &str, file: &str) -> String {
+    Path::new(file).parent().unwrap_or_else(|| Path::new("")).join(name).to_string_lossy().into_owned()
+}
+
+/// Add the commands of `base` that are not replaced by one of the same name to `commands`
+/// The commands of a file come first, they are usually the more specific ones.
+fn extend(mut commands: Vec<Command>, base: Vec<Command>) -> Vec<Command> {
+    let replaced: Vec<String> = commands.iter().map(|command| command.name.clone()).collect();
+    commands.extend(base.into_iter().filter(|command| !replaced.contains(&command.name)));
+    commands
+}
+
+/// Put `commands` on top of `base`: a command replaces the one of the same name, others are appended
+fn merge(mut base: Vec<Command>, commands: Vec<Command>) -> Vec<Command> {
+    for command in commands {
+        match base.iter_mut().find(|other| other.name == command.name) {
+            Some(other) => *other = command,
+            None => base.push(command),
+        }
+    }
+    base
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `files` to a new directory and return its path
+    fn directory(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("instrument-app-include-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for (file, source) in files {
+            std::fs::write(directory.join(file), source).unwrap();
+        }
+        directory
+    }
+
+    fn names(commands: &[Command]) -> Vec<&str> {
+        commands.iter().map(|command| command.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_builtin_and_override() {
+        let own = vec![
+            Command { name: "Reset".into(), scpi: ":SYSTem:PRESet".into(), values: vec!["".into()], ..Default::default() },
+            Command { name: "Output".into(), scpi: ":OUTPut ".into(), values: vec!["ON".into()], ..Default::default() },
+        ];
+        let commands = resolve(&["ieee488.2-common".into()], own, "config.toml", Some(3)).unwrap();
+        assert_eq!(&names(&commands)[..4], &["Reset", "Output", "Identify", "Clear Status"]);
+        assert_eq!(commands[0].scpi, ":SYSTem:PRESet");
+        assert_eq!(names(&commands).iter().filter(|name| **name == "Reset").count(), 1);
+        assert!(commands.iter().any(|command| command.scpi == ":SYSTem:ERRor"));
+    }
+
+    #[test]
+    fn test_files_and_cycles() {
+        let directory = directory("files", &[
+            ("rigol.toml", "include = [\"ieee488.2-common\"]\n[[commands]]\nname = \"Identify\"\nscpi = \"*IDN?\"\nvalues = [\"\"]\nquery = true\n"),
+            ("a.toml", "include = [\"b.toml\"]\n"),
+            ("b.toml", "include = [\"a.toml\"]\n"),
+            ("bad.toml", "[[commands]]\nname = \"Bad\"\nscpi = \"\"\nvalues = [\"\"]\n"),
+        ]);
+        let file = directory.join("config.toml").to_string_lossy().into_owned();
+
+        let commands = resolve(&["rigol.toml".into()], Vec::new(), &file, Some(2)).unwrap();
+        assert_eq!(names(&commands)[0], "Identify");
+        assert!(commands[0].query);
+
+        let errors = resolve(&["a.toml".into()], Vec::new(), &file, Some(2)).unwrap_err();
+        assert!(errors[0].message.starts_with("Include \"a.toml\" is circular"), "{}", errors[0]);
+        assert_eq!(errors[0].line, Some(1));
+
+        let included = files(&["ieee488.2-common".into(), "a.toml".into(), "missing.toml".into()], &file);
+        let expected: Vec<String> = ["a.toml", "b.toml", "missing.toml"].iter()
+            .map(|name| directory.join(name).to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(included, expected);
+
+        let errors = resolve(&["missing.toml".into(), "bad.toml".into()], Vec::new(), &file, Some(2)).unwrap_err();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].message.starts_with("Include \"missing.toml\" could not be read"), "{}", errors[0]);
+        assert_eq!((errors[0].file.as_str(), errors[0].line), (file.as_str(), Some(2)));
+        assert_eq!(errors[1].line, Some(3));
+        assert_eq!(errors[1].message, "Command \"Bad\" has no `scpi` command");
+    }
+}
@@ -0,0 +1,350 @@
+//! Provides access to a device configuration file via Rust structs
+pub mod command;
+pub mod completion;
+pub mod include;
+pub mod number;
+pub mod parameter;
+pub mod sequence;
+pub mod validation;
+pub mod writer;
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+use config::{Config, ConfigError, File};
+use validation::Diagnostic;
+use crate::networking::ConnectionSettings;
+use parameter::Parameter;
+use sequence::Sequence;
+
+/// Contains one [`Device`] and all [`Command`]s available for it
+#[derive(Debug, Deserialize, Clone)]
+pub struct Configuration {
+    pub device: Device,
+    #[serde(default)]
+    pub commands: Vec<Command>,
+    /// Sequences of commands defined in `[[sequences]]` tables
+    #[serde(default)]
+    pub sequences: Vec<Sequence>,
+    /// Path of the config file the configuration was loaded from
+    #[serde(skip)]
+    pub file: String,
+    /// Index of the `[[devices]]` entry in `file`, [`None`] for a file with a single `[device]`
+    #[serde(skip)]
+    pub entry: Option<usize>,
+}
+
+/// A config file with several `[[devices]]` entries
+#[derive(Debug, Deserialize)]
+struct DeviceList {
+    devices: Vec<DeviceEntry>,
+}
+
+/// One entry of a [`DeviceList`], holding the device properties and commands in the same table
+#[derive(Debug, Deserialize)]
+struct DeviceEntry {
+    #[serde(flatten)]
+    device: Device,
+    #[serde(default)]
+    commands: Vec<Command>,
+    #[serde(default)]
+    sequences: Vec<Sequence>,
+}
+
+/// Device properties
+#[derive(Debug, Deserialize, Clone)]
+pub struct Device {
+    /// Name of the profile shown in the device picker, defaults to the name of the config file
+    #[serde(default)]
+    pub name: String,
+    /// Address in format IP:PORT, or the name of the serial port, used to pre-fill the settings screen
+    #[serde(default)]
+    pub address: String,
+    /// Command sets the commands of this device extend, e.g. the built-in `ieee488.2-common` or a file
+    /// relative to the config file. Commands of the device replace included ones of the same name.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Patterns for the reply to `*IDN?` like `RIGOL*,DG10*` that identify this device, `*` matches any text
+    /// A connected device matching them is handed over to this profile.
+    #[serde(default)]
+    pub idn: Vec<String>,
+    /// Connect to `address` when the application starts?
+    #[serde(default)]
+    pub autoconnect: bool,
+    /// Number of channels available on the device
+    pub channels: u8,
+    /// Transport, terminator and serial framing used to talk to the device
+    #[serde(flatten)]
+    pub connection: ConnectionSettings,
+    /// Drain the error queue after every command?
+    #[serde(default)]
+    pub check_errors: bool,
+    /// Query that returns the oldest entry of the error queue
+    #[serde(default = "default_error_query")]
+    pub error_query: String,
+}
+
+/// The SCPI standard query for the error queue
+fn default_error_query() -> String {
+    ":SYSTem:ERRor?".into()
+}
+
+/// An available SCPI command
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct Command {
+    /// Per-channel command (i.e. not global)?
+    #[serde(default)]
+    pub channel: bool,
+    /// Name of the command that should be displayed
+    #[serde(default)]
+    pub name: String,
+    /// SCPI top-level command like "OUTPut"
+    #[serde(default)]
+    pub scpi: String,
+    /// Values that may be appended to `scpi`
+    #[serde(default)]
+    pub values: Vec<String>,
+    /// Always read a reply, even if the command header contains no `?`
+    #[serde(default)]
+    pub query: bool,
+    /// Number of lines the reply consists of, defaults to one
+    #[serde(default)]
+    pub lines: Option<usize>,
+    /// Unit of numeric replies, defaults to the unit of the `<TXT>` placeholder
+    #[serde(default)]
+    pub unit: Option<String>,
+    /// Type and limits of the values replacing placeholders like `<FREQ>`, keyed by the placeholder name
+    /// Anything typed is sent as is for placeholders without a declared parameter.
+    #[serde(default)]
+    pub parameters: HashMap<String, Parameter>,
+}
+
+impl Command {
+    /// Unit numbers in replies to this command are shown in, if any
+    pub fn reply_unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+            .or_else(|| self.parameters.get("TXT").and_then(|parameter| parameter.unit.as_deref()))
+    }
+}
+
+impl std::fmt::Display for Command {
+    /// Make the [`Command`] displayable by returning its `name` field
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+
+impl Configuration {
+    /// Construct new settings object from a devices file at path `configfile`.
+    /// Syntax errors and mistakes found by [`validation::validate`] are returned with the line they were found in.
+    pub fn from(configfile: &str) -> Result<Self, Vec<Diagnostic>> {
+        let mut configuration = Self::parse(configfile)
+            .map_err(|e| vec![Diagnostic::new(configfile, None, e.to_string())])?;
+        let source = read_source(configfile);
+        let diagnostics = validation::validate(&configuration, configfile, &source, None);
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        configuration.resolve_includes(configfile, &source, None)?;
+        let diagnostics = validation::validate_sequences(&configuration, configfile, &source, None);
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        configuration.file = loaded_path(configfile);
+        if configuration.device.name.is_empty() {
+            configuration.device.name = file_stem(configfile);
+        }
+        Ok(configuration)
+    }
+
+    /// Load all device profiles from `path`, which is either a config file with a single `[device]`,
+    /// a config file with several `[[devices]]` entries or a directory of such files.
+    pub fn load_all(path: &str) -> Result<Vec<Self>, Vec<Diagnostic>> {
+        let files = if Path::new(path).is_dir() {
+            let entries = std::fs::read_dir(path)
+                .map_err(|e| vec![Diagnostic::new(path, None, e.to_string())])?;
+            let mut files: Vec<String> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|file| file.extension().is_some_and(|extension| extension == "toml"))
+                .map(|file| file.to_string_lossy().into_owned())
+                .collect();
+            files.sort();
+            files
+        } else {
+            vec![path.to_string()]
+        };
+
+        let mut profiles: Vec<Self> = Vec::new();
+        let mut diagnostics = Vec::new();
+        for file in &files {
+            match Self::load_file(file) {
+                Ok(loaded) => profiles.extend(loaded),
+                Err(errors) => diagnostics.extend(errors),
+            }
+        }
+        if files.is_empty() {
+            diagnostics.push(Diagnostic::new(path, None, "No config files (*.toml) found".into()));
+        }
+        // Profiles are told apart by their name
+        for (index, profile) in profiles.iter().enumerate() {
+            if profiles[..index].iter().any(|other| other.device.name == profile.device.name) {
+                diagnostics.push(Diagnostic::new(path, None, format!("Device \"{}\" is defined twice", profile.device.name)));
+            }
+        }
+        if diagnostics.is_empty() { Ok(profiles) } else { Err(diagnostics) }
+    }
+
+    /// Load the profiles of a config file with either a single `[device]` or several `[[devices]]`
+    fn load_file(configfile: &str) -> Result<Vec<Self>, Vec<Diagnostic>> {
+        let to_diagnostics = |e: ConfigError| vec![Diagnostic::new(configfile, None, e.to_string())];
+        let mut config = Config::new();
+        config.merge(File::with_name(configfile)).map_err(to_diagnostics)?;
+        if config.get_array("devices").is_err() {
+            return Self::from(configfile).map(|configuration| vec![configuration]);
+        }
+
+        let list: DeviceList = config.try_into().map_err(to_diagnostics)?;
+        let source = read_source(configfile);
+        let mut profiles = Vec::new();
+        let mut diagnostics = Vec::new();
+        for (index, entry) in list.devices.into_iter().enumerate() {
+            let mut configuration = Self {
+                device: entry.device,
+                commands: entry.commands,
+                sequences: entry.sequences,
+                file: loaded_path(configfile),
+                entry: Some(index),
+            };
+            let errors = validation::validate(&configuration, configfile, &source, Some(index));
+            if errors.is_empty() {
+                // Sequences may use included commands, so they are checked once those are there
+                match configuration.resolve_includes(configfile, &source, Some(index)) {
+                    Ok(()) => diagnostics.extend(validation::validate_sequences(&configuration, configfile, &source, Some(index))),
+                    Err(errors) => diagnostics.extend(errors),
+                }
+            }
+            diagnostics.extend(errors);
+            if configuration.device.name.is_empty() {
+                configuration.device.name = format!("{} #{}", file_stem(configfile), index + 1);
+            }
+            profiles.push(configuration);
+        }
+        if diagnostics.is_empty() { Ok(profiles) } else { Err(diagnostics) }
+    }
+
+    /// Paths of the files the commands of the config are included from, see [`include::files`]
+    pub fn include_files(&self) -> Vec<String> {
+        include::files(&self.device.include, &self.file)
+    }
+
+    /// Return the command named `name`, compared case-insensitively
+    pub fn command(&self, name: &str) -> Option<&Command> {
+        self.commands.iter().find(|cmd| cmd.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Load the commands written in the config file, without the ones it includes
+    /// `entry` is the index of the `[[devices]]` entry, [`None`] for a file with a single `[device]`.
+    pub fn load_commands(configfile: &str, entry: Option<usize>) -> Result<Vec<Command>, String> {
+        match entry {
+            None => Self::parse(configfile).map(|configuration| configuration.commands).map_err(|e| e.to_string()),
+            Some(index) => {
+                let mut config = Config::new();
+                config.merge(File::with_name(configfile)).map_err(|e| e.to_string())?;
+                let list: DeviceList = config.try_into().map_err(|e| e.to_string())?;
+                list.devices.into_iter()
+                    .nth(index)
+                    .map(|entry| entry.commands)
+                    .ok_or_else(|| format!("Device #{} not found in {}", index + 1, configfile))
+            }
+        }
+    }
+
+    /// Put the commands of the config on top of the command sets it includes
+    /// `entry` is the index of the `[[devices]]` entry, like for [`validation::validate`].
+    fn resolve_includes(&mut self, configfile: &str, source: &str, entry: Option<usize>) -> Result<(), Vec<Diagnostic>> {
+        if self.device.include.is_empty() {
+            return Ok(());
+        }
+        let line = validation::device_line(source, entry, "include");
+        let commands = std::mem::take(&mut self.commands);
+        self.commands = include::resolve(&self.device.include, commands, configfile, line)?;
+        Ok(())
+    }
+
+    /// Read and deserialize the file at `configfile` without checking its contents
+    fn parse(configfile: &str) -> Result<Self, ConfigError> {
+        let mut config = Config::new();
+        // Start off by merging in the "default" configuration file
+        config.merge(File::with_name(configfile))?;
+        // You can deserialize (and thus freeze) the entire configuration as
+        config.try_into()
+    }
+}
+
+/// Read the text of `configfile` to find line numbers in, the extension may be omitted just like for the config crate
+fn read_source(configfile: &str) -> String {
+    std::fs::read_to_string(loaded_path(configfile)).unwrap_or_default()
+}
+
+/// Path of the file loaded for `configfile`, which is `configfile` with `.toml` appended if it has been left out
+fn loaded_path(configfile: &str) -> String {
+    let with_extension = format!("{}.toml", configfile);
+    if !Path::new(configfile).is_file() && Path::new(&with_extension).is_file() {
+        with_extension
+    } else {
+        configfile.to_string()
+    }
+}
+
+/// Name of `configfile` without directory and extension
+fn file_stem(configfile: &str) -> String {
+    Path::new(configfile).file_stem().map_or_else(|| configfile.into(), |stem| stem.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let config = Configuration::from("../config/config.toml").unwrap();
+        assert_eq!(config.device.address, "10.10.2.8:5555");
+        assert_eq!(config.device.channels, 2);
+        assert_eq!(config.device.connection, ConnectionSettings::default());
+        assert_eq!(config.device.error_query, ":SYSTem:ERRor?");
+        assert!(!config.commands.is_empty());
+        let voltage = config.command("voltage").unwrap();
+        assert_eq!(voltage.parameters["TXT"].kind, parameter::ParameterType::Float);
+        assert_eq!(config.device.name, "DG1022Z");
+        // Commands of the built-in command set are included
+        assert!(config.commands.iter().any(|cmd| cmd.scpi == "*IDN?"));
+        // The file actually loaded is kept if the extension has been left out
+        assert_eq!(Configuration::from("../config/config").unwrap().file, "../config/config.toml");
+    }
+
+    #[test]
+    fn test_load_all() {
+        // A single device file and a file with several devices
+        let profiles = Configuration::load_all("../config/config.toml").unwrap();
+        assert_eq!(profiles.len(), 1);
+        let profiles = Configuration::load_all("../config/bench.toml").unwrap();
+        let names: Vec<&str> = profiles.iter().map(|profile| profile.device.name.as_str()).collect();
+        assert_eq!(names, vec!["Generator", "Multimeter"]);
+        assert_eq!(profiles[1].device.channels, 1);
+        assert!(profiles.iter().all(|profile| !profile.commands.is_empty()));
+        // Included commands are not part of the commands written in the file
+        assert_eq!(profiles[1].entry, Some(1));
+        let commands = Configuration::load_commands(&profiles[1].file, profiles[1].entry).unwrap();
+        assert_eq!(commands.iter().map(|cmd| cmd.name.as_str()).collect::<Vec<_>>(), vec!["Measure"]);
+
+        // A directory of profiles, other files are ignored
+        let directory = std::env::temp_dir().join(format!("instrument-app-profiles-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::copy("../config/config.toml", directory.join("config.toml")).unwrap();
+        std::fs::copy("../config/bench.toml", directory.join("bench.toml")).unwrap();
+        std::fs::write(directory.join("notes.txt"), "not a profile").unwrap();
+        let profiles = Configuration::load_all(directory.to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let names: Vec<&str> = profiles.iter().map(|profile| profile.device.name.as_str()).collect();
+        assert_eq!(names, vec!["Generator", "Multimeter", "DG1022Z"]);
+    }
+}
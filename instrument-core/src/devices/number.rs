@@ -0,0 +1,163 @@
+//! Parsing and formatting of numbers in engineering notation, e.g. `10k`, `2.5 MHz` or `300mVpp`
+
+/// SI prefixes accepted after a number and their decimal exponent
+/// Unlike in SCPI suffixes, `m` is milli and `M` is mega, as engineers write them.
+const PREFIXES: [(&str, i32); 10] = [
+    ("p", -12), ("n", -9), ("u", -6), ("µ", -6), ("m", -3),
+    ("k", 3), ("K", 3), ("M", 6), ("G", 9), ("T", 12),
+];
+
+/// Whether numbers in `unit` are shown with an SI prefix, logarithmic and angular units are not
+fn takes_prefix(unit: &str) -> bool {
+    !(unit.starts_with("dB") || unit == "deg" || unit == "°" || unit == "%")
+}
+
+/// Round off the error of scaling by a power of ten, e.g. `0.30000000000000004` to `0.3`
+fn round_significant(value: f64) -> f64 {
+    format!("{:.14e}", value).parse().unwrap_or(value)
+}
+
+/// Parse a number with an optional SI prefix and `unit`, e.g. `2.5 MHz` for unit `Hz`
+/// The unit may be omitted and is compared case-insensitively if the exact spelling does not match. The prefix is then
+/// compared case-insensitively as well, except for `m` and `M`, which are rejected as it is unclear which one is meant.
+pub fn parse_number(input: &str, unit: Option<&str>) -> Result<f64, String> {
+    let input = input.trim();
+    // The number ends where the prefix or unit starts, an exponent like "1e3" belongs to the number
+    let bytes = input.as_bytes();
+    let mut end = 0;
+    while end < bytes.len() {
+        let c = bytes[end];
+        let sign = (c == b'-' || c == b'+') && (end == 0 || matches!(bytes[end - 1], b'e' | b'E'));
+        let exponent = matches!(c, b'e' | b'E') && end > 0
+            && bytes.get(end + 1).is_some_and(|n| n.is_ascii_digit() || *n == b'-' || *n == b'+');
+        if !(c.is_ascii_digit() || c == b'.' || sign || exponent) {
+            break;
+        }
+        end += 1;
+    }
+    let (number, suffix) = input.split_at(end);
+    let value: f64 = number.parse()
+        .ok()
+        .filter(|value: &f64| value.is_finite())
+        .ok_or_else(|| format!("\"{}\" is not a number", input))?;
+
+    // Whatever is left of the suffix after removing the unit has to be a prefix
+    let suffix = suffix.trim();
+    let (prefix, exact) = match unit.filter(|unit| !unit.is_empty()) {
+        Some(unit) if suffix == unit || suffix.eq_ignore_ascii_case(unit) => ("", true),
+        Some(unit) => match suffix.strip_suffix(unit) {
+            Some(prefix) => (prefix.trim(), true),
+            None => match suffix.len().checked_sub(unit.len())
+                .filter(|&split| suffix.is_char_boundary(split) && suffix[split..].eq_ignore_ascii_case(unit)) {
+                Some(split) => (suffix[..split].trim(), false),
+                None => (suffix, true),
+            },
+        },
+        None => (suffix, true),
+    };
+    if prefix.is_empty() {
+        return Ok(value);
+    }
+    if !exact && prefix.eq_ignore_ascii_case("m") {
+        return Err(format!("\"{}\" is ambiguous, write m for milli or M for mega with the unit spelled {}", input, unit.unwrap_or_default()));
+    }
+    PREFIXES.iter()
+        .find(|(p, _)| *p == prefix || (!exact && p.eq_ignore_ascii_case(prefix)))
+        .map(|(_, exponent)| round_significant(value * 10f64.powi(*exponent)))
+        .ok_or_else(|| match unit {
+            Some(unit) if !unit.is_empty() => format!("\"{}\" is not a value in {}", input, unit),
+            _ => format!("\"{}\" has an unknown SI prefix", input),
+        })
+}
+
+/// Format `value` as SCPI numeric value, in exponent notation if it is very small or large
+pub fn format_scpi(value: f64) -> String {
+    let magnitude = value.abs();
+    if magnitude != 0.0 && !(1e-4..1e10).contains(&magnitude) {
+        format!("{:E}", value)
+    } else {
+        format!("{}", value)
+    }
+}
+
+/// Format `value` in engineering notation with an SI prefix for `unit`, e.g. `10 kHz`
+pub fn format_engineering(value: f64, unit: &str) -> String {
+    let mut exponent = 0;
+    if takes_prefix(unit) && value != 0.0 {
+        // Largest prefix that keeps at least one digit before the decimal point
+        exponent = ((value.abs().log10() / 3.0).floor() as i32 * 3).clamp(-12, 12);
+    }
+    let mut text = loop {
+        let mantissa = round_significant(value / 10f64.powi(exponent));
+        // Six significant digits are plenty for a display, drop trailing zeros
+        let integer_digits = if mantissa == 0.0 { 0 } else { mantissa.abs().log10().floor().max(0.0) as i32 };
+        let text = format!("{:.*}", (5 - integer_digits).max(0) as usize, mantissa);
+        // Rounding may carry over into the next prefix, e.g. 999.9999 kHz becomes 1000.000 kHz
+        let carried = text.parse::<f64>().is_ok_and(|rounded| rounded.abs() >= 1000.0);
+        if !carried || !takes_prefix(unit) || exponent >= 12 {
+            break text;
+        }
+        exponent += 3;
+    };
+    if text.contains('.') {
+        text = text.trim_end_matches('0').trim_end_matches('.').to_string();
+    }
+    let prefix = PREFIXES.iter().find(|(_, e)| *e == exponent).map_or("", |(p, _)| *p);
+    format!("{} {}{}", text, prefix, unit).trim_end().to_string()
+}
+
+/// Format a numeric reply like `1.000000E+04` in a readable way, e.g. `10 kHz` for unit `Hz`
+/// Only a single number is formatted if the unit is known, anything else like serial numbers, flags or
+/// comma-separated fields is kept as is.
+pub fn format_reply(reply: &str, unit: Option<&str>) -> String {
+    match (reply.trim().parse::<f64>(), unit.filter(|unit| !unit.is_empty())) {
+        (Ok(value), Some(unit)) if value.is_finite() => format_engineering(value, unit),
+        _ => reply.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("10k", None), Ok(10e3));
+        assert_eq!(parse_number("2.5 MHz", Some("Hz")), Ok(2.5e6));
+        assert_eq!(parse_number("300mVpp", Some("Vpp")), Ok(0.3));
+        assert_eq!(parse_number("-3 dBm", Some("dBm")), Ok(-3.0));
+        assert_eq!(parse_number("1e-3", Some("V")), Ok(1e-3));
+        assert_eq!(parse_number("4.7 uv", Some("V")), Ok(4.7e-6));
+        assert_eq!(parse_number("1e3k", None), Ok(1e6));
+        assert_eq!(parse_number("2.5 mHz", Some("Hz")), Ok(2.5e-3));
+        assert!(parse_number("2.5 mhz", Some("Hz")).unwrap_err().contains("ambiguous"));
+        assert!(parse_number("2.5 MHZ", Some("Hz")).unwrap_err().contains("ambiguous"));
+        assert_eq!(parse_number("2.5 KHZ", Some("Hz")), Ok(2.5e3));
+        assert_eq!(parse_number("1 GHZ", Some("Hz")), Ok(1e9));
+        assert_eq!(parse_number("12", Some("Hz")), Ok(12.0));
+        assert_eq!(parse_number("1 V", Some("Hz")), Err("\"1 V\" is not a value in Hz".into()));
+        assert_eq!(parse_number("5x", None), Err("\"5x\" has an unknown SI prefix".into()));
+        assert!(parse_number("abc", None).is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_scpi(10e3), "10000");
+        assert_eq!(format_scpi(1e-6), "1E-6");
+        assert_eq!(format_engineering(1e4, "Hz"), "10 kHz");
+        assert_eq!(format_engineering(0.3, "Vpp"), "300 mVpp");
+        assert_eq!(format_engineering(999999.9, "Hz"), "1 MHz");
+        assert_eq!(format_engineering(0.9999999, "V"), "1 V");
+        assert_eq!(format_engineering(-999.9999e-6, "A"), "-1 mA");
+        assert_eq!(format_engineering(999.9999, "V"), "1 kV");
+        assert_eq!(format_engineering(999.9999, "dBm"), "1000 dBm");
+        assert_eq!(format_engineering(-3.0, "dBm"), "-3 dBm");
+        assert_eq!(format_engineering(1234.5678, "Hz"), "1.23457 kHz");
+        assert_eq!(format_engineering(0.0, "V"), "0 V");
+        assert_eq!(format_reply("1.000000E+04", Some("Hz")), "10 kHz");
+        assert_eq!(format_reply("SIN,1.000000E+03,5.000000E+00", Some("Hz")), "SIN,1.000000E+03,5.000000E+00");
+        assert_eq!(format_reply("ON", Some("Hz")), "ON");
+        assert_eq!(format_reply("0001234", None), "0001234");
+        assert_eq!(format_reply("+1.000000E+00", None), "+1.000000E+00");
+    }
+}
@@ -0,0 +1,178 @@
+//! Typed parameters of a [`Command`](super::Command) that are validated before anything is sent to the device
+use super::number;
+use serde::Deserialize;
+
+/// Type of the value a [`Parameter`] accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParameterType {
+    /// Any number, e.g. a frequency
+    Float,
+    /// A whole number, e.g. a number of cycles
+    Int,
+    /// `ON` or `OFF`, also entered as `1`/`0` or `true`/`false`
+    Bool,
+    /// One of the `choices`
+    Enum,
+    /// Any text, sent as quoted SCPI string
+    #[default]
+    String,
+}
+
+impl std::fmt::Display for ParameterType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParameterType::Float => write!(f, "number"),
+            ParameterType::Int => write!(f, "whole number"),
+            ParameterType::Bool => write!(f, "ON/OFF"),
+            ParameterType::Enum => write!(f, "choice"),
+            ParameterType::String => write!(f, "text"),
+        }
+    }
+}
+
+/// Declaration of the value that replaces a placeholder like `<FREQ>` of a command
+#[derive(Debug, Deserialize, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct Parameter {
+    /// Type of the accepted values
+    #[serde(rename = "type")]
+    pub kind: ParameterType,
+    /// Smallest accepted number
+    pub min: Option<f64>,
+    /// Largest accepted number
+    pub max: Option<f64>,
+    /// Value the input is pre-filled with
+    pub default: Option<String>,
+    /// Unit of numbers, may be typed after the number with an SI prefix but is not sent to the device
+    pub unit: Option<String>,
+    /// Numbers have to be a multiple of `step`, counted from `min`
+    pub step: Option<f64>,
+    /// Accepted values of an enum, compared case-insensitively
+    pub choices: Vec<String>,
+}
+
+// Validation rejects limits that are not finite, so comparing them is reflexive
+impl Eq for Parameter {}
+
+impl Parameter {
+    /// Check `input` against the type and limits and return the value as it is sent to the device
+    /// `name` is the name of the command, used in error messages.
+    pub fn validate(&self, name: &str, input: &str) -> Result<String, String> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(format!("{} requires a value", name));
+        }
+        match self.kind {
+            ParameterType::Float => {
+                let value = self.parse_number(name, input)?;
+                self.check_limits(name, value)?;
+                Ok(number::format_scpi(value))
+            }
+            ParameterType::Int => {
+                let value = self.parse_number(name, input)?;
+                if value.fract() != 0.0 {
+                    return Err(format!("{} must be a {}, got {}", name, self.kind, value));
+                }
+                self.check_limits(name, value)?;
+                Ok(format!("{}", value as i64))
+            }
+            ParameterType::Bool => match input.to_ascii_lowercase().as_str() {
+                "on" | "1" | "true" => Ok("ON".into()),
+                "off" | "0" | "false" => Ok("OFF".into()),
+                _ => Err(format!("{} must be ON or OFF, got \"{}\"", name, input)),
+            },
+            ParameterType::Enum => self.choices.iter()
+                .find(|choice| choice.eq_ignore_ascii_case(input))
+                .cloned()
+                .ok_or_else(|| format!("{} must be one of {}, got \"{}\"", name, self.choices.join(", "), input)),
+            ParameterType::String => Ok(format!("\"{}\"", input.replace('"', "\"\""))),
+        }
+    }
+
+    /// Short description of the accepted values, e.g. `0 to 20 Vpp, step 0.1`
+    pub fn hint(&self) -> String {
+        match self.kind {
+            ParameterType::Float | ParameterType::Int => {
+                let unit = self.unit.as_deref().unwrap_or_default();
+                let mut hint = match (self.min, self.max) {
+                    (Some(min), Some(max)) => format!("{} to {} {}", min, max, unit),
+                    (Some(min), None) => format!("at least {} {}", min, unit),
+                    (None, Some(max)) => format!("at most {} {}", max, unit),
+                    (None, None) => format!("{} {}", self.kind, unit),
+                }.trim_end().to_string();
+                if let Some(step) = self.step {
+                    hint.push_str(&format!(", step {}", step));
+                }
+                hint
+            }
+            ParameterType::Enum => self.choices.join(", "),
+            ParameterType::Bool | ParameterType::String => self.kind.to_string(),
+        }
+    }
+
+    /// Parse a number in engineering notation, optionally followed by the unit of the parameter
+    fn parse_number(&self, name: &str, input: &str) -> Result<f64, String> {
+        number::parse_number(input, self.unit.as_deref()).map_err(|e| format!("{}: {}", name, e))
+    }
+
+    /// Check that `value` is within `min` and `max` and a multiple of `step`
+    fn check_limits(&self, name: &str, value: f64) -> Result<(), String> {
+        let out_of_range = self.min.is_some_and(|min| value < min) || self.max.is_some_and(|max| value > max);
+        if out_of_range {
+            return Err(format!("{} must be {}, got {}", name, self.hint(), value));
+        }
+        if let Some(step) = self.step {
+            let steps = (value - self.min.unwrap_or(0.0)) / step;
+            if (steps - steps.round()).abs() > 1e-9 * steps.abs().max(1.0) {
+                return Err(format!("{} must be a multiple of {}, got {}", name, step, value));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers() {
+        let voltage = Parameter {
+            kind: ParameterType::Float,
+            min: Some(0.0),
+            max: Some(20.0),
+            unit: Some("Vpp".into()),
+            step: Some(0.1),
+            ..Default::default()
+        };
+        assert_eq!(voltage.validate("Voltage", " 2.5"), Ok("2.5".into()));
+        assert_eq!(voltage.validate("Voltage", "2.5 Vpp"), Ok("2.5".into()));
+        assert_eq!(voltage.validate("Voltage", "300mVpp"), Ok("0.3".into()));
+        assert_eq!(voltage.validate("Voltage", "500"), Err("Voltage must be 0 to 20 Vpp, step 0.1, got 500".into()));
+        assert_eq!(voltage.validate("Voltage", "2.55"), Err("Voltage must be a multiple of 0.1, got 2.55".into()));
+        assert_eq!(voltage.validate("Voltage", "abc"), Err("Voltage: \"abc\" is not a number".into()));
+        assert_eq!(voltage.validate("Voltage", ""), Err("Voltage requires a value".into()));
+
+        let cycles = Parameter { kind: ParameterType::Int, min: Some(1.0), ..Default::default() };
+        assert_eq!(cycles.validate("Cycles", "10"), Ok("10".into()));
+        assert_eq!(cycles.validate("Cycles", "2k"), Ok("2000".into()));
+        assert!(cycles.validate("Cycles", "1.5").is_err());
+        assert_eq!(cycles.validate("Cycles", "0"), Err("Cycles must be at least 1, got 0".into()));
+    }
+
+    #[test]
+    fn test_bool_enum_and_string() {
+        let state = Parameter { kind: ParameterType::Bool, ..Default::default() };
+        assert_eq!(state.validate("Beeper", "1"), Ok("ON".into()));
+        assert_eq!(state.validate("Beeper", "off"), Ok("OFF".into()));
+        assert!(state.validate("Beeper", "maybe").is_err());
+
+        let shape = Parameter { kind: ParameterType::Enum, choices: vec!["SINusoid".into(), "SQUare".into()], ..Default::default() };
+        assert_eq!(shape.validate("Shape", "square"), Ok("SQUare".into()));
+        assert_eq!(shape.validate("Shape", "RAMP"), Err("Shape must be one of SINusoid, SQUare, got \"RAMP\"".into()));
+
+        let text = Parameter::default();
+        assert_eq!(text.validate("Display", "say \"hi\""), Ok("\"say \"\"hi\"\"\"".into()));
+    }
+}
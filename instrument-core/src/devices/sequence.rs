@@ -0,0 +1,162 @@
+//! Sequences of commands defined in the config, e.g. the steps bringing up a test signal
+use super::{command::build_request, Configuration};
+use crate::networking::{self, DeviceConnection, Reply, Request, SharedConnection};
+use serde::Deserialize;
+use std::{collections::HashMap, io::ErrorKind, time::{Duration, Instant}};
+
+/// Longest time to wait for the device to answer `*OPC?` after a step
+pub const OPC_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// An ordered list of steps run one after the other, defined by a `[[sequences]]` table
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct Sequence {
+    /// Name of the sequence that should be displayed
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    /// Stop at the first step that fails or makes the device report an error?
+    #[serde(default)]
+    pub stop_on_error: bool,
+}
+
+/// A step of a [`Sequence`]: a command of the config, waiting for the device to complete it and a pause,
+/// each of them optional
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// Name of the command to send
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Picks one of the values of the command like on the command line, e.g. `on`, `1000` or `:HIGH 2.5`
+    #[serde(default)]
+    pub argument: Option<String>,
+    /// Channel of a per-channel command, defaults to 1
+    #[serde(default)]
+    pub channel: Option<u8>,
+    /// Values of placeholders like `<FREQ>` that are not part of `argument`, keyed by the placeholder name
+    #[serde(default)]
+    pub inputs: HashMap<String, String>,
+    /// Wait until the device has completed the command by querying `*OPC?`?
+    #[serde(default)]
+    pub wait: bool,
+    /// Pause after the step, in milliseconds
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+/// A [`Step`] with its command built from the config, ready to be run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreparedStep {
+    pub request: Option<Request>,
+    pub wait: bool,
+    pub delay: Duration,
+}
+
+impl Step {
+    /// Build the command of the step from `config`, the error queue is read after it with `error_query` if given
+    pub fn prepare(&self, config: &Configuration, error_query: Option<&str>) -> Result<PreparedStep, String> {
+        let request = match &self.command {
+            Some(name) => {
+                let mut request = build_request(config, name, self.argument.as_deref(), self.channel.unwrap_or(1), &self.inputs, false)?;
+                request.error_query = error_query.map(String::from);
+                Some(request)
+            }
+            None => None,
+        };
+        Ok(PreparedStep { request, wait: self.wait, delay: Duration::from_millis(self.delay_ms) })
+    }
+}
+
+impl std::fmt::Display for Step {
+    /// Describe the step like `Output on, channel 2, wait for completion, pause 500 ms`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(command) = &self.command {
+            let mut inputs: Vec<String> = self.inputs.iter().map(|(name, input)| format!("{}={}", name, input)).collect();
+            inputs.sort();
+            parts.push(std::iter::once(command.clone()).chain(self.argument.clone()).chain(inputs).collect::<Vec<String>>().join(" "));
+        }
+        if let Some(channel) = self.channel {
+            parts.push(format!("channel {}", channel));
+        }
+        if self.wait {
+            parts.push("wait for completion".into());
+        }
+        if self.delay_ms > 0 {
+            parts.push(format!("pause {} ms", self.delay_ms));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+impl PreparedStep {
+    /// Send the command of the step and wait for the device to complete it, the pause is left to the caller
+    /// The error queue is read once the device has completed the command, so errors of the command are not missed.
+    pub fn execute(&self, connection: &mut DeviceConnection) -> Result<Reply, String> {
+        let (mut reply, error_query) = match &self.request {
+            Some(request) => {
+                let command = Request { error_query: None, ..request.clone() };
+                (connection.execute(&command)?, request.error_query.as_deref())
+            }
+            None => (Reply { sent: 0, response: None, errors: Vec::new() }, None),
+        };
+        if self.wait {
+            reply.sent += wait_for_completion(connection)?;
+        }
+        if let Some(query) = error_query {
+            reply.errors = connection.read_errors(query)?;
+        }
+        Ok(reply)
+    }
+}
+
+/// Background task: run `step` on the device of the [`SharedConnection`] and pause as requested
+/// The connection is released during the pause, so other commands are not held up by it.
+pub async fn run(connection: SharedConnection, step: PreparedStep) -> Result<Reply, String> {
+    let reply = step.execute(&mut *networking::lock(&connection)?)?;
+    std::thread::sleep(step.delay);
+    Ok(reply)
+}
+
+/// Query `*OPC?` and wait up to [`OPC_TIMEOUT`] for the answer, return the number of bytes sent
+fn wait_for_completion(connection: &mut DeviceConnection) -> Result<usize, String> {
+    let sent = connection.send(b"*OPC?\n").map_err(|e| e.to_string())?;
+    let start = Instant::now();
+    loop {
+        match connection.read_line() {
+            Ok(_) => return Ok(sent),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) && start.elapsed() < OPC_TIMEOUT => continue,
+            Err(e) => return Err(format!("*OPC? has not been answered: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::{ConnectionSettings, Response};
+
+    #[test]
+    fn test_run_sequence() {
+        let config = Configuration::from("../config/config.toml").unwrap();
+        let sequence = &config.sequences[0];
+        assert_eq!(sequence.name, "Test signal");
+        assert_eq!(sequence.steps[0].to_string(), "Impedance 50, channel 1");
+        assert_eq!(sequence.steps[1].to_string(), "Apply Sine 1k,2,0,0, wait for completion");
+        assert_eq!(sequence.steps[3].to_string(), "Output on, pause 100 ms");
+
+        let address = crate::simulator::spawn(&config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut connection = DeviceConnection::default();
+        connection.connect(&ConnectionSettings::default(), &address.to_string()).unwrap();
+        for step in &sequence.steps {
+            let reply = step.prepare(&config, Some(&config.device.error_query)).unwrap().execute(&mut connection).unwrap();
+            assert!(reply.errors.is_empty(), "{}: {:?}", step, reply.errors);
+        }
+        let request = Request { command: ":SOURce1:VOLTage?\n".into(), reply_lines: 1, error_query: None };
+        assert_eq!(connection.execute(&request).unwrap().response, Some(Response::Text("2".into())));
+
+        // A step with an argument the command does not take
+        let step = Step { command: Some("Output".into()), argument: Some("maybe".into()), ..Default::default() };
+        assert!(step.prepare(&config, None).is_err());
+    }
+}
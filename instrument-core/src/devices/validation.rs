@@ -0,0 +1,504 @@
+//! Checks a [`Configuration`] for mistakes and reports them with the place in the config file they were found at
+use super::{command, parameter::ParameterType, Command, Configuration};
+
+/// Highest number of channels a device may declare
+pub const MAX_CHANNELS: u8 = 32;
+
+/// A problem found in a config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Path of the config file
+    pub file: String,
+    /// Line the problem was found in, starting at 1, [`None`] if it concerns the file as a whole
+    pub line: Option<usize>,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(file: &str, line: Option<usize>, message: String) -> Self {
+        Self { file: file.into(), line, message }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+/// Finds the lines of tables and keys in the text of a TOML config file
+struct SourceMap<'a> {
+    /// Lines of the file, or of the `[[devices]]` entry in a file with several devices
+    lines: Vec<&'a str>,
+    /// Index of the first line in `lines` within the file
+    offset: usize,
+    /// Header of the table holding the device properties
+    device_header: &'static str,
+    /// Path of the command tables, `commands` or `devices.commands`
+    commands: &'static str,
+    /// Path of the sequence tables, `sequences` or `devices.sequences`
+    sequences: &'static str,
+}
+
+impl<'a> SourceMap<'a> {
+    /// Map the lines of `source`, limited to the `[[devices]]` entry number `entry` if given
+    fn new(source: &'a str, entry: Option<usize>) -> Self {
+        let lines: Vec<&str> = source.lines().collect();
+        match entry {
+            None => Self { lines, offset: 0, device_header: "[device]", commands: "commands", sequences: "sequences" },
+            Some(entry) => {
+                let starts: Vec<usize> = lines.iter()
+                    .enumerate()
+                    .filter(|(_, line)| line.trim() == "[[devices]]")
+                    .map(|(number, _)| number)
+                    .collect();
+                let (offset, end) = match starts.get(entry) {
+                    Some(&start) => (start, starts.get(entry + 1).copied().unwrap_or(lines.len())),
+                    None => (0, 0),
+                };
+                Self {
+                    lines: lines[offset..end].to_vec(),
+                    offset,
+                    device_header: "[[devices]]",
+                    commands: "devices.commands",
+                    sequences: "devices.sequences",
+                }
+            }
+        }
+    }
+
+    /// Index of the first line of entry number `index` of the array of tables at `path`, searched from line index `from`
+    fn table_start(&self, path: &str, index: usize, from: usize) -> Option<usize> {
+        let header = format!("[[{}]]", path);
+        self.lines.iter()
+            .enumerate()
+            .skip(from)
+            .filter(|(_, line)| line.trim() == header)
+            .nth(index)
+            .map(|(number, _)| number)
+    }
+
+    /// Index of the first line of the command entry number `index`
+    fn command_start(&self, index: usize) -> Option<usize> {
+        self.table_start(self.commands, index, 0)
+    }
+
+    /// Line of `key` in sequence number `index`, or in its step number `step` if given
+    /// Steps written inline as `steps = [...]` are reported at the line of the sequence.
+    fn sequence_line(&self, index: usize, step: Option<usize>, key: &str) -> Option<usize> {
+        let start = self.table_start(self.sequences, index, 0)?;
+        let end = self.table_start(self.sequences, index + 1, 0).unwrap_or(self.lines.len());
+        let step_start = step
+            .and_then(|step| self.table_start(&format!("{}.steps", self.sequences), step, start))
+            .filter(|&line| line < end);
+        Some(self.key_line(step_start.unwrap_or(start), key))
+    }
+
+    /// Line of `key` in the table starting at line index `start`, or of the table header if the key is missing
+    fn key_line(&self, start: usize, key: &str) -> usize {
+        self.lines[start + 1..].iter()
+            .take_while(|line| !line.trim_start().starts_with('['))
+            .position(|line| {
+                let line = line.trim_start();
+                line.strip_prefix(key).is_some_and(|rest| rest.trim_start().starts_with('='))
+            })
+            .map_or(start, |offset| start + 1 + offset)
+            + self.offset + 1
+    }
+
+    /// Line of `key` in command number `index`
+    fn command_line(&self, index: usize, key: &str) -> Option<usize> {
+        self.command_start(index).map(|start| self.key_line(start, key))
+    }
+
+    /// Line of the parameter table for placeholder `name` in command number `index`
+    fn parameter_line(&self, index: usize, name: &str) -> Option<usize> {
+        let header = format!("[{}.parameters.{}]", self.commands, name);
+        self.command_start(index)
+            .and_then(|start| self.lines[start..].iter()
+                .position(|line| line.trim() == header)
+                .map(|offset| start + offset + self.offset + 1))
+    }
+
+    /// Line of `key` in the table of the device properties
+    fn device_line(&self, key: &str) -> Option<usize> {
+        self.lines.iter()
+            .position(|line| line.trim() == self.device_header)
+            .map(|start| self.key_line(start, key))
+    }
+}
+
+/// Check `config` loaded from `file` for mistakes, `source` is the text of the file used to find line numbers
+/// `entry` is the index of the `[[devices]]` entry `config` was loaded from, [`None`] for a file with a single `[device]`.
+/// Only the commands of the file itself are checked, included ones are checked with the file they come from.
+pub fn validate(config: &Configuration, file: &str, source: &str, entry: Option<usize>) -> Vec<Diagnostic> {
+    let map = SourceMap::new(source, entry);
+    let mut diagnostics = Vec::new();
+    let mut report = |line: Option<usize>, message: String| diagnostics.push(Diagnostic::new(file, line, message));
+
+    if !(1..=MAX_CHANNELS).contains(&config.device.channels) {
+        report(map.device_line("channels"),
+               format!("`channels` must be between 1 and {}, got {}", MAX_CHANNELS, config.device.channels));
+    }
+    if config.commands.is_empty() && config.device.include.is_empty() {
+        report(None, "No commands defined, add at least one [[commands]] entry".into());
+    }
+    check_commands(&config.commands, &map, &mut report);
+    diagnostics
+}
+
+/// Check the commands of an include file like [`validate`] does for a config file
+pub fn validate_commands(commands: &[Command], file: &str, source: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    check_commands(commands, &SourceMap::new(source, None), &mut |line, message| diagnostics.push(Diagnostic::new(file, line, message)));
+    diagnostics
+}
+
+/// Check the sequences of `config` like [`validate`] does for the commands
+/// Sequences may use included commands, so they are checked once those have been resolved.
+pub fn validate_sequences(config: &Configuration, file: &str, source: &str, entry: Option<usize>) -> Vec<Diagnostic> {
+    let map = SourceMap::new(source, entry);
+    let mut diagnostics = Vec::new();
+    let mut report = |line: Option<usize>, message: String| diagnostics.push(Diagnostic::new(file, line, message));
+
+    for (index, sequence) in config.sequences.iter().enumerate() {
+        let line = |step: Option<usize>, key: &str| map.sequence_line(index, step, key);
+        let name = if sequence.name.is_empty() { format!("#{}", index + 1) } else { format!("\"{}\"", sequence.name) };
+        if sequence.name.trim().is_empty() {
+            report(line(None, "name"), format!("Sequence {} has no `name`", name));
+        } else if config.sequences[..index].iter().any(|other| other.name == sequence.name) {
+            report(line(None, "name"), format!("Sequence {} is defined twice", name));
+        }
+        if sequence.steps.is_empty() {
+            report(line(None, "steps"), format!("Sequence {} has no steps, add [[sequences.steps]] entries", name));
+        }
+        for (number, step) in sequence.steps.iter().enumerate() {
+            let label = format!("Step {} of sequence {}", number + 1, name);
+            match &step.command {
+                None if step.argument.is_some() => report(line(Some(number), "argument"), format!("{} has an `argument` but no `command`", label)),
+                None if !step.wait && step.delay_ms == 0 => {
+                    report(line(Some(number), "command"), format!("{} does nothing, set `command`, `wait` or `delay_ms`", label));
+                }
+                None => {}
+                Some(command) => {
+                    let key = if config.command(command).is_some() { "argument" } else { "command" };
+                    if let Err(e) = step.prepare(config, None) {
+                        report(line(Some(number), key), format!("{}: {}", label, e));
+                    }
+                }
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Line of `key` in the device table of `source`, or of entry number `entry` of a file with several devices
+pub fn device_line(source: &str, entry: Option<usize>, key: &str) -> Option<usize> {
+    SourceMap::new(source, entry).device_line(key)
+}
+
+/// Line of a `key` outside of all tables, at the top of `source`
+pub fn top_level_line(source: &str, key: &str) -> Option<usize> {
+    source.lines()
+        .take_while(|line| !line.trim_start().starts_with('['))
+        .position(|line| line.trim_start().strip_prefix(key).is_some_and(|rest| rest.trim_start().starts_with('=')))
+        .map(|index| index + 1)
+}
+
+/// Check every command for mistakes and `report` them with the line they were found in
+fn check_commands(commands: &[Command], map: &SourceMap, report: &mut impl FnMut(Option<usize>, String)) {
+    for (index, cmd) in commands.iter().enumerate() {
+        let line = |key: &str| map.command_line(index, key);
+        let label = if cmd.name.is_empty() { format!("Command #{}", index + 1) } else { format!("Command \"{}\"", cmd.name) };
+
+        if cmd.name.trim().is_empty() {
+            report(line("name"), format!("{} has no `name`", label));
+        } else if let Some(first) = commands[..index].iter().position(|other| other.name == cmd.name) {
+            let first_line = map.command_line(first, "name").map_or(String::new(), |l| format!(" in line {}", l));
+            report(line("name"), format!("{} is defined twice, first{}", label, first_line));
+        }
+        if cmd.scpi.trim().is_empty() {
+            report(line("scpi"), format!("{} has no `scpi` command", label));
+        }
+        if cmd.values.is_empty() {
+            report(line("values"), format!("{} has no `values`, use [\"\"] to send `scpi` as is", label));
+        }
+
+        // Placeholders have to be well-formed, declared parameters have to be used
+        let mut unknown: Vec<&str> = Vec::new();
+        for (key, text) in std::iter::once(("scpi", &cmd.scpi)).chain(cmd.values.iter().map(|value| ("values", value))) {
+            for token in placeholder_tokens(text) {
+                if token != "CH" && command::placeholders(&format!("<{}>", token)).is_empty() && !unknown.contains(&token) {
+                    unknown.push(token);
+                    report(line(key), format!("{} contains unknown placeholder <{}>, names are written in capitals like <FREQ>", label, token));
+                }
+            }
+        }
+        let templates: Vec<String> = cmd.values.iter().map(|value| format!("{}{}", cmd.scpi, value)).collect();
+        if !cmd.channel && templates.iter().chain(std::iter::once(&cmd.scpi)).any(|template| template.contains("<CH>")) {
+            report(line("channel"), format!("{} uses <CH> but is not a per-channel command, set `channel = true`", label));
+        }
+        let used: Vec<String> = templates.iter().flat_map(|template| command::placeholders(template)).collect();
+        let mut names: Vec<&String> = cmd.parameters.keys().collect();
+        names.sort();
+        for name in names {
+            let parameter = &cmd.parameters[name];
+            let table_line = map.parameter_line(index, name);
+            if !used.contains(name) {
+                report(table_line, format!("{} declares a parameter for unknown placeholder <{}>", label, name));
+            }
+            for (key, limit) in [("min", parameter.min), ("max", parameter.max), ("step", parameter.step)] {
+                if limit.is_some_and(|limit| !limit.is_finite()) {
+                    report(table_line, format!("{} has a parameter <{}> whose `{}` is not a finite number", label, name, key));
+                }
+            }
+            let numeric = matches!(parameter.kind, ParameterType::Float | ParameterType::Int);
+            let number_keys = [
+                ("min", parameter.min.is_some()),
+                ("max", parameter.max.is_some()),
+                ("step", parameter.step.is_some()),
+                ("unit", parameter.unit.is_some()),
+            ];
+            // A parameter without `type` is a string, so its limits would be ignored
+            let kind = match parameter.kind {
+                ParameterType::Bool => "bool",
+                ParameterType::Enum => "enum",
+                _ => "string",
+            };
+            for (key, _) in number_keys.iter().filter(|(_, set)| *set && !numeric) {
+                report(table_line, format!(
+                    "{} has a parameter <{}> of type \"{}\" with `{}`, which is only checked for type \"float\" or \"int\"",
+                    label, name, kind, key,
+                ));
+            }
+            if let Some(step) = parameter.step.filter(|step| *step <= 0.0) {
+                report(table_line, format!("{} has a parameter <{}> with `step` {}, it has to be above 0", label, name, step));
+            }
+            if let (Some(min), Some(max)) = (parameter.min, parameter.max) {
+                if min > max {
+                    report(table_line, format!("{} has a parameter <{}> with `min` {} above `max` {}", label, name, min, max));
+                }
+            }
+            if parameter.kind == ParameterType::Enum && parameter.choices.is_empty() {
+                report(table_line, format!("{} has an enum parameter <{}> without `choices`", label, name));
+            }
+        }
+    }
+}
+
+/// Returns the text between all `<` and `>` in `template`, whether or not it is a valid placeholder name
+fn placeholder_tokens(template: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        match rest.find('>') {
+            // Another "<" before the ">" means the first one was no placeholder
+            Some(end) if !rest[..end].contains('<') => tokens.push(&rest[..end]),
+            Some(_) => {}
+            None => break,
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load `source` as config file and return the messages of all diagnostics
+    fn diagnostics(name: &str, source: &str) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("instrument-app-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let result = Configuration::from(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let file = path.to_string_lossy().to_string();
+        result.err().unwrap_or_default().iter()
+            .map(|diagnostic| diagnostic.to_string().replace(&file, "config"))
+            .collect()
+    }
+
+    #[test]
+    fn test_example_config_is_valid() {
+        assert!(Configuration::from("../config/config.toml").is_ok());
+    }
+
+    #[test]
+    fn test_diagnostics_have_lines() {
+        let source = r#"[device]
+address = ""
+channels = 0
+
+[[commands]]
+channel = false
+name = "Output"
+scpi = ":OUTPut<CH> "
+values = ["<state>"]
+
+[[commands]]
+channel = true
+name = "Output"
+scpi = ""
+values = []
+[commands.parameters.FREQ]
+type = "enum"
+"#;
+        assert_eq!(diagnostics("lines", source), vec![
+            "config:3: `channels` must be between 1 and 32, got 0",
+            "config:9: Command \"Output\" contains unknown placeholder <state>, names are written in capitals like <FREQ>",
+            "config:6: Command \"Output\" uses <CH> but is not a per-channel command, set `channel = true`",
+            "config:13: Command \"Output\" is defined twice, first in line 7",
+            "config:14: Command \"Output\" has no `scpi` command",
+            "config:15: Command \"Output\" has no `values`, use [\"\"] to send `scpi` as is",
+            "config:16: Command \"Output\" declares a parameter for unknown placeholder <FREQ>",
+            "config:16: Command \"Output\" has an enum parameter <FREQ> without `choices`",
+        ]);
+    }
+
+    #[test]
+    fn test_limits_are_finite() {
+        let source = r#"[device]
+channels = 1
+
+[[commands]]
+name = "Frequency"
+scpi = ":FREQ "
+values = ["<FREQ>"]
+[commands.parameters.FREQ]
+type = "float"
+min = nan
+max = inf
+step = 1.0
+"#;
+        assert_eq!(diagnostics("limits", source), vec![
+            "config:8: Command \"Frequency\" has a parameter <FREQ> whose `min` is not a finite number",
+            "config:8: Command \"Frequency\" has a parameter <FREQ> whose `max` is not a finite number",
+        ]);
+    }
+
+    #[test]
+    fn test_number_keys_need_a_number_type() {
+        let source = r#"[device]
+channels = 1
+
+[[commands]]
+name = "Voltage"
+scpi = ":VOLT "
+values = ["<TXT>", "<STATE>"]
+[commands.parameters.TXT]
+min = 0
+max = 20
+[commands.parameters.STATE]
+type = "bool"
+unit = "V"
+"#;
+        assert_eq!(diagnostics("types", source), vec![
+            "config:11: Command \"Voltage\" has a parameter <STATE> of type \"bool\" with `unit`, which is only checked for type \"float\" or \"int\"",
+            "config:8: Command \"Voltage\" has a parameter <TXT> of type \"string\" with `min`, which is only checked for type \"float\" or \"int\"",
+            "config:8: Command \"Voltage\" has a parameter <TXT> of type \"string\" with `max`, which is only checked for type \"float\" or \"int\"",
+        ]);
+    }
+
+    #[test]
+    fn test_step_above_zero() {
+        let source = r#"[device]
+channels = 1
+
+[[commands]]
+name = "Voltage"
+scpi = ":VOLT "
+values = ["<TXT>"]
+[commands.parameters.TXT]
+type = "float"
+step = 0
+"#;
+        assert_eq!(diagnostics("step", source), vec![
+            "config:8: Command \"Voltage\" has a parameter <TXT> with `step` 0, it has to be above 0",
+        ]);
+    }
+
+    #[test]
+    fn test_sequences() {
+        let source = r#"[device]
+channels = 2
+
+[[commands]]
+channel = true
+name = "Output"
+scpi = ":OUTPut<CH> "
+values = ["on", "off"]
+
+[[sequences]]
+name = "Start"
+
+[[sequences.steps]]
+command = "Output"
+argument = "maybe"
+
+[[sequences.steps]]
+command = "Outptu"
+
+[[sequences.steps]]
+command = "Output"
+argument = "on"
+channel = 3
+
+[[sequences.steps]]
+
+[[sequences]]
+name = "Start"
+"#;
+        assert_eq!(diagnostics("sequences", source), vec![
+            "config:15: Step 1 of sequence \"Start\": \"maybe\" is no argument of Output, expected one of \"on\", \"off\"",
+            "config:18: Step 2 of sequence \"Start\": Unknown command \"Outptu\"",
+            "config:22: Step 3 of sequence \"Start\": Channel 3 is not available, the device has 2",
+            "config:25: Step 4 of sequence \"Start\" does nothing, set `command`, `wait` or `delay_ms`",
+            "config:28: Sequence \"Start\" is defined twice",
+            "config:27: Sequence \"Start\" has no steps, add [[sequences.steps]] entries",
+        ]);
+    }
+
+    #[test]
+    fn test_syntax_errors_and_missing_commands() {
+        let errors = diagnostics("syntax", "[device]\nchannels = 2\naddress = \"10.0.0.1\n");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("config:"), "{}", errors[0]);
+
+        let errors = diagnostics("empty", "[device]\nchannels = 2\n");
+        assert_eq!(errors, vec!["config: No commands defined, add at least one [[commands]] entry"]);
+    }
+
+    #[test]
+    fn test_lines_in_device_list() {
+        let source = r#"[[devices]]
+name = "Generator"
+channels = 1
+
+[[devices.commands]]
+name = "Output"
+scpi = ":OUTPut "
+values = ["on"]
+
+[[devices]]
+name = "Scope"
+channels = 4
+
+[[devices.commands]]
+name = "Run"
+scpi = ":RUN"
+values = ["", "<x>"]
+[devices.commands.parameters.X]
+type = "float"
+"#;
+        let path = std::env::temp_dir().join(format!("instrument-app-list-{}.toml", std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let result = Configuration::load_all(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<Option<usize>> = result.unwrap_err().iter().map(|diagnostic| diagnostic.line).collect();
+        assert_eq!(lines, vec![Some(17), Some(18)]);
+    }
+}
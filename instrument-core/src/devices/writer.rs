@@ -0,0 +1,181 @@
+//! Writes commands edited in the app back to a config file, keeping comments and keys the editor does not show
+use super::Command;
+use toml_edit::{Array, ArrayOfTables, DocumentMut, Item, Table, Value};
+
+/// A command edited in the app and the `[[commands]]` entry of the config file it was loaded from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EditedCommand {
+    /// Index of the entry in the config file, [`None`] for a new command
+    pub origin: Option<usize>,
+    pub command: Command,
+}
+
+/// Replace the commands of `file` by `commands`, or those of its `[[devices]]` entry number `entry`
+pub fn save_commands(file: &str, entry: Option<usize>, commands: &[EditedCommand]) -> Result<(), String> {
+    let source = std::fs::read_to_string(file).map_err(|e| format!("{} could not be read: {}", file, e))?;
+    let updated = update_commands(&source, entry, commands)?;
+    std::fs::write(file, updated).map_err(|e| format!("{} could not be written: {}", file, e))
+}
+
+/// Return `source` with its commands replaced by `commands`
+/// Entries of the file are reused for the commands loaded from them, so their comments, parameters and
+/// other keys stay, and only the values that changed are written anew.
+pub fn update_commands(source: &str, entry: Option<usize>, commands: &[EditedCommand]) -> Result<String, String> {
+    let mut document: DocumentMut = source.parse().map_err(|e: toml_edit::TomlError| e.to_string())?;
+    let table = match entry {
+        None => document.as_table_mut(),
+        Some(index) => document.get_mut("devices")
+            .and_then(Item::as_array_of_tables_mut)
+            .and_then(|devices| devices.get_mut(index))
+            .ok_or_else(|| format!("Device #{} not found in the config file", index + 1))?,
+    };
+    let array = table.entry("commands")
+        .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or("`commands` is not a list of [[commands]] tables")?;
+    let old: Vec<Table> = std::mem::take(array).into_iter().collect();
+
+    for edited in commands {
+        let command = &edited.command;
+        let mut table = edited.origin.and_then(|index| old.get(index).cloned()).unwrap_or_default();
+        // `channel = false` is only written if the entry is new or had the key already
+        if command.channel || edited.origin.is_none() || table.contains_key("channel") {
+            set(&mut table, "channel", Value::from(command.channel));
+        }
+        set(&mut table, "name", Value::from(command.name.as_str()));
+        set(&mut table, "scpi", Value::from(command.scpi.as_str()));
+        set(&mut table, "values", Value::Array(command.values.iter().map(String::as_str).collect::<Array>()));
+        array.push(table);
+    }
+
+    // Tables are written in order of their position, so number them in the new order
+    renumber(document.as_table_mut(), &mut 0);
+    Ok(document.to_string())
+}
+
+/// Set `key` of `table` to `value`, keeping the formatting of the old value if it did not change
+fn set(table: &mut Table, key: &str, value: Value) {
+    match table.get_mut(key).and_then(Item::as_value_mut) {
+        Some(old) if same(old, &value) => {}
+        Some(old) => {
+            let decor = old.decor().clone();
+            *old = value;
+            *old.decor_mut() = decor;
+        }
+        None => { table.insert(key, Item::Value(value)); }
+    }
+}
+
+/// Whether `a` and `b` hold the same strings, booleans or arrays of them, no matter how they are written
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| same(a, b)),
+        _ => false,
+    }
+}
+
+/// Number `table` and all tables below it in the order they are nested in
+fn renumber(table: &mut Table, next: &mut usize) {
+    table.set_position(*next);
+    *next += 1;
+    for (_, item) in table.iter_mut() {
+        match item {
+            Item::Table(table) => renumber(table, next),
+            Item::ArrayOfTables(array) => array.iter_mut().for_each(|table| renumber(table, next)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"# Sample config
+[device]
+channels = 2
+
+# Turns the output on or off
+[[commands]]
+channel = true
+name = "Output"
+scpi = ":OUTPut<CH> "
+values = ["on", "off"]  # lower case works as well
+
+[[commands]]
+name = "Frequency"
+scpi = ":FREQ"
+values = [" <TXT>", "?"]
+# Limits of the frequency
+[commands.parameters.TXT]
+type = "float"
+unit = "Hz"
+"#;
+
+    fn edited(origin: Option<usize>, name: &str, scpi: &str, values: &[&str]) -> EditedCommand {
+        let command = Command {
+            name: name.into(),
+            scpi: scpi.into(),
+            values: values.iter().map(|value| value.to_string()).collect(),
+            ..Default::default()
+        };
+        EditedCommand { origin, command }
+    }
+
+    #[test]
+    fn test_unchanged_commands_keep_the_file() {
+        let mut output = edited(Some(0), "Output", ":OUTPut<CH> ", &["on", "off"]);
+        output.command.channel = true;
+        let commands = vec![output, edited(Some(1), "Frequency", ":FREQ", &[" <TXT>", "?"])];
+        assert_eq!(update_commands(SOURCE, None, &commands).unwrap(), SOURCE);
+
+        // The same holds for the sample config
+        let source = std::fs::read_to_string("../config/config.toml").unwrap();
+        let commands: Vec<EditedCommand> = crate::devices::Configuration::load_commands("../config/config.toml", None).unwrap()
+            .into_iter()
+            .enumerate()
+            .map(|(index, command)| EditedCommand { origin: Some(index), command })
+            .collect();
+        assert_eq!(update_commands(&source, None, &commands).unwrap(), source);
+    }
+
+    #[test]
+    fn test_edit_reorder_add_and_delete() {
+        let commands = vec![
+            edited(Some(1), "Frequency", ":SOURce:FREQ", &[" <TXT>", "?"]),
+            edited(None, "Reset", "*RST", &[""]),
+        ];
+        let updated = update_commands(SOURCE, None, &commands).unwrap();
+        assert_eq!(updated, r#"# Sample config
+[device]
+channels = 2
+
+[[commands]]
+name = "Frequency"
+scpi = ":SOURce:FREQ"
+values = [" <TXT>", "?"]
+# Limits of the frequency
+[commands.parameters.TXT]
+type = "float"
+unit = "Hz"
+
+[[commands]]
+channel = false
+name = "Reset"
+scpi = "*RST"
+values = [""]
+"#);
+    }
+
+    #[test]
+    fn test_device_list() {
+        let source = "[[devices]]\nname = \"A\"\n\n[[devices.commands]]\nname = \"X\"\nscpi = \"X\"\nvalues = [\"\"]\n\n[[devices]]\nname = \"B\"\n";
+        let commands = vec![edited(Some(0), "X", "X", &[""]), edited(None, "Y", "Y?", &[""])];
+        let updated = update_commands(source, Some(0), &commands).unwrap();
+        let device_b = updated.find("name = \"B\"").unwrap();
+        assert!(updated.find("name = \"Y\"").unwrap() < device_b, "{}", updated);
+        assert!(update_commands(source, Some(2), &commands).is_err());
+    }
+}
@@ -0,0 +1,23 @@
+//! Core of instrument-app: connections to lab instruments, device configs, building SCPI commands and reading replies
+//!
+//! Useful on its own for test tools that drive instruments described by the same config files as the app:
+//!
+//! ```no_run
+//! use instrument_core::{command, Configuration, DeviceConnection};
+//! use std::collections::HashMap;
+//!
+//! let config = Configuration::from("config/config.toml").unwrap();
+//! let frequency = config.command("Frequency").unwrap();
+//! let scpi = command::make_scpi_command(frequency.clone(), 1, " <TXT>", &HashMap::from([("TXT".into(), "1k".into())])).unwrap();
+//!
+//! let mut connection = DeviceConnection::default();
+//! connection.connect(&config.device.connection, &config.device.address).unwrap();
+//! connection.execute(&command::make_request(frequency, scpi)).unwrap();
+//! ```
+pub mod devices;
+pub mod networking;
+pub mod script;
+pub mod simulator;
+
+pub use devices::{command, number, Command, Configuration, Device};
+pub use networking::{ConnectionSettings, DeviceConnection, Identity, Reply, Request, Response, SharedConnection, TransportKind};
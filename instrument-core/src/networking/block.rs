@@ -0,0 +1,83 @@
+//! Encoding of IEEE 488.2 arbitrary block data, as used for waveforms, screenshots and similar binary data
+//!
+//! A definite length block has the form `#<n><length><data>` with `n` being the number of digits of
+//! `length`, an indefinite length block has the form `#0<data>` and lasts until the end of the message.
+
+/// Largest definite length block accepted, longer ones are taken for a corrupted header
+pub const MAX_LENGTH: usize = 256 * 1024 * 1024;
+
+/// Number of bytes of a block read at once
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Encode `data` as a definite length block
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let length = data.len().to_string();
+    let mut block = format!("#{}{}", length.len(), length).into_bytes();
+    block.extend_from_slice(data);
+    block
+}
+
+/// Parse the number of digits following `#`, [`None`] for an indefinite length block
+pub fn parse_digit_count(digit: u8) -> std::io::Result<Option<usize>> {
+    match digit {
+        b'0' => Ok(None),
+        b'1'..=b'9' => Ok(Some((digit - b'0') as usize)),
+        _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid block header")),
+    }
+}
+
+/// Parse the length digits of a definite length block header, lengths above [`MAX_LENGTH`] are rejected
+pub fn parse_length(digits: &[u8]) -> std::io::Result<usize> {
+    let length: usize = std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid block length"))?;
+    if length > MAX_LENGTH {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Block of {} bytes exceeds the limit of {} bytes", length, MAX_LENGTH)));
+    }
+    Ok(length)
+}
+
+/// Format `data` as a hex dump with 16 bytes per line, showing at most `limit` bytes
+pub fn hex_dump(data: &[u8], limit: usize) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in data[..data.len().min(limit)].chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = chunk.iter()
+            .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })
+            .collect();
+        dump.push_str(&format!("{:08X}  {:<47}  {}\n", line * 16, hex.join(" "), ascii));
+    }
+    if data.len() > limit {
+        dump.push_str(&format!("... {} more bytes\n", data.len() - limit));
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(b"ABC"), b"#13ABC");
+        assert_eq!(encode(&[0u8; 1200])[..6], *b"#41200");
+        assert_eq!(encode(b""), b"#10");
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(parse_digit_count(b'0').unwrap(), None);
+        assert_eq!(parse_digit_count(b'9').unwrap(), Some(9));
+        assert!(parse_digit_count(b'X').is_err());
+        assert_eq!(parse_length(b"000001200").unwrap(), 1200);
+        assert!(parse_length(b"12a").is_err());
+        assert_eq!(parse_length(b"999999999").unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(hex_dump(b"AB\x00", 16), format!("00000000  {:<47}  AB.\n", "41 42 00"));
+        assert!(hex_dump(&[0u8; 40], 32).ends_with("... 8 more bytes\n"));
+    }
+}
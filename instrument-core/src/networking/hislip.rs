@@ -0,0 +1,510 @@
+//! HiSLIP transport (IVI-6.1), using a synchronous channel for data and an asynchronous channel
+//! for device clear, locking, status queries and service requests
+use super::{parse_ip, Transport, TIMEOUT};
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    net::{SocketAddr, TcpStream},
+    io::{Read, Write},
+    time::Duration};
+
+/// Message types as defined by IVI-6.1
+pub mod message_type {
+    pub const INITIALIZE: u8 = 0;
+    pub const INITIALIZE_RESPONSE: u8 = 1;
+    pub const FATAL_ERROR: u8 = 2;
+    pub const ERROR: u8 = 3;
+    pub const ASYNC_LOCK: u8 = 4;
+    pub const ASYNC_LOCK_RESPONSE: u8 = 5;
+    pub const DATA: u8 = 6;
+    pub const DATA_END: u8 = 7;
+    pub const DEVICE_CLEAR_COMPLETE: u8 = 8;
+    pub const DEVICE_CLEAR_ACKNOWLEDGE: u8 = 9;
+    pub const INTERRUPTED: u8 = 13;
+    pub const ASYNC_INTERRUPTED: u8 = 14;
+    pub const ASYNC_MAXIMUM_MESSAGE_SIZE: u8 = 15;
+    pub const ASYNC_MAXIMUM_MESSAGE_SIZE_RESPONSE: u8 = 16;
+    pub const ASYNC_INITIALIZE: u8 = 17;
+    pub const ASYNC_INITIALIZE_RESPONSE: u8 = 18;
+    pub const ASYNC_DEVICE_CLEAR: u8 = 19;
+    pub const ASYNC_SERVICE_REQUEST: u8 = 20;
+    pub const ASYNC_STATUS_QUERY: u8 = 21;
+    pub const ASYNC_STATUS_RESPONSE: u8 = 22;
+    pub const ASYNC_DEVICE_CLEAR_ACKNOWLEDGE: u8 = 23;
+}
+use message_type::*;
+
+/// Protocol version 1.0 as sent during initialization
+const PROTOCOL_VERSION: u32 = 0x0100;
+/// Vendor id sent during initialization, "ZZ" is reserved for clients that have none
+const VENDOR_ID: &[u8; 2] = b"ZZ";
+/// MessageID of the first message after initialization or a device clear
+const INITIAL_MESSAGE_ID: u32 = 0xFFFF_FF00;
+/// Largest message this client accepts
+const MAXIMUM_MESSAGE_SIZE: u64 = 1 << 20;
+
+/// Name of the device on the instrument if none is given in the address
+pub const DEFAULT_DEVICE: &str = "hislip0";
+
+/// A HiSLIP message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub message_type: u8,
+    pub control_code: u8,
+    pub parameter: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    /// Create a message without payload
+    pub fn new(message_type: u8, control_code: u8, parameter: u32) -> Self {
+        Self { message_type, control_code, parameter, payload: Vec::new() }
+    }
+
+    /// Add a payload to the message
+    pub fn with_payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /// Write the message including its header to `stream`
+    pub fn write_to(&self, stream: &mut impl Write) -> std::io::Result<()> {
+        let mut data = Vec::with_capacity(16 + self.payload.len());
+        data.extend_from_slice(b"HS");
+        data.push(self.message_type);
+        data.push(self.control_code);
+        data.extend_from_slice(&self.parameter.to_be_bytes());
+        data.extend_from_slice(&(self.payload.len() as u64).to_be_bytes());
+        data.extend_from_slice(&self.payload);
+        stream.write_all(&data)
+    }
+
+    /// Read a message from `stream`
+    pub fn read_from(stream: &mut impl Read) -> std::io::Result<Self> {
+        let mut header = [0u8; 16];
+        stream.read_exact(&mut header)?;
+        if &header[..2] != b"HS" {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid HiSLIP message header"));
+        }
+        let mut length = [0u8; 8];
+        length.copy_from_slice(&header[8..]);
+        let length = u64::from_be_bytes(length);
+        if length > MAXIMUM_MESSAGE_SIZE {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "HiSLIP message exceeds maximum size"));
+        }
+        let mut payload = vec![0u8; length as usize];
+        stream.read_exact(&mut payload)?;
+        Ok(Self {
+            message_type: header[2],
+            control_code: header[3],
+            parameter: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            payload,
+        })
+    }
+
+    /// Turn `Error` and `FatalError` messages into an [`std::io::Error`]
+    fn check_error(self) -> std::io::Result<Self> {
+        match self.message_type {
+            ERROR | FATAL_ERROR => Err(std::io::Error::other(
+                format!("HiSLIP {}error {}: {}",
+                        if self.message_type == FATAL_ERROR { "fatal " } else { "" },
+                        self.control_code,
+                        String::from_utf8_lossy(&self.payload)),
+            )),
+            _ => Ok(self),
+        }
+    }
+}
+
+/// Split a HiSLIP address of the form `IP:PORT[/SUBADDRESS]` into the socket address and sub-address
+fn split_address(address: &str) -> std::io::Result<(SocketAddr, &str)> {
+    let (host, device) = address.split_once('/').unwrap_or((address, DEFAULT_DEVICE));
+    let host = parse_ip(host).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    Ok((host, device))
+}
+
+/// Open a channel to `address`
+fn open_channel(address: &SocketAddr) -> std::io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// An established HiSLIP session
+struct Session {
+    sync_channel: TcpStream,
+    async_channel: TcpStream,
+    /// Sub-address of the device on the instrument, e.g. `hislip0`
+    device: String,
+    /// MessageID of the next message sent on the synchronous channel
+    message_id: u32,
+    /// Whether a complete response has been received since the last message was sent
+    rmt_delivered: bool,
+    /// Whether the server operates in overlapped (`true`) or synchronized mode
+    overlapped: bool,
+    /// Largest message the server accepts
+    maximum_message_size: usize,
+}
+
+impl Session {
+    /// Send a message on the asynchronous channel and wait for the response of the given type
+    /// Service requests that arrive in the meantime are queued in `service_requests`
+    fn async_request(&mut self, message: Message, response_type: u8, service_requests: &mut VecDeque<u8>) -> std::io::Result<Message> {
+        message.write_to(&mut self.async_channel)?;
+        loop {
+            let response = Message::read_from(&mut self.async_channel)?.check_error()?;
+            match response.message_type {
+                t if t == response_type => return Ok(response),
+                ASYNC_SERVICE_REQUEST => service_requests.push_back(response.control_code),
+                ASYNC_INTERRUPTED => {}
+                t => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Unexpected HiSLIP message type {}", t))),
+            }
+        }
+    }
+}
+
+/// [`Transport`] implementing a HiSLIP client
+#[derive(Default)]
+pub struct HiSlip {
+    session: Option<Session>,
+    /// Bytes that have been received but not yet handed out
+    pending: Vec<u8>,
+    /// Whether the last received data message was a `DataEND`
+    message_end: bool,
+    /// Status bytes received via `AsyncServiceRequest` that have not been handed out yet
+    service_requests: VecDeque<u8>,
+}
+
+impl HiSlip {
+    /// Return the established session or a `NotConnected` error
+    fn session(&mut self) -> std::io::Result<&mut Session> {
+        self.session.as_mut().ok_or_else(|| std::io::ErrorKind::NotConnected.into())
+    }
+
+    /// Whether the server operates in overlapped mode
+    pub fn overlapped(&self) -> Option<bool> {
+        self.session.as_ref().map(|session| session.overlapped)
+    }
+
+    /// Receive the next data message on the synchronous channel and append its payload to `pending`
+    fn receive_data(&mut self) -> std::io::Result<()> {
+        let session = self.session()?;
+        let message = Message::read_from(&mut session.sync_channel)?.check_error()?;
+        match message.message_type {
+            DATA | DATA_END => {
+                if message.message_type == DATA_END {
+                    session.rmt_delivered = true;
+                }
+                self.message_end = message.message_type == DATA_END;
+                self.pending.extend(message.payload);
+                Ok(())
+            }
+            // The server discarded a response because a new message was sent before it was read
+            INTERRUPTED => {
+                self.pending.clear();
+                Ok(())
+            }
+            t => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Unexpected HiSLIP message type {}", t))),
+        }
+    }
+
+    /// Move the first `count` pending bytes into `buffer`
+    fn take_pending(&mut self, count: usize, buffer: &mut Vec<u8>) -> usize {
+        buffer.extend(self.pending.drain(..count));
+        if self.pending.is_empty() {
+            self.message_end = false;
+        }
+        count
+    }
+}
+
+impl Transport for HiSlip {
+    fn connect(&mut self, address: &str) -> std::io::Result<()> {
+        // The old link may be dead already, which is when reconnecting is needed most
+        let _ = self.close();
+        let (address, device) = split_address(address)?;
+
+        // Initialize the synchronous channel
+        let mut sync_channel = open_channel(&address)?;
+        let vendor = u16::from_be_bytes(*VENDOR_ID) as u32;
+        Message::new(INITIALIZE, 0, PROTOCOL_VERSION << 16 | vendor)
+            .with_payload(device.as_bytes())
+            .write_to(&mut sync_channel)?;
+        let response = Message::read_from(&mut sync_channel)?.check_error()?;
+        if response.message_type != INITIALIZE_RESPONSE {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "HiSLIP initialization failed"));
+        }
+        let overlapped = response.control_code & 1 != 0;
+        let session_id = response.parameter & 0xFFFF;
+
+        // Attach the asynchronous channel to the session
+        let async_channel = open_channel(&address)?;
+        let mut session = Session {
+            sync_channel,
+            async_channel,
+            device: device.into(),
+            message_id: INITIAL_MESSAGE_ID,
+            rmt_delivered: false,
+            overlapped,
+            maximum_message_size: MAXIMUM_MESSAGE_SIZE as usize,
+        };
+        session.async_request(Message::new(ASYNC_INITIALIZE, 0, session_id), ASYNC_INITIALIZE_RESPONSE, &mut self.service_requests)?;
+
+        // Negotiate the maximum message size, the payload of the response holds the server's limit
+        let request = Message::new(ASYNC_MAXIMUM_MESSAGE_SIZE, 0, 0).with_payload(&MAXIMUM_MESSAGE_SIZE.to_be_bytes());
+        let response = session.async_request(request, ASYNC_MAXIMUM_MESSAGE_SIZE_RESPONSE, &mut self.service_requests)?;
+        if let Ok(size) = <[u8; 8]>::try_from(response.payload.as_slice()) {
+            session.maximum_message_size = (u64::from_be_bytes(size) as usize).max(1);
+        }
+
+        self.session = Some(session);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let session = self.session()?;
+        let mut chunks = data.chunks(session.maximum_message_size).peekable();
+        while let Some(chunk) = chunks.next() {
+            let message_type = if chunks.peek().is_none() { DATA_END } else { DATA };
+            Message::new(message_type, session.rmt_delivered as u8, session.message_id)
+                .with_payload(chunk)
+                .write_to(&mut session.sync_channel)?;
+            session.rmt_delivered = false;
+            session.message_id = session.message_id.wrapping_add(2);
+        }
+        Ok(data.len())
+    }
+
+    fn read_until(&mut self, terminator: u8, buffer: &mut Vec<u8>) -> std::io::Result<usize> {
+        loop {
+            if let Some(position) = self.pending.iter().position(|b| *b == terminator) {
+                return Ok(self.take_pending(position + 1, buffer));
+            }
+            // A complete message without terminator is returned as a whole
+            if self.message_end {
+                let count = self.pending.len();
+                return Ok(self.take_pending(count, buffer));
+            }
+            self.receive_data()?;
+        }
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> std::io::Result<()> {
+        while self.pending.len() < buffer.len() {
+            if self.message_end {
+                self.message_end = false;
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            self.receive_data()?;
+        }
+        let mut data = Vec::with_capacity(buffer.len());
+        self.take_pending(buffer.len(), &mut data);
+        buffer.copy_from_slice(&data);
+        Ok(())
+    }
+
+    fn read_message(&mut self, buffer: &mut Vec<u8>) -> std::io::Result<usize> {
+        while !self.message_end {
+            self.receive_data()?;
+        }
+        let count = self.pending.len();
+        Ok(self.take_pending(count, buffer))
+    }
+
+    fn skip(&mut self, expected: &[u8], _timeout: Duration) -> std::io::Result<bool> {
+        // Whatever follows a block comes with it in the same message, which has been read already
+        if !self.pending.starts_with(expected) {
+            return Ok(false);
+        }
+        self.take_pending(expected.len(), &mut Vec::new());
+        Ok(true)
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        self.pending.clear();
+        self.message_end = false;
+        self.service_requests.clear();
+        if let Some(session) = self.session.take() {
+            session.async_channel.shutdown(std::net::Shutdown::Both).ok();
+            session.sync_channel.shutdown(std::net::Shutdown::Both)?;
+        }
+        Ok(())
+    }
+
+    fn peer(&self) -> std::io::Result<String> {
+        let session = self.session.as_ref().ok_or(std::io::ErrorKind::NotConnected)?;
+        Ok(format!("{} (HiSLIP {}, {} mode)",
+                   session.sync_channel.peer_addr()?,
+                   session.device,
+                   if session.overlapped { "overlapped" } else { "synchronized" }))
+    }
+
+    /// Device clear as described in IVI-6.1 section 6.12, keeping the server's preferred mode
+    fn clear(&mut self) -> std::io::Result<()> {
+        self.pending.clear();
+        self.message_end = false;
+        let session = self.session.as_mut().ok_or(std::io::ErrorKind::NotConnected)?;
+        let acknowledge = session.async_request(Message::new(ASYNC_DEVICE_CLEAR, 0, 0), ASYNC_DEVICE_CLEAR_ACKNOWLEDGE, &mut self.service_requests)?;
+        Message::new(DEVICE_CLEAR_COMPLETE, acknowledge.control_code, 0).write_to(&mut session.sync_channel)?;
+        // Everything the server sent on the synchronous channel before the acknowledgement is stale
+        loop {
+            let message = Message::read_from(&mut session.sync_channel)?.check_error()?;
+            if message.message_type == DEVICE_CLEAR_ACKNOWLEDGE {
+                session.overlapped = message.control_code & 1 != 0;
+                session.message_id = INITIAL_MESSAGE_ID;
+                session.rmt_delivered = false;
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_status_byte(&mut self) -> std::io::Result<u8> {
+        let session = self.session.as_mut().ok_or(std::io::ErrorKind::NotConnected)?;
+        let request = Message::new(ASYNC_STATUS_QUERY, session.rmt_delivered as u8, session.message_id.wrapping_sub(2));
+        session.rmt_delivered = false;
+        Ok(session.async_request(request, ASYNC_STATUS_RESPONSE, &mut self.service_requests)?.control_code)
+    }
+
+    fn lock(&mut self, timeout: Duration) -> std::io::Result<()> {
+        let session = self.session.as_mut().ok_or(std::io::ErrorKind::NotConnected)?;
+        let request = Message::new(ASYNC_LOCK, 1, timeout.as_millis() as u32);
+        match session.async_request(request, ASYNC_LOCK_RESPONSE, &mut self.service_requests)?.control_code {
+            1 => Ok(()),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Device could not be locked")),
+        }
+    }
+
+    fn unlock(&mut self) -> std::io::Result<()> {
+        let session = self.session.as_mut().ok_or(std::io::ErrorKind::NotConnected)?;
+        let request = Message::new(ASYNC_LOCK, 0, session.message_id.wrapping_sub(2));
+        match session.async_request(request, ASYNC_LOCK_RESPONSE, &mut self.service_requests)?.control_code {
+            1 | 2 => Ok(()),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "No lock held by this session")),
+        }
+    }
+
+    fn service_request(&mut self) -> std::io::Result<Option<u8>> {
+        let session = self.session.as_mut().ok_or(std::io::ErrorKind::NotConnected)?;
+        // Collect service requests the server sent without being asked
+        loop {
+            session.async_channel.set_nonblocking(true)?;
+            let mut header = [0u8; 16];
+            let available = session.async_channel.peek(&mut header);
+            session.async_channel.set_nonblocking(false)?;
+            match available {
+                Ok(16) => {
+                    let message = Message::read_from(&mut session.async_channel)?.check_error()?;
+                    if message.message_type == ASYNC_SERVICE_REQUEST {
+                        self.service_requests.push_back(message.control_code);
+                    }
+                }
+                Ok(0) => return Err(std::io::ErrorKind::ConnectionAborted.into()),
+                Ok(_) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(self.service_requests.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    /// Start a HiSLIP stand-in server that echoes written data, return its address
+    fn stand_in_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut sync_channel, _) = listener.accept().unwrap();
+            let initialize = Message::read_from(&mut sync_channel).unwrap();
+            assert_eq!(initialize.message_type, INITIALIZE);
+            assert_eq!(initialize.payload, b"hislip0");
+            Message::new(INITIALIZE_RESPONSE, 1, PROTOCOL_VERSION << 16 | 42).write_to(&mut sync_channel).unwrap();
+
+            let (mut async_channel, _) = listener.accept().unwrap();
+            let initialize = Message::read_from(&mut async_channel).unwrap();
+            assert_eq!((initialize.message_type, initialize.parameter), (ASYNC_INITIALIZE, 42));
+            Message::new(ASYNC_INITIALIZE_RESPONSE, 0, 0).write_to(&mut async_channel).unwrap();
+            // Service request interleaved with the responses the client asked for
+            Message::new(ASYNC_SERVICE_REQUEST, 0x50, 0).write_to(&mut async_channel).unwrap();
+
+            thread::spawn(move || {
+                while let Ok(message) = Message::read_from(&mut async_channel) {
+                    let response = match message.message_type {
+                        // Tiny maximum size to exercise splitting of writes
+                        ASYNC_MAXIMUM_MESSAGE_SIZE => Message::new(ASYNC_MAXIMUM_MESSAGE_SIZE_RESPONSE, 0, 0).with_payload(&4u64.to_be_bytes()),
+                        ASYNC_STATUS_QUERY => Message::new(ASYNC_STATUS_RESPONSE, 0x40, 0),
+                        ASYNC_LOCK => Message::new(ASYNC_LOCK_RESPONSE, 1, 0),
+                        ASYNC_DEVICE_CLEAR => Message::new(ASYNC_DEVICE_CLEAR_ACKNOWLEDGE, 1, 0),
+                        _ => Message::new(ERROR, 0, 0),
+                    };
+                    response.write_to(&mut async_channel).unwrap();
+                }
+            });
+
+            let mut expected_id = INITIAL_MESSAGE_ID;
+            let mut input = Vec::new();
+            while let Ok(message) = Message::read_from(&mut sync_channel) {
+                match message.message_type {
+                    DATA | DATA_END => {
+                        assert_eq!(message.parameter, expected_id);
+                        expected_id = expected_id.wrapping_add(2);
+                        input.extend(message.payload);
+                        if message.message_type == DATA_END {
+                            // Echo the complete message back in two parts
+                            let (first, second) = input.split_at(input.len() / 2);
+                            Message::new(DATA, 0, message.parameter).with_payload(first).write_to(&mut sync_channel).unwrap();
+                            Message::new(DATA_END, 0, message.parameter).with_payload(second).write_to(&mut sync_channel).unwrap();
+                            input.clear();
+                        }
+                    }
+                    DEVICE_CLEAR_COMPLETE => {
+                        expected_id = INITIAL_MESSAGE_ID;
+                        Message::new(DEVICE_CLEAR_ACKNOWLEDGE, message.control_code, 0).write_to(&mut sync_channel).unwrap();
+                    }
+                    _ => Message::new(ERROR, 0, 0).write_to(&mut sync_channel).unwrap(),
+                }
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_hislip_round_trip() {
+        let address = stand_in_server();
+        let mut hislip = HiSlip::default();
+        hislip.connect(&address.to_string()).unwrap();
+        assert_eq!(hislip.overlapped(), Some(true));
+        assert_eq!(hislip.peer().unwrap(), format!("{} (HiSLIP hislip0, overlapped mode)", address));
+
+        assert_eq!(hislip.write(b"*IDN?\n").unwrap(), 6);
+        let mut line = Vec::new();
+        hislip.read_until(b'\n', &mut line).unwrap();
+        assert_eq!(line, b"*IDN?\n");
+
+        hislip.write(b"ABCDEFGH").unwrap();
+        let mut block = [0u8; 2];
+        hislip.read_exact(&mut block).unwrap();
+        assert_eq!(&block, b"AB");
+        let mut message = Vec::new();
+        hislip.read_message(&mut message).unwrap();
+        assert_eq!(message, b"CDEFGH");
+
+        assert_eq!(hislip.service_request().unwrap(), Some(0x50));
+        assert_eq!(hislip.service_request().unwrap(), None);
+        assert_eq!(hislip.read_status_byte().unwrap(), 0x40);
+        hislip.lock(Duration::from_millis(100)).unwrap();
+        hislip.clear().unwrap();
+
+        // Message ids start over after the device clear
+        hislip.write(b"*OPC?\n").unwrap();
+        line.clear();
+        hislip.read_until(b'\n', &mut line).unwrap();
+        assert_eq!(line, b"*OPC?\n");
+        hislip.close().unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+//! Identification of an instrument by its reply to the IEEE 488.2 `*IDN?` query
+
+/// Query every SCPI instrument answers with its identity
+pub const IDN_QUERY: &str = "*IDN?";
+
+/// Manufacturer, model, serial number and firmware version reported by an instrument
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub manufacturer: String,
+    pub model: String,
+    /// Serial number, `0` if the instrument does not report one
+    pub serial: String,
+    pub firmware: String,
+    /// The reply as received, used to match `idn` patterns of the device profiles
+    pub raw: String,
+}
+
+impl Identity {
+    /// Parse a reply like `RIGOL TECHNOLOGIES,DG1022Z,DG1ZA000000001,00.03.00.09.00.02.04`
+    /// Missing fields are left empty, returns [`None`] if the reply holds no manufacturer and model.
+    pub fn parse(reply: &str) -> Option<Self> {
+        let raw = reply.trim().to_string();
+        let mut fields = raw.splitn(4, ',').map(|field| field.trim().trim_matches('"').to_string());
+        let identity = Self {
+            manufacturer: fields.next().unwrap_or_default(),
+            model: fields.next().unwrap_or_default(),
+            serial: fields.next().unwrap_or_default(),
+            firmware: fields.next().unwrap_or_default(),
+            raw: raw.clone(),
+        };
+        if identity.manufacturer.is_empty() || identity.model.is_empty() {
+            return None;
+        }
+        Some(identity)
+    }
+
+    /// Whether the reply matches any of `patterns`, compared case-insensitively
+    /// `*` in a pattern stands for any text, e.g. `RIGOL*,DG10*`. Fields left out at the end match anything.
+    pub fn matches(&self, patterns: &[String]) -> bool {
+        patterns.iter().any(|pattern| {
+            let pattern = pattern.trim().to_ascii_lowercase();
+            let raw = self.raw.to_ascii_lowercase();
+            !pattern.is_empty() && (wildcard_match(&pattern, &raw) || wildcard_match(&format!("{},*", pattern), &raw))
+        })
+    }
+}
+
+impl std::fmt::Display for Identity {
+    /// Show the identity like `RIGOL TECHNOLOGIES DG1022Z, serial DG1ZA000000001, firmware 00.03`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.manufacturer, self.model)?;
+        if !self.serial.is_empty() && self.serial != "0" {
+            write!(f, ", serial {}", self.serial)?;
+        }
+        if !self.firmware.is_empty() {
+            write!(f, ", firmware {}", self.firmware)?;
+        }
+        Ok(())
+    }
+}
+
+/// Match `text` against `pattern`, where `*` matches any sequence of characters
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // The text has to start with the part before the first `*` ...
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    let last = match parts.split_last() {
+        Some((last, middle)) => {
+            // ... contain the parts between them in order, as early as possible ...
+            for part in middle {
+                match rest.find(part) {
+                    Some(index) => rest = &rest[index + part.len()..],
+                    None => return false,
+                }
+            }
+            *last
+        }
+        None => return rest.is_empty(),
+    };
+    // ... and end with the part after the last `*`
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let identity = Identity::parse("RIGOL TECHNOLOGIES,DG1022Z,DG1ZA000000001,00.03.00.09.00.02.04\n").unwrap();
+        assert_eq!(identity.manufacturer, "RIGOL TECHNOLOGIES");
+        assert_eq!(identity.model, "DG1022Z");
+        assert_eq!(identity.serial, "DG1ZA000000001");
+        assert_eq!(identity.firmware, "00.03.00.09.00.02.04");
+        assert_eq!(identity.to_string(), "RIGOL TECHNOLOGIES DG1022Z, serial DG1ZA000000001, firmware 00.03.00.09.00.02.04");
+
+        let identity = Identity::parse("Keysight Technologies,34465A,0,A.02.14-02.40").unwrap();
+        assert_eq!(identity.to_string(), "Keysight Technologies 34465A, firmware A.02.14-02.40");
+        assert_eq!(Identity::parse(""), None);
+        assert_eq!(Identity::parse("1"), None);
+    }
+
+    #[test]
+    fn test_matches() {
+        let identity = Identity::parse("RIGOL TECHNOLOGIES,DG1022Z,DG1ZA000000001,00.03").unwrap();
+        assert!(identity.matches(&["rigol technologies,DG1022Z".into()]));
+        assert!(identity.matches(&["RIGOL*,DG10*".into()]));
+        assert!(identity.matches(&["*,DG1022Z,*".into()]));
+        assert!(identity.matches(&["Keysight*".into(), "*DG1022Z*".into()]));
+        assert!(!identity.matches(&["RIGOL*,DS1054Z".into()]));
+        assert!(!identity.matches(&["RIGOL TECHNOLOGIES,DG1022".into()]));
+        assert!(!identity.matches(&[]));
+        assert!(!identity.matches(&["".into()]));
+    }
+}
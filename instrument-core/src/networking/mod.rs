@@ -0,0 +1,703 @@
+//! Provides a [`DeviceConnection`] and utility functions for networking and communication
+//!
+//! The actual I/O is done by a [`Transport`], one implementation per kind of physical or
+//! protocol-level connection. Which one is used is selected via [`TransportKind`].
+pub mod block;
+pub mod identity;
+mod raw_socket;
+mod rpc;
+mod vxi11;
+mod hislip;
+mod serial;
+
+pub use raw_socket::RawSocket;
+pub use vxi11::Vxi11;
+pub use hislip::HiSlip;
+pub use serial::{Serial, Parity, FlowControl};
+pub use identity::Identity;
+
+use serde::Deserialize;
+use std::{
+    io::BufRead,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration};
+
+/// Timeout used when connecting to and reading from a device
+pub const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Maximum number of entries read from the error queue after a command,
+/// in case a device never reports `0,"No error"`
+const MAX_ERRORS: usize = 32;
+
+/// Time to wait for the terminator after a definite length block, some devices send none
+const BLOCK_TERMINATOR_WAIT: Duration = Duration::from_millis(50);
+
+/// A [`DeviceConnection`] that can be shared between the UI and background tasks
+pub type SharedConnection = Arc<Mutex<DeviceConnection>>;
+
+/// A way of talking to an instrument, e.g. a raw TCP socket or a serial port
+pub trait Transport: Send {
+    /// Open the connection to the device at `address`, the format of which depends on the transport
+    fn connect(&mut self, address: &str) -> std::io::Result<()>;
+    /// Write all of `data` to the device and return the number of bytes written
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize>;
+    /// Append bytes to `buffer` until (and including) `terminator`, return the number of bytes read
+    fn read_until(&mut self, terminator: u8, buffer: &mut Vec<u8>) -> std::io::Result<usize>;
+    /// Read exactly `buffer.len()` bytes from the device
+    fn read_exact(&mut self, buffer: &mut [u8]) -> std::io::Result<()>;
+    /// Append bytes to `buffer` until the end of the current message, return the number of bytes read
+    /// Transports without message framing read up to the next newline.
+    fn read_message(&mut self, buffer: &mut Vec<u8>) -> std::io::Result<usize> {
+        self.read_until(b'\n', buffer)
+    }
+    /// Read `expected` if it is what the device sends next, waiting at most `timeout` for it to arrive
+    /// Anything else stays unread, returns whether `expected` has been read.
+    fn skip(&mut self, expected: &[u8], timeout: Duration) -> std::io::Result<bool>;
+    /// Close the connection, further reads and writes return a `NotConnected` error
+    fn close(&mut self) -> std::io::Result<()>;
+    /// Human-readable description of the connected peer
+    fn peer(&self) -> std::io::Result<String>;
+    /// Send a device clear, if the transport supports it
+    fn clear(&mut self) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+    /// Read the status byte without going through the message channel, if the transport supports it
+    fn read_status_byte(&mut self) -> std::io::Result<u8> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+    /// Acquire exclusive access to the device, waiting at most `timeout`, if the transport supports it
+    fn lock(&mut self, _timeout: Duration) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+    /// Release the lock acquired with [`Transport::lock`], if the transport supports it
+    fn unlock(&mut self) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+    /// Return the status byte of the oldest service request the device sent, if the transport supports it
+    fn service_request(&mut self) -> std::io::Result<Option<u8>> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// All available kinds of [`Transport`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// [`RawSocket`]
+    #[default]
+    #[serde(alias = "raw")]
+    RawSocket,
+    /// [`Vxi11`]
+    #[serde(alias = "vxi-11")]
+    Vxi11,
+    /// [`HiSlip`]
+    HiSlip,
+    /// [`Serial`]
+    Serial,
+}
+
+impl TransportKind {
+    /// All transports, in the order they are offered to the user
+    pub const ALL: [TransportKind; 4] = [TransportKind::RawSocket, TransportKind::Vxi11, TransportKind::HiSlip, TransportKind::Serial];
+
+    /// Create a new, not yet connected [`Transport`] of this kind, configured by `settings`
+    pub fn create(&self, settings: &ConnectionSettings) -> Box<dyn Transport> {
+        match self {
+            TransportKind::RawSocket => Box::new(RawSocket::default()),
+            TransportKind::Vxi11 => Box::new(Vxi11::default()),
+            TransportKind::HiSlip => Box::new(HiSlip::default()),
+            TransportKind::Serial => Box::new(Serial::new(settings.clone())),
+        }
+    }
+
+    /// The port instruments usually listen on for this transport, [`None`] if it does not use ports
+    pub fn default_port(&self) -> Option<u16> {
+        match self {
+            TransportKind::RawSocket => Some(5555),
+            // Port of the portmapper, which is asked for the port of the VXI-11 core channel
+            TransportKind::Vxi11 => Some(111),
+            TransportKind::HiSlip => Some(4880),
+            TransportKind::Serial => None,
+        }
+    }
+
+    /// Hint shown in the empty host field for this transport
+    pub fn host_placeholder(&self) -> &'static str {
+        match self {
+            TransportKind::Serial => "Serial port",
+            _ => "IP Address",
+        }
+    }
+
+    /// Build the address passed to [`Transport::connect`] from the host and port entered by the user
+    pub fn make_address(&self, host: &str, port: &str) -> Result<String, String> {
+        match self {
+            TransportKind::Serial if host.trim().is_empty() => Err("No serial port entered".into()),
+            TransportKind::Serial => Ok(host.trim().into()),
+            TransportKind::RawSocket => parse_ip(&format!("{}:{}", host, port)).map(|addr| addr.to_string()),
+            TransportKind::Vxi11 => parse_ip(&format!("{}:{}", host, port)).map(|addr| format!("{}/{}", addr, vxi11::DEFAULT_DEVICE)),
+            TransportKind::HiSlip => parse_ip(&format!("{}:{}", host, port)).map(|addr| format!("{}/{}", addr, hislip::DEFAULT_DEVICE)),
+        }
+    }
+
+    /// Split an address from the config into host and port, the inverse of [`TransportKind::make_address`]
+    /// Addresses without a port get the default port of the transport.
+    pub fn split_address(&self, address: &str) -> (String, String) {
+        let address = address.trim();
+        let default_port = match self.default_port() {
+            Some(port) => port.to_string(),
+            None => return (address.into(), String::new()),
+        };
+        // Ignore a device name like "/inst0" after the port
+        let address = address.split('/').next().unwrap_or_default();
+        match address.rsplit_once(':') {
+            Some((host, port)) if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => (host.into(), port.into()),
+            _ => (address.into(), default_port),
+        }
+    }
+}
+
+impl std::fmt::Display for TransportKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportKind::RawSocket => write!(f, "Raw socket"),
+            TransportKind::Vxi11 => write!(f, "VXI-11"),
+            TransportKind::HiSlip => write!(f, "HiSLIP"),
+            TransportKind::Serial => write!(f, "Serial"),
+        }
+    }
+}
+
+/// How to talk to a device, as set in the `[device]` table of the config
+/// The terminator applies to all transports, the serial framing is only used by the [`Serial`] transport.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ConnectionSettings {
+    /// Transport used to talk to the device, e.g. `"raw"`, `"vxi11"`, `"hislip"` or `"serial"`
+    pub transport: TransportKind,
+    /// Line terminator used by the device, a trailing newline of sent commands is replaced by it
+    pub terminator: String,
+    /// Baud rate of a serial connection
+    pub baud_rate: u32,
+    /// Number of data bits per character (5 to 8) of a serial connection
+    pub data_bits: u8,
+    /// Parity of a serial connection
+    pub parity: Parity,
+    /// Number of stop bits (1 or 2) of a serial connection
+    pub stop_bits: u8,
+    /// Flow control of a serial connection
+    pub flow_control: FlowControl,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        Self {
+            transport: TransportKind::default(),
+            terminator: "\n".into(),
+            baud_rate: 9600,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+/// A command to be sent to the device and the reply expected in return
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// Complete command, ending in a newline
+    pub command: String,
+    /// Number of lines the device replies with, `0` for commands that do not produce a reply
+    pub reply_lines: usize,
+    /// Query that drains the device's error queue after the command, [`None`] to skip the check
+    pub error_query: Option<String>,
+}
+
+/// Reply read from the device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// One or more lines of text, joined by newlines
+    Text(String),
+    /// Binary data from an IEEE 488.2 block
+    Block(Vec<u8>),
+}
+
+/// Wrapper for an [`Option<Box<dyn Transport>>`] that is shared across the application
+#[derive(Default)]
+pub struct DeviceConnection {
+    transport: Option<Box<dyn Transport>>,
+    /// Line terminator of the connected device, empty if not connected
+    terminator: Vec<u8>,
+}
+
+impl DeviceConnection {
+    /// Connect to a device listening on `address` using the [`Transport`] selected in `settings`
+    pub fn connect(&mut self, settings: &ConnectionSettings, address: &str) -> std::io::Result<()> {
+        // The old link may be dead already, which is when reconnecting is needed most
+        let _ = self.close();
+        let mut transport = settings.transport.create(settings);
+        transport.connect(address)?;
+        self.transport = Some(transport);
+        self.terminator = match settings.terminator.as_bytes() {
+            [] => b"\n".to_vec(),
+            terminator => terminator.to_vec(),
+        };
+        Ok(())
+    }
+
+    /// Return if the [`DeviceConnection`] is connected to a device
+    pub fn connected(&self) -> bool {
+        self.transport.is_some()
+    }
+
+    /// Return the [`Transport`] or a `NotConnected` error
+    fn transport(&mut self) -> std::io::Result<&mut Box<dyn Transport>> {
+        self.transport.as_mut().ok_or_else(|| std::io::ErrorKind::NotConnected.into())
+    }
+
+    /// Send a `&[u8]` slice to the connected device, replacing a trailing newline with the device's terminator
+    pub fn send(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        match data.strip_suffix(b"\n") {
+            Some(line) if self.terminator != b"\n" => {
+                let mut line = line.to_vec();
+                line.extend_from_slice(&self.terminator);
+                self.transport()?.write(&line)?;
+                Ok(data.len())
+            }
+            _ => self.transport()?.write(data),
+        }
+    }
+
+    /// Receive a line from the connected device, without its terminator
+    /// Reading stops at the complete terminator or when a message-based transport signals the end of the message.
+    pub fn read_line(&mut self) -> std::io::Result<String> {
+        self.read_line_after(Vec::new())
+    }
+
+    /// Receive the rest of a line of which `data` has already been read
+    fn read_line_after(&mut self, mut data: Vec<u8>) -> std::io::Result<String> {
+        let terminator = self.terminator.clone();
+        let last = *terminator.last().ok_or(std::io::ErrorKind::NotConnected)?;
+        let transport = self.transport()?;
+        while !data.ends_with(&terminator) {
+            if transport.read_until(last, &mut data)? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            // The end of a message without terminator
+            if data.last() != Some(&last) {
+                return Ok(String::from_utf8_lossy(&data).into_owned());
+            }
+        }
+        data.truncate(data.len() - terminator.len());
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+
+    /// Receive `count` lines from the connected device and join them with newlines
+    pub fn read_lines(&mut self, count: usize) -> std::io::Result<String> {
+        let lines = (0..count).map(|_| self.read_line()).collect::<std::io::Result<Vec<_>>>()?;
+        Ok(lines.join("\n"))
+    }
+
+    /// Receive a reply of `lines` lines of text, or a single block if the reply starts with `#`
+    pub fn read_response(&mut self, lines: usize) -> std::io::Result<Response> {
+        let mut first = [0u8; 1];
+        self.transport()?.read_exact(&mut first)?;
+        if first[0] == b'#' {
+            return self.read_block_after_hash().map(Response::Block);
+        }
+        let mut text = self.read_line_after(first.to_vec())?;
+        if lines > 1 {
+            text.push('\n');
+            text.push_str(&self.read_lines(lines - 1)?);
+        }
+        Ok(Response::Text(text))
+    }
+
+    /// Receive an IEEE 488.2 block, skipping anything in front of its `#`
+    pub fn read_block(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut byte = [0u8; 1];
+        while byte[0] != b'#' {
+            self.transport()?.read_exact(&mut byte)?;
+        }
+        self.read_block_after_hash()
+    }
+
+    /// Receive the rest of an IEEE 488.2 block of which the `#` has already been read
+    fn read_block_after_hash(&mut self) -> std::io::Result<Vec<u8>> {
+        let terminator = self.terminator.clone();
+        let transport = self.transport()?;
+        let mut digit_count = [0u8; 1];
+        transport.read_exact(&mut digit_count)?;
+        let mut data = Vec::new();
+        match block::parse_digit_count(digit_count[0])? {
+            // Indefinite length blocks last until the end of the message, which ends in a newline
+            None => {
+                transport.read_message(&mut data)?;
+                if data.last() == Some(&b'\n') {
+                    data.pop();
+                }
+            }
+            Some(digit_count) => {
+                let mut digits = vec![0u8; digit_count];
+                transport.read_exact(&mut digits)?;
+                let length = block::parse_length(&digits)?;
+                // Grow the buffer as the data arrives, so a corrupted header allocates no more than has been received
+                while data.len() < length {
+                    let start = data.len();
+                    data.resize(length.min(start + block::CHUNK_SIZE), 0);
+                    transport.read_exact(&mut data[start..])?;
+                }
+                transport.skip(&terminator, BLOCK_TERMINATOR_WAIT)?;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Send `header` followed by `data` as a definite length block and the terminator, e.g. for uploading waveforms
+    pub fn send_block(&mut self, header: &str, data: &[u8]) -> std::io::Result<usize> {
+        let mut message = header.as_bytes().to_vec();
+        message.extend(block::encode(data));
+        message.push(b'\n');
+        self.send(&message)
+    }
+
+    /// Close the connection to the device, if there is one
+    pub fn close(&mut self) -> std::io::Result<()> {
+        self.terminator.clear();
+        match self.transport.take() {
+            Some(mut transport) => transport.close(),
+            None => Ok(()),
+        }
+    }
+
+    /// Send a device clear, aborting pending operations and emptying the device's buffers
+    pub fn clear(&mut self) -> std::io::Result<()> {
+        self.transport()?.clear()
+    }
+
+    /// Read the status byte of the device
+    pub fn read_status_byte(&mut self) -> std::io::Result<u8> {
+        self.transport()?.read_status_byte()
+    }
+
+    /// Acquire exclusive access to the device, waiting at most `timeout`
+    pub fn lock(&mut self, timeout: Duration) -> std::io::Result<()> {
+        self.transport()?.lock(timeout)
+    }
+
+    /// Release the lock acquired with [`DeviceConnection::lock`]
+    pub fn unlock(&mut self) -> std::io::Result<()> {
+        self.transport()?.unlock()
+    }
+
+    /// Return the status byte of the oldest pending service request, [`None`] if there is none
+    pub fn service_request(&mut self) -> std::io::Result<Option<u8>> {
+        self.transport()?.service_request()
+    }
+
+    /// Get a description of the connected peer or a `NotConnected` error, if there is no connection established
+    pub fn peer(&self) -> std::io::Result<String> {
+        self.transport.as_ref().ok_or(std::io::ErrorKind::NotConnected)?.peer()
+    }
+
+    /// Send the command of `request`, read as many lines as it expects and drain the error queue if requested
+    /// Errors are turned into messages for the user.
+    pub fn execute(&mut self, request: &Request) -> Result<Reply, String> {
+        let sent = self.send(request.command.as_bytes()).map_err(describe_error)?;
+        let response = match request.reply_lines {
+            0 => None,
+            lines => Some(self.read_response(lines).map_err(describe_error)?),
+        };
+        let errors = match &request.error_query {
+            Some(query) => self.read_errors(query)?,
+            None => Vec::new(),
+        };
+        Ok(Reply { sent, response, errors })
+    }
+
+    /// Drain the error queue with `query` and return the decoded entries
+    pub fn read_errors(&mut self, query: &str) -> Result<Vec<String>, String> {
+        drain_errors(self, query).map_err(describe_error)
+    }
+}
+
+/// Reply of the device to a command that has been sent in the background
+#[derive(Debug, Clone)]
+pub struct Reply {
+    /// Number of bytes that have been sent to the device
+    pub sent: usize,
+    /// Response read from the device, [`None`] if no response has been requested
+    pub response: Option<Response>,
+    /// Decoded entries of the error queue read after the command, empty if there were none or it was not checked
+    pub errors: Vec<String>,
+}
+
+/// [`Transport::skip`] for transports reading through a [`BufRead`], `set_timeout` sets the read timeout of the stream
+/// The timeout is set back to [`TIMEOUT`] afterwards.
+fn skip_buffered<R: BufRead>(
+    reader: &mut R,
+    expected: &[u8],
+    timeout: Duration,
+    set_timeout: impl Fn(&mut R, Duration) -> std::io::Result<()>,
+) -> std::io::Result<bool> {
+    set_timeout(reader, timeout)?;
+    let mut rest = expected;
+    let result = loop {
+        if rest.is_empty() {
+            break Ok(true);
+        }
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break Ok(false),
+            Err(e) => break Err(e),
+        };
+        let count = available.len().min(rest.len());
+        if count == 0 || available[..count] != rest[..count] {
+            break Ok(false);
+        }
+        reader.consume(count);
+        rest = &rest[count..];
+    };
+    set_timeout(reader, TIMEOUT)?;
+    result
+}
+
+/// Lock the [`SharedConnection`], turning a poisoned lock into an error message
+pub(crate) fn lock(connection: &SharedConnection) -> Result<MutexGuard<'_, DeviceConnection>, String> {
+    connection.lock().map_err(|_| "Connection is unavailable after a previous failure".to_string())
+}
+
+/// Background task: connect the [`SharedConnection`] to `address` and return a description of the peer
+pub async fn connect(connection: SharedConnection, settings: ConnectionSettings, address: String) -> Result<String, String> {
+    let mut connection = lock(&connection)?;
+    connection.connect(&settings, &address).map_err(|e| e.to_string())?;
+    connection.peer().map_err(|e| e.to_string())
+}
+
+/// A device the [`SharedConnection`] has been connected to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    /// Description of the peer, e.g. `10.10.2.8:5555 (raw socket)`
+    pub description: String,
+    /// Identity reported by the device, or why it could not be read
+    pub identity: Result<Identity, String>,
+}
+
+/// Background task: connect like [`connect`] and ask the device who it is via `*IDN?`
+/// A device that does not answer is still connected, only its identity is unknown.
+pub async fn connect_and_identify(connection: SharedConnection, settings: ConnectionSettings, address: String) -> Result<Peer, String> {
+    let description = connect(connection.clone(), settings, address).await?;
+    let mut connection = lock(&connection)?;
+    let identity = connection.send(format!("{}\n", identity::IDN_QUERY).as_bytes())
+        .and_then(|_| connection.read_line())
+        .map_err(describe_error)
+        .and_then(|reply| Identity::parse(&reply).ok_or_else(|| format!("Unexpected reply to {}: {}", identity::IDN_QUERY, reply)));
+    Ok(Peer { description, identity })
+}
+
+/// Turn an I/O error into a message for the user, explaining timeouts in plain words
+fn describe_error(error: std::io::Error) -> String {
+    match error.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => format!("No reply received within {:?}", TIMEOUT),
+        _ => error.to_string(),
+    }
+}
+
+/// Background task: send the command of `request` to the device and read as many lines as it expects
+pub async fn execute(connection: SharedConnection, request: Request) -> Result<Reply, String> {
+    lock(&connection)?.execute(&request)
+}
+
+/// Send `query` until the device reports an empty error queue and return the decoded errors
+fn drain_errors(connection: &mut DeviceConnection, query: &str) -> std::io::Result<Vec<String>> {
+    let mut errors = Vec::new();
+    for _ in 0..MAX_ERRORS {
+        connection.send(format!("{}\n", query.trim_end()).as_bytes())?;
+        let entry = connection.read_line()?;
+        match decode_error(&entry) {
+            Some((0, _)) => break,
+            Some((code, message)) => errors.push(format!("{} {}", code, message)),
+            None => {
+                // Not an error queue entry, so asking again will not help either
+                errors.push(entry);
+                break;
+            }
+        }
+    }
+    Ok(errors)
+}
+
+/// Decode an entry of the SCPI error queue like `-113,"Undefined header"` into its code and message
+/// Returns [`None`] if `entry` does not start with a numeric code.
+pub fn decode_error(entry: &str) -> Option<(i32, String)> {
+    let (code, message) = entry.split_once(',').unwrap_or((entry, ""));
+    let code = code.trim().parse().ok()?;
+    Some((code, message.trim().trim_matches('"').to_string()))
+}
+
+/// Attempt to parse an `IP:port` string into a [`SocketAddr`]
+pub fn parse_ip(address: &str) -> Result<SocketAddr, String> {
+    SocketAddr::from_str(address).map_err(|_x| { "Invalid IP Address".to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread, io::{BufRead, BufReader, Write}};
+
+    #[test]
+    fn test_query_in_background() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            assert_eq!(line, "*IDN?\n");
+            (&stream).write_all(b"TEST,INSTRUMENT,0,1.0\n").unwrap();
+        });
+
+        let connection = SharedConnection::default();
+        let connected = futures::executor::block_on(connect(connection.clone(), ConnectionSettings::default(), address.to_string()));
+        assert_eq!(connected, Ok(format!("{} (raw socket)", address)));
+
+        let request = Request { command: "*IDN?\n".into(), reply_lines: 1, error_query: None };
+        let reply = futures::executor::block_on(execute(connection, request)).unwrap();
+        assert_eq!(reply.sent, 6);
+        assert_eq!(reply.response, Some(Response::Text("TEST,INSTRUMENT,0,1.0".into())));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_lines_are_buffered_between_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, ":OUTPut1 on\r\n");
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "*IDN?;*OPC?\r\n");
+            // Both replies arrive in a single segment
+            (&stream).write_all(b"TEST,INSTRUMENT,0,1.0\r\n1\r\n").unwrap();
+        });
+
+        let connection = SharedConnection::default();
+        let settings = ConnectionSettings { terminator: "\r\n".into(), ..Default::default() };
+        futures::executor::block_on(connect(connection.clone(), settings, address.to_string())).unwrap();
+
+        // A set-command returns immediately without waiting for a reply
+        let request = Request { command: ":OUTPut1 on\n".into(), reply_lines: 0, error_query: None };
+        let reply = futures::executor::block_on(execute(connection.clone(), request)).unwrap();
+        assert_eq!(reply.response, None);
+
+        let request = Request { command: "*IDN?;*OPC?\n".into(), reply_lines: 2, error_query: None };
+        let reply = futures::executor::block_on(execute(connection, request)).unwrap();
+        assert_eq!(reply.response, Some(Response::Text("TEST,INSTRUMENT,0,1.0\n1".into())));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_binary_blocks() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut upload = Vec::new();
+            reader.read_until(b'\n', &mut upload).unwrap();
+            // The block itself contains a newline, so read the remaining bytes of it
+            let mut rest = [0u8; 4];
+            std::io::Read::read_exact(&mut reader, &mut rest).unwrap();
+            upload.extend_from_slice(&rest);
+            assert_eq!(upload, b":DATA:DAC VOLATILE,#15\x00\n\x01\xFF\x80\n");
+            (&stream).write_all(b"#15\x00\n\x01\xFF\x80\n").unwrap();
+            (&stream).write_all(b"#0\x01\x02\n").unwrap();
+            (&stream).write_all(b":WAV:DATA #13ABC\n").unwrap();
+        });
+
+        let mut connection = DeviceConnection::default();
+        connection.connect(&ConnectionSettings::default(), &address.to_string()).unwrap();
+        connection.send_block(":DATA:DAC VOLATILE,", &[0x00, b'\n', 0x01, 0xFF, 0x80]).unwrap();
+        assert_eq!(connection.read_response(1).unwrap(), Response::Block(vec![0x00, b'\n', 0x01, 0xFF, 0x80]));
+        assert_eq!(connection.read_response(1).unwrap(), Response::Block(vec![0x01, 0x02]));
+        assert_eq!(connection.read_block().unwrap(), b"ABC");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_block_without_terminator() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            (&stream).write_all(b"#13XYZ").unwrap();
+            thread::sleep(TIMEOUT / 2);
+            (&stream).write_all(b"#12AB1\n").unwrap();
+            (&stream).write_all(b"#9999999999").unwrap();
+        });
+
+        let mut connection = DeviceConnection::default();
+        connection.connect(&ConnectionSettings::default(), &address.to_string()).unwrap();
+        let start = std::time::Instant::now();
+        assert_eq!(connection.read_response(1).unwrap(), Response::Block(b"XYZ".to_vec()));
+        assert!(start.elapsed() < TIMEOUT / 2);
+        // What follows a block is only skipped if it is the terminator
+        assert_eq!(connection.read_response(1).unwrap(), Response::Block(b"AB".to_vec()));
+        assert_eq!(connection.read_line().unwrap(), "1");
+        assert_eq!(connection.read_response(1).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_split_address() {
+        assert_eq!(TransportKind::RawSocket.split_address("10.10.2.8:5555"), ("10.10.2.8".into(), "5555".into()));
+        assert_eq!(TransportKind::Vxi11.split_address("10.10.2.8"), ("10.10.2.8".into(), "111".into()));
+        assert_eq!(TransportKind::HiSlip.split_address("10.10.2.8:4881/hislip0"), ("10.10.2.8".into(), "4881".into()));
+        assert_eq!(TransportKind::Serial.split_address("/dev/ttyUSB0"), ("/dev/ttyUSB0".into(), String::new()));
+    }
+
+    #[test]
+    fn test_decode_error() {
+        assert_eq!(decode_error("0,\"No error\""), Some((0, "No error".into())));
+        assert_eq!(decode_error("-113,\"Undefined header\""), Some((-113, "Undefined header".into())));
+        assert_eq!(decode_error("+0,\"No error\""), Some((0, "No error".into())));
+        assert_eq!(decode_error("ACME,DG1022Z"), None);
+    }
+
+    #[test]
+    fn test_identify() {
+        let config = crate::devices::Configuration::from("../config/config.toml").unwrap();
+        let address = crate::simulator::spawn(&config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let connection = SharedConnection::default();
+        let peer = futures::executor::block_on(connect_and_identify(connection, ConnectionSettings::default(), address.to_string())).unwrap();
+        assert_eq!(peer.description, format!("{} (raw socket)", address));
+        let identity = peer.identity.unwrap();
+        assert_eq!((identity.manufacturer.as_str(), identity.model.as_str()), ("instrument-app", "Simulator"));
+    }
+
+    #[test]
+    fn test_error_queue_is_drained() {
+        let config = crate::devices::Configuration::from("../config/config.toml").unwrap();
+        let address = crate::simulator::spawn(&config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let connection = SharedConnection::default();
+        futures::executor::block_on(connect(connection.clone(), ConnectionSettings::default(), address.to_string())).unwrap();
+
+        // Two bad commands in one message queue two errors
+        let error_query = Some(":SYSTem:ERRor?".to_string());
+        let request = Request { command: ":BOGus;:SOURce9:FREQ 1\n".into(), reply_lines: 0, error_query: error_query.clone() };
+        let reply = futures::executor::block_on(execute(connection.clone(), request)).unwrap();
+        assert_eq!(reply.errors, vec!["-113 Undefined header", "-113 Undefined header"]);
+
+        let request = Request { command: ":SOURce1:FREQ 1;:SOURce1:FREQ?\n".into(), reply_lines: 1, error_query };
+        let reply = futures::executor::block_on(execute(connection, request)).unwrap();
+        assert_eq!(reply.response, Some(Response::Text("1".into())));
+        assert!(reply.errors.is_empty());
+    }
+}
@@ -0,0 +1,61 @@
+//! Raw SCPI socket transport, as offered by most LXI instruments on port 5555
+use super::{parse_ip, skip_buffered, Transport, TIMEOUT};
+use std::{
+    net::TcpStream,
+    io::{BufRead, BufReader, Read, Write},
+    time::Duration};
+
+/// [`Transport`] sending SCPI strings over a plain [`TcpStream`]
+#[derive(Default)]
+pub struct RawSocket {
+    /// Buffered reader wrapping the stream, so no bytes are lost between reads
+    reader: Option<BufReader<TcpStream>>,
+}
+
+impl RawSocket {
+    /// Return the underlying stream or a `NotConnected` error
+    fn stream(&mut self) -> std::io::Result<&mut BufReader<TcpStream>> {
+        self.reader.as_mut().ok_or_else(|| std::io::ErrorKind::NotConnected.into())
+    }
+}
+
+impl Transport for RawSocket {
+    fn connect(&mut self, address: &str) -> std::io::Result<()> {
+        self.reader = None;
+        let address = parse_ip(address).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        self.reader = Some(BufReader::new(stream));
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let stream = self.stream()?.get_mut();
+        stream.write_all(data)?;
+        Ok(data.len())
+    }
+
+    fn read_until(&mut self, terminator: u8, buffer: &mut Vec<u8>) -> std::io::Result<usize> {
+        self.stream()?.read_until(terminator, buffer)
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> std::io::Result<()> {
+        self.stream()?.read_exact(buffer)
+    }
+
+    fn skip(&mut self, expected: &[u8], timeout: Duration) -> std::io::Result<bool> {
+        skip_buffered(self.stream()?, expected, timeout, |reader, timeout| reader.get_ref().set_read_timeout(Some(timeout)))
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        match self.reader.take() {
+            Some(reader) => reader.get_ref().shutdown(std::net::Shutdown::Both),
+            None => Ok(()),
+        }
+    }
+
+    fn peer(&self) -> std::io::Result<String> {
+        let reader = self.reader.as_ref().ok_or(std::io::ErrorKind::NotConnected)?;
+        Ok(format!("{} (raw socket)", reader.get_ref().peer_addr()?))
+    }
+}
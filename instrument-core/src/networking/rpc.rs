@@ -0,0 +1,260 @@
+//! Minimal ONC RPC client over TCP (RFC 5531) with XDR encoding (RFC 4506), as used by VXI-11
+use std::{
+    net::{SocketAddr, TcpStream},
+    io::{Read, Write},
+    time::Duration};
+
+/// Program number of the portmapper
+pub const PORTMAPPER_PROGRAM: u32 = 100000;
+/// Version of the portmapper protocol
+pub const PORTMAPPER_VERSION: u32 = 2;
+/// Procedure of the portmapper that looks up the port of a program
+pub const PORTMAPPER_GETPORT: u32 = 3;
+/// Protocol number of TCP as used in portmapper requests
+const IPPROTO_TCP: u32 = 6;
+
+/// Flag in the record marking header that marks the last fragment of a record
+const LAST_FRAGMENT: u32 = 0x8000_0000;
+/// Largest record accepted, well above the 64 KiB requested by each VXI-11 read
+const MAXIMUM_RECORD_SIZE: usize = 1 << 20;
+
+/// Shorthand for creating an [`std::io::Error`] of kind `InvalidData`
+pub fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Serializes values into an XDR byte stream
+#[derive(Default)]
+pub struct XdrWriter {
+    data: Vec<u8>,
+}
+
+impl XdrWriter {
+    /// Append an unsigned 32 bit integer
+    pub fn u32(mut self, value: u32) -> Self {
+        self.data.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    /// Append a signed 32 bit integer
+    pub fn i32(self, value: i32) -> Self {
+        self.u32(value as u32)
+    }
+
+    /// Append a boolean
+    pub fn bool(self, value: bool) -> Self {
+        self.u32(value as u32)
+    }
+
+    /// Append variable-length opaque data, padded to a multiple of four bytes
+    pub fn opaque(self, value: &[u8]) -> Self {
+        let mut writer = self.u32(value.len() as u32);
+        writer.data.extend_from_slice(value);
+        writer.data.resize((writer.data.len() + 3) & !3, 0);
+        writer
+    }
+
+    /// Append a string
+    pub fn string(self, value: &str) -> Self {
+        self.opaque(value.as_bytes())
+    }
+
+    /// Return the serialized bytes
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Deserializes values from an XDR byte stream
+pub struct XdrReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> XdrReader<'a> {
+    /// Read from the start of `data`
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Take the next `length` bytes
+    fn take(&mut self, length: usize) -> std::io::Result<&'a [u8]> {
+        if self.data.len() < length {
+            return Err(invalid_data("XDR data ends unexpectedly".into()));
+        }
+        let (head, tail) = self.data.split_at(length);
+        self.data = tail;
+        Ok(head)
+    }
+
+    /// Read an unsigned 32 bit integer
+    pub fn u32(&mut self) -> std::io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Read a signed 32 bit integer
+    pub fn i32(&mut self) -> std::io::Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+
+    /// Read a boolean
+    #[allow(dead_code)]
+    pub fn bool(&mut self) -> std::io::Result<bool> {
+        Ok(self.u32()? != 0)
+    }
+
+    /// Read variable-length opaque data and skip its padding
+    pub fn opaque(&mut self) -> std::io::Result<Vec<u8>> {
+        let length = self.u32()? as usize;
+        let value = self.take(length)?.to_vec();
+        self.take((4 - length % 4) % 4)?;
+        Ok(value)
+    }
+
+    /// Read a string
+    #[allow(dead_code)]
+    pub fn string(&mut self) -> std::io::Result<String> {
+        String::from_utf8(self.opaque()?).map_err(|e| invalid_data(e.to_string()))
+    }
+}
+
+/// Write `data` as a single record using record marking
+pub fn write_record(stream: &mut impl Write, data: &[u8]) -> std::io::Result<()> {
+    let mut record = (LAST_FRAGMENT | data.len() as u32).to_be_bytes().to_vec();
+    record.extend_from_slice(data);
+    stream.write_all(&record)
+}
+
+/// Read a complete record, joining all of its fragments
+pub fn read_record(stream: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let mut record = Vec::new();
+    loop {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header)?;
+        let header = u32::from_be_bytes(header);
+        let start = record.len();
+        let length = (header & !LAST_FRAGMENT) as usize;
+        if start + length > MAXIMUM_RECORD_SIZE {
+            return Err(invalid_data(format!("RPC record exceeds the maximum size of {} bytes", MAXIMUM_RECORD_SIZE)));
+        }
+        record.resize(start + length, 0);
+        stream.read_exact(&mut record[start..])?;
+        if header & LAST_FRAGMENT != 0 {
+            return Ok(record);
+        }
+    }
+}
+
+/// Client for one RPC program on a remote host
+pub struct RpcClient {
+    stream: TcpStream,
+    program: u32,
+    version: u32,
+    /// Transaction id of the last call
+    xid: u32,
+}
+
+impl RpcClient {
+    /// Connect to the `program` in the given `version` listening on `address`
+    pub fn connect(address: &SocketAddr, program: u32, version: u32, timeout: Duration) -> std::io::Result<Self> {
+        let stream = TcpStream::connect_timeout(address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_nodelay(true)?;
+        Ok(Self { stream, program, version, xid: std::process::id() })
+    }
+
+    /// Call `procedure` with XDR-encoded `arguments` and return the XDR-encoded results
+    pub fn call(&mut self, procedure: u32, arguments: &[u8]) -> std::io::Result<Vec<u8>> {
+        self.xid = self.xid.wrapping_add(1);
+        let mut message = XdrWriter::default()
+            .u32(self.xid)
+            .u32(0) // CALL
+            .u32(2) // RPC version
+            .u32(self.program)
+            .u32(self.version)
+            .u32(procedure)
+            .u32(0).u32(0) // AUTH_NONE credentials
+            .u32(0).u32(0) // AUTH_NONE verifier
+            .finish();
+        message.extend_from_slice(arguments);
+        write_record(&mut self.stream, &message)?;
+
+        // Skip replies to earlier calls that timed out on our side
+        loop {
+            let reply = read_record(&mut self.stream)?;
+            let mut reader = XdrReader::new(&reply);
+            if reader.u32()? != self.xid {
+                continue;
+            }
+            if reader.u32()? != 1 {
+                return Err(invalid_data("RPC message is not a reply".into()));
+            }
+            if reader.u32()? != 0 {
+                return Err(invalid_data("RPC call was denied".into()));
+            }
+            reader.u32()?; // verifier flavor
+            reader.opaque()?; // verifier body
+            return match reader.u32()? {
+                0 => Ok(reader.data.to_vec()),
+                1 => Err(invalid_data("RPC program unavailable".into())),
+                2 => Err(invalid_data("RPC program version mismatch".into())),
+                3 => Err(invalid_data("RPC procedure unavailable".into())),
+                4 => Err(invalid_data("RPC arguments could not be decoded".into())),
+                n => Err(invalid_data(format!("RPC call failed with status {}", n))),
+            };
+        }
+    }
+
+    /// Address of the remote host
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    /// Close the connection
+    pub fn shutdown(&self) -> std::io::Result<()> {
+        self.stream.shutdown(std::net::Shutdown::Both)
+    }
+}
+
+/// Ask the portmapper at `portmapper` for the TCP port of `program` in the given `version`
+pub fn get_port(portmapper: &SocketAddr, program: u32, version: u32, timeout: Duration) -> std::io::Result<u16> {
+    let mut client = RpcClient::connect(portmapper, PORTMAPPER_PROGRAM, PORTMAPPER_VERSION, timeout)?;
+    let arguments = XdrWriter::default().u32(program).u32(version).u32(IPPROTO_TCP).u32(0).finish();
+    let port = XdrReader::new(&client.call(PORTMAPPER_GETPORT, &arguments)?).u32()?;
+    client.shutdown().ok();
+    match port {
+        0 => Err(std::io::Error::new(std::io::ErrorKind::NotFound, "RPC program is not registered with the portmapper")),
+        port => Ok(port as u16),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records() {
+        let mut stream = Vec::new();
+        write_record(&mut stream, b"abcd").unwrap();
+        assert_eq!(read_record(&mut stream.as_slice()).unwrap(), b"abcd");
+
+        // Two fragments of a record
+        let mut stream = 2u32.to_be_bytes().to_vec();
+        stream.extend_from_slice(b"ab");
+        stream.extend_from_slice(&(LAST_FRAGMENT | 2).to_be_bytes());
+        stream.extend_from_slice(b"cd");
+        assert_eq!(read_record(&mut stream.as_slice()).unwrap(), b"abcd");
+
+        // A fragment claiming 2 GB is rejected before anything is allocated
+        let stream = (LAST_FRAGMENT | 0x7FFF_FFFF).to_be_bytes();
+        let error = read_record(&mut stream.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        // So are fragments adding up to more than the limit
+        let mut stream = Vec::new();
+        for _ in 0..2 {
+            stream.extend_from_slice(&(MAXIMUM_RECORD_SIZE as u32 / 2 + 1).to_be_bytes());
+            stream.resize(stream.len() + MAXIMUM_RECORD_SIZE / 2 + 1, 0);
+        }
+        assert_eq!(read_record(&mut stream.as_slice()).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+}
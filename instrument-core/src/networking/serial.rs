@@ -0,0 +1,164 @@
+//! Serial transport for instruments connected via RS-232 or a USB-serial adapter (virtual COM port)
+use super::{skip_buffered, ConnectionSettings, Transport, TIMEOUT};
+use serde::Deserialize;
+use std::{io::{BufRead, BufReader, Read, Write}, time::Duration};
+
+/// Parity bit of a serial frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+/// Flow control of a serial connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    #[default]
+    None,
+    /// XON/XOFF
+    Software,
+    /// RTS/CTS
+    Hardware,
+}
+
+/// [`Transport`] talking to a serial port
+pub struct Serial {
+    /// Framing of the connection
+    settings: ConnectionSettings,
+    /// Buffered reader wrapping the port, so no bytes are lost between reads
+    reader: Option<BufReader<Box<dyn serialport::SerialPort>>>,
+}
+
+impl Serial {
+    /// Create a serial transport that is configured with `settings` when connecting
+    pub fn new(settings: ConnectionSettings) -> Self {
+        Self { settings, reader: None }
+    }
+
+    /// Return the underlying port or a `NotConnected` error
+    fn port(&mut self) -> std::io::Result<&mut BufReader<Box<dyn serialport::SerialPort>>> {
+        self.reader.as_mut().ok_or_else(|| std::io::ErrorKind::NotConnected.into())
+    }
+}
+
+impl Transport for Serial {
+    fn connect(&mut self, address: &str) -> std::io::Result<()> {
+        self.reader = None;
+        let data_bits = match self.settings.data_bits {
+            5 => serialport::DataBits::Five,
+            6 => serialport::DataBits::Six,
+            7 => serialport::DataBits::Seven,
+            8 => serialport::DataBits::Eight,
+            n => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid number of data bits: {}", n))),
+        };
+        let stop_bits = match self.settings.stop_bits {
+            1 => serialport::StopBits::One,
+            2 => serialport::StopBits::Two,
+            n => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid number of stop bits: {}", n))),
+        };
+        let parity = match self.settings.parity {
+            Parity::None => serialport::Parity::None,
+            Parity::Odd => serialport::Parity::Odd,
+            Parity::Even => serialport::Parity::Even,
+        };
+        let flow_control = match self.settings.flow_control {
+            FlowControl::None => serialport::FlowControl::None,
+            FlowControl::Software => serialport::FlowControl::Software,
+            FlowControl::Hardware => serialport::FlowControl::Hardware,
+        };
+        let port = serialport::new(address, self.settings.baud_rate)
+            .data_bits(data_bits)
+            .stop_bits(stop_bits)
+            .parity(parity)
+            .flow_control(flow_control)
+            .timeout(TIMEOUT)
+            .open()?;
+        self.reader = Some(BufReader::new(port));
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let port = self.port()?.get_mut();
+        port.write_all(data)?;
+        port.flush()?;
+        Ok(data.len())
+    }
+
+    fn read_until(&mut self, terminator: u8, buffer: &mut Vec<u8>) -> std::io::Result<usize> {
+        self.port()?.read_until(terminator, buffer)
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> std::io::Result<()> {
+        self.port()?.read_exact(buffer)
+    }
+
+    fn skip(&mut self, expected: &[u8], timeout: Duration) -> std::io::Result<bool> {
+        skip_buffered(self.port()?, expected, timeout, |reader, timeout| Ok(reader.get_mut().set_timeout(timeout)?))
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        self.reader = None;
+        Ok(())
+    }
+
+    fn peer(&self) -> std::io::Result<String> {
+        let reader = self.reader.as_ref().ok_or(std::io::ErrorKind::NotConnected)?;
+        let settings = &self.settings;
+        Ok(format!("{} ({} baud, {}{}{})",
+                   reader.get_ref().name().unwrap_or_default(),
+                   settings.baud_rate,
+                   settings.data_bits,
+                   match settings.parity { Parity::None => 'N', Parity::Odd => 'O', Parity::Even => 'E' },
+                   settings.stop_bits))
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        let port = self.port()?;
+        // Drop whatever has been buffered locally as well as in the driver
+        let buffered = port.buffer().len();
+        port.consume(buffered);
+        Ok(port.get_ref().clear(serialport::ClearBuffer::All)?)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::networking::{DeviceConnection, TransportKind};
+    use serialport::SerialPort;
+
+    #[test]
+    fn test_serial_pseudo_terminal() {
+        // Only keep the master side open, the transport opens the slave side by name
+        let (mut master, slave) = serialport::TTYPort::pair().unwrap();
+        let name = slave.name().unwrap();
+        drop(slave);
+        master.set_timeout(TIMEOUT).unwrap();
+
+        let settings = ConnectionSettings {
+            transport: TransportKind::Serial,
+            terminator: "\r\n".into(),
+            baud_rate: 115200,
+            ..Default::default()
+        };
+        let mut connection = DeviceConnection::default();
+        connection.connect(&settings, &name).unwrap();
+        assert!(connection.peer().unwrap().ends_with("(115200 baud, 8N1)"));
+
+        // Newlines are sent as the configured terminator
+        connection.send(b"*IDN?\n").unwrap();
+        let mut request = [0u8; 7];
+        master.read_exact(&mut request).unwrap();
+        assert_eq!(&request, b"*IDN?\r\n");
+
+        // Replies are read up to the complete terminator, a lone carriage return is kept
+        master.write_all(b"ACME,A\rB,0,1.0\r\n1\r\n2\r\n").unwrap();
+        assert_eq!(connection.read_line().unwrap(), "ACME,A\rB,0,1.0");
+        assert_eq!(connection.read_lines(2).unwrap(), "1\n2");
+        connection.close().unwrap();
+    }
+}
@@ -0,0 +1,360 @@
+//! VXI-11 transport, talking to the instrument's core channel via ONC RPC
+use super::{parse_ip, Transport, TIMEOUT, rpc::{self, RpcClient, XdrReader, XdrWriter}};
+use std::time::Duration;
+
+/// Program number of the VXI-11 core channel
+pub const DEVICE_CORE: u32 = 0x0607AF;
+/// Version of the VXI-11 core channel
+pub const DEVICE_CORE_VERSION: u32 = 1;
+
+/// Core channel procedure opening a link to a device
+pub const CREATE_LINK: u32 = 10;
+/// Core channel procedure writing data to a device
+pub const DEVICE_WRITE: u32 = 11;
+/// Core channel procedure reading data from a device
+pub const DEVICE_READ: u32 = 12;
+/// Core channel procedure reading the status byte of a device
+pub const DEVICE_READSTB: u32 = 13;
+/// Core channel procedure sending a device clear
+pub const DEVICE_CLEAR: u32 = 15;
+/// Core channel procedure closing a link
+pub const DESTROY_LINK: u32 = 23;
+
+/// Flag marking the last block of a write
+const FLAG_END: i32 = 0x08;
+/// Flag enabling the termination character of a read
+const FLAG_TERMCHRSET: i32 = 0x80;
+
+/// Read ended because the device signalled the end of the message
+const REASON_END: i32 = 0x04;
+
+/// Largest number of bytes requested by a single `device_read`
+const READ_SIZE: u32 = 0x10000;
+
+/// Name of the device on the instrument if none is given in the address
+pub const DEFAULT_DEVICE: &str = "inst0";
+
+/// Turn a VXI-11 `Device_ErrorCode` into an [`std::io::Error`]
+fn check_error(code: i32) -> std::io::Result<()> {
+    use std::io::ErrorKind::*;
+    let (kind, message) = match code {
+        0 => return Ok(()),
+        1 => (InvalidInput, "syntax error"),
+        3 => (NotFound, "device not accessible"),
+        4 => (NotConnected, "invalid link identifier"),
+        5 => (InvalidInput, "parameter error"),
+        6 => (NotConnected, "channel not established"),
+        8 => (Unsupported, "operation not supported"),
+        9 => (OutOfMemory, "out of resources"),
+        11 => (PermissionDenied, "device locked by another link"),
+        12 => (PermissionDenied, "no lock held by this link"),
+        15 => (TimedOut, "I/O timeout"),
+        17 => (Other, "I/O error"),
+        21 => (InvalidInput, "invalid address"),
+        23 => (Interrupted, "abort"),
+        29 => (AlreadyExists, "channel already established"),
+        _ => (Other, "unknown error"),
+    };
+    Err(std::io::Error::new(kind, format!("VXI-11 error {}: {}", code, message)))
+}
+
+/// Split a VXI-11 address of the form `IP:PORT[/DEVICE]` into the portmapper address and device name
+fn split_address(address: &str) -> std::io::Result<(std::net::SocketAddr, &str)> {
+    let (host, device) = address.split_once('/').unwrap_or((address, DEFAULT_DEVICE));
+    let host = parse_ip(host).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    Ok((host, device))
+}
+
+/// Established link to a device
+struct Link {
+    client: RpcClient,
+    /// Link id assigned by the instrument
+    id: i32,
+    /// Largest block the instrument accepts in a single `device_write`
+    max_recv_size: usize,
+    /// Name of the device on the instrument, e.g. `inst0`
+    device: String,
+}
+
+/// [`Transport`] using the VXI-11 core channel, with the portmapper being asked for its port
+#[derive(Default)]
+pub struct Vxi11 {
+    link: Option<Link>,
+    /// Bytes that have been read from the device but not yet handed out
+    pending: Vec<u8>,
+    /// Whether the last read ended with the end of the message
+    message_end: bool,
+}
+
+impl Vxi11 {
+    /// Return the established link or a `NotConnected` error
+    fn link(&mut self) -> std::io::Result<&mut Link> {
+        self.link.as_mut().ok_or_else(|| std::io::ErrorKind::NotConnected.into())
+    }
+
+    /// Parameters shared by `device_readstb`, `device_clear` and others
+    fn generic_parameters(id: i32) -> Vec<u8> {
+        let timeout = TIMEOUT.as_millis() as u32;
+        XdrWriter::default().i32(id).i32(0).u32(timeout).u32(timeout).finish()
+    }
+
+    /// Call `device_read` once and append the data to `pending`, return the reason the read ended
+    fn device_read(&mut self, terminator: Option<u8>) -> std::io::Result<i32> {
+        let link = self.link()?;
+        let timeout = TIMEOUT.as_millis() as u32;
+        let (flags, terminator) = match terminator {
+            Some(t) => (FLAG_TERMCHRSET, t as i32),
+            None => (0, 0),
+        };
+        let parameters = XdrWriter::default()
+            .i32(link.id).u32(READ_SIZE).u32(timeout).u32(timeout).i32(flags).i32(terminator)
+            .finish();
+        let response = link.client.call(DEVICE_READ, &parameters)?;
+        let mut reader = XdrReader::new(&response);
+        check_error(reader.i32()?)?;
+        let reason = reader.i32()?;
+        self.pending.extend(reader.opaque()?);
+        self.message_end = reason & REASON_END != 0;
+        Ok(reason)
+    }
+
+    /// Move the first `count` pending bytes into `buffer`
+    fn take_pending(&mut self, count: usize, buffer: &mut Vec<u8>) -> usize {
+        buffer.extend(self.pending.drain(..count));
+        if self.pending.is_empty() {
+            self.message_end = false;
+        }
+        count
+    }
+}
+
+impl Transport for Vxi11 {
+    fn connect(&mut self, address: &str) -> std::io::Result<()> {
+        // The old link may be dead already, which is when reconnecting is needed most
+        let _ = self.close();
+        let (portmapper, device) = split_address(address)?;
+        let port = rpc::get_port(&portmapper, DEVICE_CORE, DEVICE_CORE_VERSION, TIMEOUT)?;
+
+        // Leave some headroom so the instrument reports its I/O timeout before the RPC call times out
+        let core = std::net::SocketAddr::new(portmapper.ip(), port);
+        let mut client = RpcClient::connect(&core, DEVICE_CORE, DEVICE_CORE_VERSION, TIMEOUT * 2)?;
+        let parameters = XdrWriter::default()
+            .i32(std::process::id() as i32).bool(false).u32(0).string(device)
+            .finish();
+        let response = client.call(CREATE_LINK, &parameters)?;
+        let mut reader = XdrReader::new(&response);
+        check_error(reader.i32()?)?;
+        let id = reader.i32()?;
+        let _abort_port = reader.u32()?;
+        let max_recv_size = reader.u32()?.max(1) as usize;
+        self.link = Some(Link { client, id, max_recv_size, device: device.into() });
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let link = self.link()?;
+        let timeout = TIMEOUT.as_millis() as u32;
+        let mut rest = data;
+        while !rest.is_empty() {
+            let chunk = &rest[..rest.len().min(link.max_recv_size)];
+            let flags = if chunk.len() == rest.len() { FLAG_END } else { 0 };
+            let parameters = XdrWriter::default()
+                .i32(link.id).u32(timeout).u32(timeout).i32(flags).opaque(chunk)
+                .finish();
+            let response = link.client.call(DEVICE_WRITE, &parameters)?;
+            let mut reader = XdrReader::new(&response);
+            check_error(reader.i32()?)?;
+            // The device may take only part of the chunk, the rest is sent again
+            let size = reader.u32()? as usize;
+            if size == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "VXI-11 device did not accept any data"));
+            }
+            rest = &rest[size.min(chunk.len())..];
+        }
+        Ok(data.len())
+    }
+
+    fn read_until(&mut self, terminator: u8, buffer: &mut Vec<u8>) -> std::io::Result<usize> {
+        loop {
+            if let Some(position) = self.pending.iter().position(|b| *b == terminator) {
+                return Ok(self.take_pending(position + 1, buffer));
+            }
+            // A complete message without terminator is returned as a whole
+            if self.message_end {
+                let count = self.pending.len();
+                return Ok(self.take_pending(count, buffer));
+            }
+            self.device_read(Some(terminator))?;
+        }
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> std::io::Result<()> {
+        while self.pending.len() < buffer.len() {
+            if self.message_end {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            self.device_read(None)?;
+        }
+        let mut data = Vec::with_capacity(buffer.len());
+        self.take_pending(buffer.len(), &mut data);
+        buffer.copy_from_slice(&data);
+        Ok(())
+    }
+
+    fn read_message(&mut self, buffer: &mut Vec<u8>) -> std::io::Result<usize> {
+        while !self.message_end {
+            self.device_read(None)?;
+        }
+        let count = self.pending.len();
+        Ok(self.take_pending(count, buffer))
+    }
+
+    fn skip(&mut self, expected: &[u8], _timeout: Duration) -> std::io::Result<bool> {
+        // Whatever follows a block comes with it in the same message, which has been read already
+        if !self.pending.starts_with(expected) {
+            return Ok(false);
+        }
+        self.take_pending(expected.len(), &mut Vec::new());
+        Ok(true)
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        self.pending.clear();
+        self.message_end = false;
+        match self.link.take() {
+            Some(mut link) => {
+                let result = link.client.call(DESTROY_LINK, &XdrWriter::default().i32(link.id).finish())
+                    .and_then(|response| check_error(XdrReader::new(&response).i32()?));
+                link.client.shutdown().ok();
+                result
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn peer(&self) -> std::io::Result<String> {
+        let link = self.link.as_ref().ok_or(std::io::ErrorKind::NotConnected)?;
+        Ok(format!("{} (VXI-11 {})", link.client.peer_addr()?.ip(), link.device))
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        self.pending.clear();
+        self.message_end = false;
+        let link = self.link()?;
+        let response = link.client.call(DEVICE_CLEAR, &Self::generic_parameters(link.id))?;
+        check_error(XdrReader::new(&response).i32()?)
+    }
+
+    fn read_status_byte(&mut self) -> std::io::Result<u8> {
+        let link = self.link()?;
+        let response = link.client.call(DEVICE_READSTB, &Self::generic_parameters(link.id))?;
+        let mut reader = XdrReader::new(&response);
+        check_error(reader.i32()?)?;
+        Ok(reader.u32()? as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::{TcpListener, TcpStream}, thread};
+
+    /// Answer RPC calls on `stream` with `handler` until the client disconnects
+    fn serve(mut stream: TcpStream, mut handler: impl FnMut(u32, &mut XdrReader) -> Vec<u8>) {
+        while let Ok(call) = rpc::read_record(&mut stream) {
+            let mut reader = XdrReader::new(&call);
+            let xid = reader.u32().unwrap();
+            for _ in 0..4 { reader.u32().unwrap(); } // message type, RPC version, program, version
+            let procedure = reader.u32().unwrap();
+            for _ in 0..2 { reader.u32().unwrap(); reader.opaque().unwrap(); } // credentials, verifier
+            let mut reply = XdrWriter::default().u32(xid).u32(1).u32(0).u32(0).u32(0).u32(0).finish();
+            reply.extend(handler(procedure, &mut reader));
+            rpc::write_record(&mut stream, &reply).unwrap();
+        }
+    }
+
+    /// Start a portmapper and a VXI-11 core channel stand-in that echoes written data, return the portmapper address
+    fn stand_in_server() -> std::net::SocketAddr {
+        let portmapper = TcpListener::bind("127.0.0.1:0").unwrap();
+        let core = TcpListener::bind("127.0.0.1:0").unwrap();
+        let core_port = core.local_addr().unwrap().port();
+        let address = portmapper.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (stream, _) = portmapper.accept().unwrap();
+            serve(stream, |procedure, reader| {
+                assert_eq!(procedure, rpc::PORTMAPPER_GETPORT);
+                assert_eq!(reader.u32().unwrap(), DEVICE_CORE);
+                XdrWriter::default().u32(core_port as u32).finish()
+            });
+        });
+        thread::spawn(move || {
+            let (stream, _) = core.accept().unwrap();
+            let mut output = Vec::new();
+            serve(stream, |procedure, reader| {
+                match procedure {
+                    CREATE_LINK => {
+                        reader.i32().unwrap();
+                        reader.bool().unwrap();
+                        reader.u32().unwrap();
+                        assert_eq!(reader.string().unwrap(), "inst0");
+                        // Tiny maximum size to exercise splitting of writes
+                        XdrWriter::default().i32(0).i32(7).u32(0).u32(4).finish()
+                    }
+                    DEVICE_WRITE => {
+                        assert_eq!(reader.i32().unwrap(), 7);
+                        reader.u32().unwrap();
+                        reader.u32().unwrap();
+                        reader.i32().unwrap();
+                        // Take at most three bytes to exercise sending the rest of a chunk again
+                        let data = reader.opaque().unwrap();
+                        let size = data.len().min(3);
+                        output.extend_from_slice(&data[..size]);
+                        XdrWriter::default().i32(0).u32(size as u32).finish()
+                    }
+                    DEVICE_READ => {
+                        // Hand out the echo in chunks of three bytes to exercise reassembly
+                        let count = output.len().min(3);
+                        let data: Vec<u8> = output.drain(..count).collect();
+                        let reason = if output.is_empty() { REASON_END } else { 0 };
+                        XdrWriter::default().i32(0).i32(reason).opaque(&data).finish()
+                    }
+                    DEVICE_READSTB => XdrWriter::default().i32(0).u32(0x40).finish(),
+                    DEVICE_CLEAR => {
+                        output.clear();
+                        XdrWriter::default().i32(0).finish()
+                    }
+                    DESTROY_LINK => XdrWriter::default().i32(0).finish(),
+                    _ => XdrWriter::default().i32(8).finish(),
+                }
+            });
+        });
+        address
+    }
+
+    #[test]
+    fn test_vxi11_round_trip() {
+        let address = stand_in_server();
+        let mut vxi11 = Vxi11::default();
+        vxi11.connect(&address.to_string()).unwrap();
+        assert_eq!(vxi11.peer().unwrap(), "127.0.0.1 (VXI-11 inst0)");
+
+        assert_eq!(vxi11.write(b"*IDN?\n").unwrap(), 6);
+        let mut line = Vec::new();
+        vxi11.read_until(b'\n', &mut line).unwrap();
+        assert_eq!(line, b"*IDN?\n");
+
+        vxi11.write(b"ABCDEFG").unwrap();
+        let mut block = [0u8; 2];
+        vxi11.read_exact(&mut block).unwrap();
+        assert_eq!(&block, b"AB");
+        let mut message = Vec::new();
+        vxi11.read_message(&mut message).unwrap();
+        assert_eq!(message, b"CDEFG");
+
+        assert_eq!(vxi11.read_status_byte().unwrap(), 0x40);
+        vxi11.clear().unwrap();
+        vxi11.close().unwrap();
+        assert!(vxi11.write(b"*RST\n").is_err());
+    }
+}
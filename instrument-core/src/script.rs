@@ -0,0 +1,181 @@
+//! Scripts driving a device with loops, math and decisions based on replies, written in [Rhai](https://rhai.rs)
+//!
+//! Besides the language itself, scripts can use:
+//! - `send(scpi)` to send a command, `query(scpi)` to send one and return the reply as text
+//! - `command(name, argument)` and `command(name, argument, channel)` for the SCPI text of a command of the config,
+//!   `command_query(name)` and `command_query(name, channel)` for the one reading its setting back
+//! - `commands()` for the names of all commands of the config
+//! - `number(text)` to read a number like `1.5E+03` or `10k`
+//! - `sleep(ms)` to pause, `log(text)` and `print(value)` to write a line to the output
+use crate::{devices::{command::build_request, number, Configuration}, networking::{self, Reply, Request, Response, SharedConnection}};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Position};
+use std::{collections::HashMap, convert::TryFrom, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
+
+/// Error returned by [`run`] if the script has been cancelled
+pub const CANCELLED: &str = "Cancelled";
+
+/// Longest time a sleeping script takes to notice it has been cancelled
+const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Device and config the functions available to scripts work with
+#[derive(Clone)]
+struct Context {
+    config: Arc<Configuration>,
+    connection: SharedConnection,
+    /// Query reading the error queue after each command, [`None`] to skip the check
+    error_query: Option<String>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Context {
+    /// Send `scpi` and read `reply_lines` lines, device errors fail the script
+    fn execute(&self, scpi: &str, reply_lines: usize) -> ScriptResult<Reply> {
+        let command = scpi.trim_end();
+        let request = Request { command: format!("{}\n", command), reply_lines, error_query: self.error_query.clone() };
+        let reply = networking::lock(&self.connection)?.execute(&request).map_err(|e| format!("{}: {}", command, e))?;
+        if !reply.errors.is_empty() {
+            return Err(format!("{}: The device reported {}", command, reply.errors.join(", ")).into());
+        }
+        Ok(reply)
+    }
+
+    /// Send `scpi` and return the reply as text
+    fn query(&self, scpi: &str) -> ScriptResult<String> {
+        match self.execute(scpi, 1)?.response {
+            Some(Response::Text(text)) => Ok(text),
+            Some(Response::Block(data)) => Err(format!("{}: Binary reply of {} bytes, scripts only read text", scpi.trim_end(), data.len()).into()),
+            None => Ok(String::new()),
+        }
+    }
+
+    /// SCPI text of the command of the config called `name`
+    fn command(&self, name: &str, argument: Option<&str>, channel: i64, query: bool) -> ScriptResult<String> {
+        let channel = u8::try_from(channel).map_err(|_| format!("Channel {} is not available", channel))?;
+        let request = build_request(&self.config, name, argument, channel, &HashMap::new(), query)?;
+        Ok(request.command.trim_end().to_string())
+    }
+
+    /// Pause for `ms` milliseconds, ending early if the script is cancelled
+    fn sleep(&self, ms: i64) -> ScriptResult<()> {
+        let end = Instant::now() + Duration::from_millis(ms.max(0) as u64);
+        loop {
+            if self.cancel.load(Ordering::Relaxed) {
+                return Err(EvalAltResult::ErrorTerminated(CANCELLED.into(), Position::NONE).into());
+            }
+            let left = end.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+            std::thread::sleep(left.min(CANCEL_POLL));
+        }
+    }
+}
+
+/// Run the script `source` with the commands of `config` against the device of `connection`
+/// The error queue is read with `error_query` after each command if given. Lines written by the script are passed
+/// to `output`, setting `cancel` stops the script, which then returns [`CANCELLED`].
+pub fn run(
+    source: &str,
+    config: &Configuration,
+    connection: SharedConnection,
+    error_query: Option<String>,
+    cancel: Arc<AtomicBool>,
+    output: impl Fn(&str) + Send + Sync + 'static,
+) -> Result<(), String> {
+    let context = Context { config: Arc::new(config.clone()), connection, error_query, cancel };
+    let output = Arc::new(output);
+    let mut engine = Engine::new();
+
+    let print = output.clone();
+    engine.on_print(move |text| print(text));
+    let debug = output.clone();
+    engine.on_debug(move |text, _, _| debug(text));
+    engine.register_fn("log", move |text: &str| output(text));
+    let cancel = context.cancel.clone();
+    engine.on_progress(move |_| cancel.load(Ordering::Relaxed).then(|| CANCELLED.into()));
+
+    let c = context.clone();
+    engine.register_fn("send", move |scpi: &str| c.execute(scpi, 0).map(|_| ()));
+    let c = context.clone();
+    engine.register_fn("query", move |scpi: &str| c.query(scpi));
+    let c = context.clone();
+    engine.register_fn("sleep", move |ms: i64| c.sleep(ms));
+    let c = context.clone();
+    engine.register_fn("command", move |name: &str, argument: &str| c.command(name, Some(argument), 1, false));
+    let c = context.clone();
+    engine.register_fn("command", move |name: &str, argument: &str, channel: i64| c.command(name, Some(argument), channel, false));
+    let c = context.clone();
+    engine.register_fn("command_query", move |name: &str| c.command(name, None, 1, true));
+    let c = context.clone();
+    engine.register_fn("command_query", move |name: &str, channel: i64| c.command(name, None, channel, true));
+    let names: Array = context.config.commands.iter().map(|cmd| Dynamic::from(cmd.name.clone())).collect();
+    engine.register_fn("commands", move || names.clone());
+    engine.register_fn("number", |text: &str| -> ScriptResult<f64> { Ok(number::parse_number(text, None)?) });
+
+    engine.run(source).map_err(|e| match *e {
+        EvalAltResult::ErrorTerminated(..) => CANCELLED.to_string(),
+        e => e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::{ConnectionSettings, DeviceConnection};
+    use std::sync::Mutex;
+
+    /// Run `source` against a simulated device and return the result and the lines written by the script
+    fn run_simulated(source: &str, cancel: Arc<AtomicBool>) -> (Result<(), String>, Vec<String>) {
+        let config = Configuration::from("../config/config.toml").unwrap();
+        let address = crate::simulator::spawn(&config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut connection = DeviceConnection::default();
+        connection.connect(&ConnectionSettings::default(), &address.to_string()).unwrap();
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let output = lines.clone();
+        let error_query = Some(config.device.error_query.clone());
+        let result = run(source, &config, Arc::new(Mutex::new(connection)), error_query, cancel, move |line| {
+            output.lock().unwrap().push(line.to_string())
+        });
+        let lines = lines.lock().unwrap().clone();
+        (result, lines)
+    }
+
+    #[test]
+    fn test_run_script() {
+        let source = r#"
+            for channel in 1..=2 {
+                send(command("Frequency", `${channel * 1000}`, channel));
+            }
+            let frequency = number(query(command_query("Frequency", 2)));
+            if frequency != 2000.0 {
+                throw `unexpected frequency ${frequency}`;
+            }
+            log(`frequency ${frequency}`);
+            print(commands().contains("Apply Sine"));
+            sleep(10);
+        "#;
+        let (result, lines) = run_simulated(source, Arc::new(AtomicBool::new(false)));
+        assert_eq!(result, Ok(()));
+        assert_eq!(lines, vec!["frequency 2000.0", "true"]);
+
+        let (result, _) = run_simulated(r#"send(":BOGus:HEADer 1");"#, Arc::new(AtomicBool::new(false)));
+        assert!(result.unwrap_err().contains(":BOGus:HEADer 1: The device reported"));
+        let (result, _) = run_simulated(r#"command("Output", "maybe");"#, Arc::new(AtomicBool::new(false)));
+        assert!(result.unwrap_err().contains("\"maybe\" is no argument of Output"));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let (result, _) = run_simulated("loop { sleep(1000); }", cancel);
+        assert_eq!(result, Err(CANCELLED.to_string()));
+    }
+}
@@ -0,0 +1,299 @@
+//! SCPI instrument simulator that accepts the commands of a device [`Configuration`]
+//!
+//! Settings are remembered per channel so the matching queries return the last value set,
+//! unknown headers end up in the SCPI error queue.
+use crate::devices::Configuration;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{SocketAddr, TcpListener, TcpStream},
+    io::{BufRead, BufReader, Write},
+    sync::{Arc, Mutex},
+    thread};
+
+/// Maximum number of entries in the error queue, as recommended by SCPI-99
+const ERROR_QUEUE_SIZE: usize = 20;
+
+/// What a header from the config does
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Form {
+    /// Takes an argument that is stored, e.g. `:SOURce1:FREQ <TXT>`
+    Setting,
+    /// Stores the last node of the header as value of the rest, e.g. `:OUTPut1:POLarity:NORMal`
+    Choice(String),
+    /// Returns the stored value, e.g. `:SOURce1:FREQ?`
+    Query,
+    /// Does something without storing a value, e.g. `:SYSTem:BEEPer:IMMediate`
+    Event,
+}
+
+/// A header the config can produce
+#[derive(Debug, Clone)]
+struct Entry {
+    /// Nodes of the header as written in the config, e.g. `["SOURce1", "FREQ"]`
+    nodes: Vec<String>,
+    form: Form,
+}
+
+impl Entry {
+    /// Key under which the value of this header is stored
+    fn key(&self) -> String {
+        self.nodes.join(":")
+    }
+}
+
+/// Split a header into its nodes, ignoring leading and trailing colons
+fn split_nodes(header: &str) -> Vec<String> {
+    header.trim_matches(':').split(':').map(String::from).collect()
+}
+
+/// Split a node into its mnemonic and numeric suffix
+fn split_suffix(node: &str) -> (&str, &str) {
+    let position = node.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    node.split_at(position)
+}
+
+/// Whether the header node `input` matches the `pattern` node from the config in short or long form
+fn node_matches(pattern: &str, input: &str) -> bool {
+    let (pattern_name, pattern_suffix) = split_suffix(pattern);
+    let (input_name, input_suffix) = split_suffix(input);
+    // An omitted numeric suffix means 1
+    let suffix_matches = pattern_suffix.trim_start_matches('0').max("1") == input_suffix.trim_start_matches('0').max("1")
+        || pattern_suffix == input_suffix;
+    let short_form: String = pattern_name.chars().filter(|c| !c.is_lowercase()).collect();
+    suffix_matches && (input_name.eq_ignore_ascii_case(pattern_name) || input_name.eq_ignore_ascii_case(&short_form))
+}
+
+/// Whether all nodes of `input` match the `pattern` nodes
+fn nodes_match(pattern: &[String], input: &[String]) -> bool {
+    pattern.len() == input.len() && pattern.iter().zip(input).all(|(p, i)| node_matches(p, i))
+}
+
+/// State of a simulated instrument
+pub struct Simulator {
+    entries: Vec<Entry>,
+    /// Last value set per header key
+    state: HashMap<String, String>,
+    /// SCPI error queue, oldest error first
+    errors: VecDeque<String>,
+}
+
+impl Simulator {
+    /// Create a simulator accepting every command that can be built from `config`
+    pub fn from(config: &Configuration) -> Self {
+        let mut entries = Vec::new();
+        for command in &config.commands {
+            let channels: Vec<String> = if command.channel {
+                (1..=config.device.channels).map(|ch| ch.to_string()).collect()
+            } else {
+                vec![String::new()]
+            };
+            for channel in &channels {
+                let prefix = command.scpi.replace("<CH>", channel);
+                for value in &command.values {
+                    let full = format!("{}{}", prefix, value);
+                    let mut parts = full.trim().splitn(2, char::is_whitespace);
+                    let header = parts.next().unwrap_or_default();
+                    let argument = parts.next().unwrap_or_default().trim();
+                    if header.is_empty() {
+                        continue;
+                    }
+                    let entry = if let Some(query) = header.strip_suffix('?') {
+                        Entry { nodes: split_nodes(query), form: Form::Query }
+                    } else if !argument.is_empty() {
+                        Entry { nodes: split_nodes(header), form: Form::Setting }
+                    } else if prefix.ends_with(':') && !value.is_empty() && !value.contains(':') {
+                        Entry { nodes: split_nodes(prefix.trim_end_matches(':')), form: Form::Choice(value.trim().into()) }
+                    } else {
+                        Entry { nodes: split_nodes(header), form: Form::Event }
+                    };
+                    entries.push(entry);
+                }
+            }
+        }
+        Self { entries, state: HashMap::new(), errors: VecDeque::new() }
+    }
+
+    /// Add an error to the queue, replacing the last one with a overflow error if the queue is full
+    fn push_error(&mut self, error: &str) {
+        if self.errors.len() >= ERROR_QUEUE_SIZE {
+            self.errors.pop_back();
+            self.errors.push_back("-350,\"Queue overflow\"".into());
+        } else {
+            self.errors.push_back(error.into());
+        }
+    }
+
+    /// Handle a line of one or more commands separated by `;`, return the replies to all queries in it
+    pub fn handle(&mut self, line: &str) -> Option<String> {
+        let replies: Vec<String> = line.split(';')
+            .map(str::trim)
+            .filter(|command| !command.is_empty())
+            .filter_map(|command| self.handle_command(command))
+            .collect();
+        if replies.is_empty() { None } else { Some(replies.join(";")) }
+    }
+
+    /// Handle a single command, return the reply if it is a query
+    fn handle_command(&mut self, command: &str) -> Option<String> {
+        let mut parts = command.splitn(2, char::is_whitespace);
+        let header = parts.next().unwrap_or_default();
+        let argument = parts.next().unwrap_or_default().trim();
+
+        // IEEE 488.2 common commands and the SCPI error queue are always available
+        match header.to_ascii_uppercase().as_str() {
+            "*IDN?" => return Some(format!("instrument-app,Simulator,0,{}", env!("CARGO_PKG_VERSION"))),
+            "*OPC?" => return Some("1".into()),
+            "*ESR?" | "*STB?" => return Some("0".into()),
+            "*CLS" => {
+                self.errors.clear();
+                return None;
+            }
+            "*RST" => {
+                self.state.clear();
+                return None;
+            }
+            "*OPC" | "*WAI" | "*ESE" | "*SRE" => return None,
+            _ => {}
+        }
+        let error_queue = split_nodes(":SYSTem:ERRor:NEXT");
+        let nodes = split_nodes(header.trim_end_matches('?'));
+        if header.ends_with('?') && (nodes_match(&error_queue[..2], &nodes) || nodes_match(&error_queue, &nodes)) {
+            return Some(self.errors.pop_front().unwrap_or_else(|| "0,\"No error\"".into()));
+        }
+
+        if header.ends_with('?') {
+            let key = self.entries.iter()
+                .find(|entry| entry.form != Form::Event && nodes_match(&entry.nodes, &nodes))
+                .map(Entry::key);
+            return match key {
+                Some(key) => Some(self.state.get(&key).cloned().unwrap_or_else(|| "0".into())),
+                None => {
+                    self.push_error("-113,\"Undefined header\"");
+                    None
+                }
+            };
+        }
+
+        let matching = self.entries.iter().find(|entry| match &entry.form {
+            Form::Setting | Form::Event => nodes_match(&entry.nodes, &nodes),
+            Form::Choice(choice) => nodes.split_last()
+                .is_some_and(|(last, rest)| node_matches(choice, last) && nodes_match(&entry.nodes, rest)),
+            Form::Query => false,
+        }).cloned();
+        match matching {
+            Some(Entry { form: Form::Setting, .. }) if argument.is_empty() => self.push_error("-109,\"Missing parameter\""),
+            Some(entry @ Entry { form: Form::Setting, .. }) => {
+                self.state.insert(entry.key(), argument.into());
+            }
+            Some(entry @ Entry { form: Form::Choice(_), .. }) => {
+                self.state.insert(entry.key(), nodes.last().cloned().unwrap_or_default());
+            }
+            Some(_) => {}
+            None => self.push_error("-113,\"Undefined header\""),
+        }
+        None
+    }
+}
+
+/// Answer the commands of one client until it disconnects
+fn serve_client(simulator: Arc<Mutex<Simulator>>, stream: TcpStream) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        let reply = simulator.lock()
+            .map_err(|_| std::io::Error::other("Simulator state is unavailable"))?
+            .handle(&line);
+        if let Some(reply) = reply {
+            writer.write_all(format!("{}\n", reply).as_bytes())?;
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Accept clients on `listener` forever, each one in its own thread
+fn serve(simulator: Simulator, listener: TcpListener) {
+    let simulator = Arc::new(Mutex::new(simulator));
+    for stream in listener.incoming().flatten() {
+        let simulator = simulator.clone();
+        thread::spawn(move || serve_client(simulator, stream));
+    }
+}
+
+/// Run a simulator for every profile in `configs`, the first one listening on `address`
+/// and the others on the following ports. Blocks forever.
+pub fn run(configs: &[Configuration], address: SocketAddr) -> std::io::Result<()> {
+    for (index, config) in configs.iter().enumerate() {
+        // Port 0 lets the system pick a free port for each profile
+        let port = match address.port() {
+            0 => Some(0),
+            port => port.checked_add(index as u16),
+        }.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "No port left for the next profile"))?;
+        let bound = spawn(config, SocketAddr::new(address.ip(), port))?;
+        println!("Simulating {} commands of {} on {}", config.commands.len(), config.device.name, bound);
+    }
+    loop {
+        thread::park();
+    }
+}
+
+/// Start a simulator for `config` in the background and return the address it listens on
+pub fn spawn(config: &Configuration, address: SocketAddr) -> std::io::Result<SocketAddr> {
+    let listener = TcpListener::bind(address)?;
+    let address = listener.local_addr()?;
+    let simulator = Simulator::from(config);
+    thread::spawn(move || serve(simulator, listener));
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::{ConnectionSettings, DeviceConnection, Response};
+
+    fn simulator() -> Simulator {
+        Simulator::from(&Configuration::from("../config/config.toml").unwrap())
+    }
+
+    #[test]
+    fn test_settings_are_remembered_per_channel() {
+        let mut simulator = simulator();
+        assert_eq!(simulator.handle(":SOURce1:FREQ 1000\n"), None);
+        assert_eq!(simulator.handle(":SOUR2:FREQ 250"), None);
+        assert_eq!(simulator.handle(":SOURce1:FREQ?").as_deref(), Some("1000"));
+        assert_eq!(simulator.handle(":sour2:freq?").as_deref(), Some("250"));
+
+        // Choices appended to the header and arguments after a space
+        simulator.handle("OUTPut2:POLarity:INVerted;:OUTPut1 on");
+        assert_eq!(simulator.handle("OUTP2:POL?;:OUTPut1?").as_deref(), Some("INVerted;on"));
+        simulator.handle(":OUTPut1:IMPedance 50;:OUTP2:IMP:INF");
+        assert_eq!(simulator.handle(":OUTP1:IMP?;:OUTP2:IMP?").as_deref(), Some("50;INF"));
+        assert_eq!(simulator.handle(":SYSTem:ERRor?").as_deref(), Some("0,\"No error\""));
+    }
+
+    #[test]
+    fn test_error_queue() {
+        let mut simulator = simulator();
+        simulator.handle(":SOURce3:FREQ 1000");
+        simulator.handle(":BOGus");
+        assert!(simulator.handle("*IDN?").unwrap().starts_with("instrument-app,Simulator"));
+        assert_eq!(simulator.handle(":SYST:ERR?").as_deref(), Some("-113,\"Undefined header\""));
+        assert_eq!(simulator.handle(":SYST:ERR?").as_deref(), Some("-113,\"Undefined header\""));
+        assert_eq!(simulator.handle(":SYST:ERR?").as_deref(), Some("0,\"No error\""));
+        simulator.handle(":BOGus");
+        simulator.handle("*CLS");
+        assert_eq!(simulator.handle(":SYSTem:ERRor:NEXT?").as_deref(), Some("0,\"No error\""));
+    }
+
+    #[test]
+    fn test_device_connection_against_simulator() {
+        let config = Configuration::from("../config/config.toml").unwrap();
+        let address = spawn(&config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut connection = DeviceConnection::default();
+        connection.connect(&ConnectionSettings::default(), &address.to_string()).unwrap();
+        connection.send(b":SOURce1:VOLTage:HIGH 2.5\n").unwrap();
+        connection.send(b":SOURce1:VOLTage:HIGH?\n").unwrap();
+        assert_eq!(connection.read_response(1).unwrap(), Response::Text("2.5".into()));
+    }
+}
@@ -0,0 +1,168 @@
+//! Command-line arguments of the app, and the config used last which is opened if none is given
+use crate::messages::ScreenType;
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+
+/// Control lab instruments over the network using SCPI commands
+#[derive(Debug, Clone, Default, Parser)]
+#[command(version)]
+pub struct Arguments {
+    /// Config file or directory of config files, the one used last is opened if none is given
+    #[arg(value_name = "CONFIG", conflicts_with = "config")]
+    config_file: Option<String>,
+    /// Config file or directory of config files, same as CONFIG
+    #[arg(long, short)]
+    config: Option<String>,
+    /// Address of the device of the first profile, replacing the one of the config
+    #[arg(long, short)]
+    pub address: Option<String>,
+    /// Connect to the device of the first profile on start, as with `autoconnect = true`
+    #[arg(long)]
+    pub connect: bool,
+    /// Screen shown on start
+    #[arg(long, value_enum)]
+    pub screen: Option<ScreenType>,
+    /// Scaling factor of the user interface, higher numbers zoom in
+    #[arg(long, default_value_t = 1.0, value_parser = parse_scale)]
+    pub scale: f64,
+    /// Size of the window as WIDTHxHEIGHT
+    #[arg(long, value_name = "WIDTHxHEIGHT", default_value = "800x600", value_parser = parse_window_size)]
+    pub window_size: (u32, u32),
+    /// Run a simulated instrument for every profile on ADDRESS instead of the GUI
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = "127.0.0.1:5555")]
+    pub simulate: Option<String>,
+    /// Send commands without the GUI
+    #[command(subcommand)]
+    pub action: Option<Action>,
+}
+
+/// Subcommands sending the commands of a config without the GUI, their arguments are parsed by [`crate::cli`]
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum Action {
+    /// Send a command of the config and print the reply
+    Send(ActionArguments),
+    /// Send a command of the config and print the reply, reading one even if the command is no query
+    Query(ActionArguments),
+    /// List the commands of a profile and their arguments
+    ListCommands(ActionArguments),
+    /// Run the send and query lines of a file in order
+    RunFile(ActionArguments),
+    /// Run a Rhai script driving the device
+    RunScript(ActionArguments),
+}
+
+/// Arguments of an [`Action`], passed on to [`crate::cli`] as they are
+#[derive(Debug, Clone, PartialEq, Eq, clap::Args)]
+#[command(disable_help_flag = true)]
+pub struct ActionArguments {
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub args: Vec<String>,
+}
+
+impl Action {
+    /// Name of the subcommand followed by its arguments, as taken by [`crate::cli::run`]
+    pub fn to_args(&self) -> Vec<String> {
+        let (name, arguments) = match self {
+            Action::Send(arguments) => ("send", arguments),
+            Action::Query(arguments) => ("query", arguments),
+            Action::ListCommands(arguments) => ("list-commands", arguments),
+            Action::RunFile(arguments) => ("run-file", arguments),
+            Action::RunScript(arguments) => ("run-script", arguments),
+        };
+        std::iter::once(name.to_string()).chain(arguments.args.iter().cloned()).collect()
+    }
+}
+
+impl Arguments {
+    /// The config given on the command line, if any
+    pub fn config(&self) -> Option<&str> {
+        self.config.as_deref().or(self.config_file.as_deref())
+    }
+}
+
+/// Parse a scaling factor, which must be a positive number
+fn parse_scale(scale: &str) -> Result<f64, String> {
+    match scale.parse::<f64>() {
+        Ok(scale) if scale > 0.0 && scale.is_finite() => Ok(scale),
+        _ => Err(format!("\"{}\" is not a positive number", scale)),
+    }
+}
+
+/// Parse a window size like `1024x768`
+fn parse_window_size(size: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("\"{}\" is not a size like 800x600", size);
+    let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (width.trim().parse(), height.trim().parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+/// File the path of the config used last is kept in, e.g. `~/.config/instrument-app/last-config`
+fn last_config_file() -> Option<PathBuf> {
+    dirs::config_dir().map(|directory| directory.join("instrument-app").join("last-config"))
+}
+
+/// Path of the config used last, if it still exists
+pub fn last_config() -> Option<String> {
+    let path = std::fs::read_to_string(last_config_file()?).ok()?;
+    let path = path.trim();
+    Path::new(path).exists().then(|| path.to_string())
+}
+
+/// Remember `path` as the config used last
+pub fn remember_config(path: &str) {
+    let file = match last_config_file() {
+        Some(file) => file,
+        None => return,
+    };
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let result = file.parent().map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::write(&file, path.to_string_lossy().as_bytes()));
+    if let Err(e) = result {
+        eprintln!("Error: The config used last could not be remembered: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Arguments, clap::Error> {
+        Arguments::try_parse_from(std::iter::once("instrument-app").chain(line.split_whitespace()))
+    }
+
+    #[test]
+    fn test_parse() {
+        let arguments = parse("--config bench.toml --address 10.0.0.5:5025 --connect --screen terminal --scale 1.5 --window-size 1024x768").unwrap();
+        assert_eq!(arguments.config(), Some("bench.toml"));
+        assert_eq!(arguments.address.as_deref(), Some("10.0.0.5:5025"));
+        assert!(arguments.connect);
+        assert_eq!(arguments.screen, Some(ScreenType::Terminal));
+        assert_eq!(arguments.scale, 1.5);
+        assert_eq!(arguments.window_size, (1024, 768));
+
+        let arguments = parse("config.toml").unwrap();
+        assert_eq!(arguments.config(), Some("config.toml"));
+        assert_eq!((arguments.scale, arguments.window_size, arguments.screen), (1.0, (800, 600), None));
+        assert_eq!(parse("").unwrap().config(), None);
+        assert!(parse("--scale 0").is_err());
+        assert!(parse("--window-size 800").is_err());
+        assert!(parse("--screen bogus").is_err());
+        assert!(parse("a.toml --config b.toml").is_err());
+    }
+
+    #[test]
+    fn test_subcommands() {
+        let arguments = parse("config.toml --simulate").unwrap();
+        assert_eq!(arguments.simulate.as_deref(), Some("127.0.0.1:5555"));
+        assert_eq!(parse("config.toml --simulate 0.0.0.0:5025").unwrap().simulate.as_deref(), Some("0.0.0.0:5025"));
+
+        // Options of the subcommands are left to them
+        let arguments = parse("config.toml send Frequency 1k --channel 2 FREQ=1").unwrap();
+        assert_eq!(arguments.config(), Some("config.toml"));
+        assert_eq!(arguments.action.unwrap().to_args(), vec!["send", "Frequency", "1k", "--channel", "2", "FREQ=1"]);
+        let arguments = parse("--config config.toml list-commands --profile Generator").unwrap();
+        assert_eq!(arguments.action.unwrap().to_args(), vec!["list-commands", "--profile", "Generator"]);
+    }
+}
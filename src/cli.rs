@@ -0,0 +1,302 @@
+//! Subcommands sending the commands of a config from the command line or a file, without starting the GUI
+use instrument_core::{devices::{self, command::{self, build_request}}, networking::{Request, Response}, script, DeviceConnection};
+use std::{collections::HashMap, io::Write, sync::{atomic::AtomicBool, mpsc, Arc, Mutex}};
+
+/// Help on the subcommands, printed if they are used wrongly
+const USAGE: &str = "Usage:
+    instrument-app <CONFIG> send <COMMAND> [ARGUMENT] [OPTIONS] [NAME=VALUE...]
+    instrument-app <CONFIG> query <COMMAND> [ARGUMENT] [OPTIONS] [NAME=VALUE...]
+    instrument-app <CONFIG> list-commands [--profile NAME]
+    instrument-app <CONFIG> run-file <FILE> [OPTIONS]
+    instrument-app <CONFIG> run-script <FILE> [OPTIONS]
+Options:
+    --profile NAME     Device profile to use, defaults to the first one
+    --channel N        Channel of per-channel commands, defaults to 1
+    --address ADDRESS  Address of the device, defaults to `address` of the config
+    --no-check         Do not read the error queue after each command";
+
+/// Options and arguments of a subcommand
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Arguments {
+    /// Name of the subcommand followed by its positional arguments
+    words: Vec<String>,
+    /// Inputs for placeholders like `<FREQ>`, given as `FREQ=1k`
+    inputs: HashMap<String, String>,
+    profile: Option<String>,
+    channel: Option<u8>,
+    address: Option<String>,
+    /// Do not read the error queue after each command?
+    no_check: bool,
+}
+
+/// Split `args` into the options and positional arguments of a subcommand
+fn parse(args: &[String]) -> Result<Arguments, String> {
+    let mut arguments = Arguments::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |option: &str| args.next().cloned().ok_or_else(|| format!("{} requires a value", option));
+        match arg.as_str() {
+            "--profile" => arguments.profile = Some(value(arg)?),
+            "--address" => arguments.address = Some(value(arg)?),
+            "--channel" => {
+                let channel = value(arg)?;
+                arguments.channel = Some(channel.parse().map_err(|_| format!("Invalid channel \"{}\"", channel))?);
+            }
+            "--no-check" => arguments.no_check = true,
+            option if option.starts_with("--") => return Err(format!("Unknown option {}", option)),
+            word => match word.split_once('=') {
+                Some((name, value)) if command::placeholders(&format!("<{}>", name)) == [name] => {
+                    arguments.inputs.insert(name.to_string(), value.to_string());
+                }
+                _ => arguments.words.push(word.to_string()),
+            },
+        }
+    }
+    Ok(arguments)
+}
+
+/// Split a line of a command file into words at whitespace, text in single or double quotes is kept together
+/// Double quotes inside single quotes are kept, e.g. for SCPI strings like `'"Ready?"'`.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err("Missing closing quote".into());
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Run the subcommand in `args` with the profiles loaded from `config` and return the exit code of the program
+/// Replies go to stdout, errors to stderr.
+pub fn run(config: &str, args: &[String]) -> i32 {
+    let stdout = std::io::stdout();
+    match run_to(config, args, &mut stdout.lock()) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+/// Run the subcommand in `args` like [`run`], writing replies to `out`
+fn run_to(config: &str, args: &[String], out: &mut impl Write) -> Result<(), String> {
+    let arguments = parse(args).map_err(|e| format!("{}\n{}", e, USAGE))?;
+    let configs = devices::Configuration::load_all(config)
+        .map_err(|diagnostics| diagnostics.iter().map(ToString::to_string).collect::<Vec<String>>().join("\n"))?;
+    let config = match &arguments.profile {
+        Some(name) => configs.into_iter()
+            .find(|config| config.device.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("No profile named \"{}\" in {}", name, config))?,
+        None => configs.into_iter().next().ok_or_else(|| format!("No profile found in {}", config))?,
+    };
+    let mut session = Session { config, address: arguments.address.clone(), connection: DeviceConnection::default() };
+
+    match arguments.words.first().map(String::as_str) {
+        Some("send") => session.execute(&arguments, false, out),
+        Some("query") => session.execute(&arguments, true, out),
+        Some("list-commands") => session.list_commands(out),
+        Some("run-file") => match arguments.words.get(1) {
+            Some(file) => session.run_file(file, &arguments, out),
+            None => Err(format!("run-file requires a file\n{}", USAGE)),
+        },
+        Some("run-script") => match arguments.words.get(1) {
+            Some(file) => session.run_script(file, &arguments, out),
+            None => Err(format!("run-script requires a file\n{}", USAGE)),
+        },
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Profile the subcommands work with and its connection, which is opened for the first command sent
+struct Session {
+    config: devices::Configuration,
+    /// Address given on the command line, overrides the one of the config
+    address: Option<String>,
+    connection: DeviceConnection,
+}
+
+impl Session {
+    /// Print the name of every command of the profile and the arguments it takes
+    fn list_commands(&self, out: &mut impl Write) -> Result<(), String> {
+        for cmd in &self.config.commands {
+            let channel = if cmd.channel { " (per channel)" } else { "" };
+            let values: Vec<&str> = cmd.values.iter().map(|value| value.trim()).filter(|value| !value.is_empty()).collect();
+            let line = if values.is_empty() {
+                format!("{}{}", cmd.name, channel)
+            } else {
+                format!("{}{}: {}", cmd.name, channel, values.join(" | "))
+            };
+            writeln!(out, "{}", line).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Send the command named in `arguments` and print the reply, `query` reads a reply even if the command is no query
+    /// Errors if the device cannot be reached or reports errors for the command.
+    fn execute(&mut self, arguments: &Arguments, query: bool, out: &mut impl Write) -> Result<(), String> {
+        let request = self.build(arguments, query)?;
+        let command = request.command.trim_end().to_string();
+        let reply = self.connect()?.execute(&request).map_err(|e| format!("{}: {}", command, e))?;
+        match reply.response {
+            Some(Response::Text(text)) => writeln!(out, "{}", text),
+            Some(Response::Block(data)) => out.write_all(&data),
+            None => Ok(()),
+        }.map_err(|e| e.to_string())?;
+        if !reply.errors.is_empty() {
+            return Err(format!("{}: The device reported {}", command, reply.errors.join(", ")));
+        }
+        Ok(())
+    }
+
+    /// Build the [`Request`] for the command named in `arguments`
+    fn build(&self, arguments: &Arguments, query: bool) -> Result<Request, String> {
+        let name = arguments.words.get(1).ok_or_else(|| format!("Missing the name of the command\n{}", USAGE))?;
+        if let Some(extra) = arguments.words.get(3) {
+            return Err(format!("Unexpected argument \"{}\", quote arguments containing spaces", extra));
+        }
+        if self.config.command(name).is_none() {
+            return Err(format!("{} has no command named \"{}\", see list-commands", self.config.device.name, name));
+        }
+        let argument = arguments.words.get(2).map(String::as_str);
+        let mut request = build_request(&self.config, name, argument, arguments.channel.unwrap_or(1), &arguments.inputs, query)?;
+        if !arguments.no_check {
+            request.error_query = Some(self.config.device.error_query.clone());
+        }
+        Ok(request)
+    }
+
+    /// Run the `send` and `query` lines of `file` in order, stopping at the first one failing
+    /// Empty lines and lines starting with `#` are skipped, `defaults` holds the options given on the command line.
+    fn run_file(&mut self, file: &str, defaults: &Arguments, out: &mut impl Write) -> Result<(), String> {
+        let text = std::fs::read_to_string(file).map_err(|e| format!("{} could not be read: {}", file, e))?;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let run_line = |session: &mut Self, out: &mut _| {
+                let mut arguments = parse(&split_words(line)?)?;
+                if arguments.profile.is_some() || arguments.address.is_some() {
+                    return Err("--profile and --address can only be given on the command line".to_string());
+                }
+                arguments.channel = arguments.channel.or(defaults.channel);
+                arguments.no_check |= defaults.no_check;
+                match arguments.words.first().map(String::as_str) {
+                    Some("send") => session.execute(&arguments, false, out),
+                    Some("query") => session.execute(&arguments, true, out),
+                    _ => Err("Lines must start with send or query".to_string()),
+                }
+            };
+            run_line(self, out).map_err(|e| format!("{}:{}: {}", file, index + 1, e))?;
+        }
+        Ok(())
+    }
+
+    /// Run the Rhai script in `file`, printing the lines it writes as they come
+    /// The error queue is read after each command unless `--no-check` is given, device errors stop the script.
+    fn run_script(&mut self, file: &str, arguments: &Arguments, out: &mut impl Write) -> Result<(), String> {
+        let source = std::fs::read_to_string(file).map_err(|e| format!("{} could not be read: {}", file, e))?;
+        self.connect()?;
+        let connection = Arc::new(Mutex::new(std::mem::take(&mut self.connection)));
+        let config = self.config.clone();
+        let error_query = (!arguments.no_check).then(|| config.device.error_query.clone());
+        let (sender, receiver) = mpsc::channel();
+        let script = std::thread::spawn(move || {
+            let cancel = Arc::new(AtomicBool::new(false));
+            script::run(&source, &config, connection, error_query, cancel, move |line| {
+                // Fails only once writing to `out` has failed, which is reported already
+                let _ = sender.send(line.to_string());
+            })
+        });
+        for line in receiver {
+            writeln!(out, "{}", line).map_err(|e| e.to_string())?;
+        }
+        let result = script.join().map_err(|_| "The script has crashed".to_string())?;
+        result.map_err(|e| format!("{}: {}", file, e))
+    }
+
+    /// Return the connection to the device, connecting to it first if needed
+    fn connect(&mut self) -> Result<&mut DeviceConnection, String> {
+        let device = &self.config.device;
+        let address = self.address.clone().unwrap_or_else(|| device.address.clone());
+        if address.is_empty() {
+            return Err(format!("No address for {}, set `address` in the config or pass --address", device.name));
+        }
+        if !self.connection.connected() {
+            self.connection.connect(&device.connection, &address)
+                .map_err(|e| format!("Could not connect to {}: {}", address, e))?;
+        }
+        Ok(&mut self.connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        split_words(line).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let arguments = parse(&args("send 'Apply Sine' --channel 2 FREQ=1k AMP=2 --no-check")).unwrap();
+        assert_eq!(arguments.words, vec!["send", "Apply Sine"]);
+        assert_eq!(arguments.channel, Some(2));
+        assert_eq!(arguments.inputs.get("FREQ").map(String::as_str), Some("1k"));
+        assert!(arguments.no_check);
+        assert_eq!(args(r#"send Text '"Ready?"'"#), vec!["send", "Text", "\"Ready?\""]);
+        assert!(parse(&args("send Output --channel")).is_err());
+        assert!(parse(&args("send Output --bogus")).is_err());
+        assert!(split_words("send \"Apply Sine").is_err());
+    }
+
+    #[test]
+    fn test_send_and_query() {
+        let config = devices::Configuration::from("config/config.toml").unwrap();
+        let address = instrument_core::simulator::spawn(&config, "127.0.0.1:0".parse().unwrap()).unwrap().to_string();
+        let run = |line: &str| {
+            let mut out = Vec::new();
+            let result = run_to("config/config.toml", &args(&format!("{} --address {}", line, address)), &mut out);
+            result.map(|()| String::from_utf8(out).unwrap())
+        };
+
+        assert_eq!(run("send Frequency 1000 --channel 2"), Ok(String::new()));
+        assert_eq!(run("query frequency --channel 2"), Ok("1000\n".into()));
+        assert_eq!(run("query Frequency --channel 1"), Ok("0\n".into()));
+        assert!(run("send Frequency 1000 --channel 3").unwrap_err().contains("Channel 3 is not available"));
+        assert!(run("send Output maybe").unwrap_err().contains("expected one of \"on\", \"off\""));
+        assert!(run("send Bogus").is_err());
+
+        let file = std::env::temp_dir().join(format!("instrument-app-run-file-{}", std::process::id()));
+        std::fs::write(&file, "# Set up channel 2\nsend Voltage ':HIGH 2.5'\n\nsend Frequency 500\nquery Frequency\n").unwrap();
+        assert_eq!(run(&format!("run-file {} --channel 2", file.display())), Ok("500\n".into()));
+        std::fs::write(&file, "send Output on\nsend Output? --channel 2\nsend Output off\n").unwrap();
+        let result = run(&format!("run-file {}", file.display()));
+        std::fs::remove_file(&file).unwrap();
+        assert!(result.unwrap_err().starts_with(&format!("{}:2:", file.display())));
+
+        let file = std::env::temp_dir().join(format!("instrument-app-run-script-{}.rhai", std::process::id()));
+        std::fs::write(&file, "for f in [100, 200] {\n    send(command(\"Frequency\", `${f}`));\n    print(query(command_query(\"Frequency\")));\n}\n").unwrap();
+        assert_eq!(run(&format!("run-script {}", file.display())), Ok("100\n200\n".into()));
+        std::fs::write(&file, "send(command(\"Output\", \"maybe\"));\n").unwrap();
+        let result = run(&format!("run-script {}", file.display()));
+        std::fs::remove_file(&file).unwrap();
+        assert!(result.unwrap_err().contains("\"maybe\" is no argument of Output"));
+    }
+}
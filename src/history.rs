@@ -0,0 +1,118 @@
+//! History of the commands typed in the terminal, kept between runs of the app
+use std::{io::Write, path::PathBuf};
+
+/// Highest number of commands kept in the history
+const MAX_ENTRIES: usize = 500;
+
+/// Commands typed so far, oldest first, and the position while walking through them
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+    /// Index of the entry shown in the input, [`None`] while typing a new command
+    position: Option<usize>,
+    /// File the history is kept in, [`None`] if it is not persisted
+    file: Option<PathBuf>,
+}
+
+impl History {
+    /// Load the history from the data directory of the user, e.g. `~/.local/share/instrument-app/history`
+    pub fn load() -> Self {
+        let file = dirs::data_dir().map(|directory| directory.join("instrument-app").join("history"));
+        match file {
+            Some(file) => Self::from_file(file),
+            None => Self::default(),
+        }
+    }
+
+    /// Load the history kept in `file`, a missing file starts an empty history
+    pub fn from_file(file: PathBuf) -> Self {
+        let entries = std::fs::read_to_string(&file)
+            .map(|text| text.lines().filter(|line| !line.trim().is_empty()).map(String::from).collect())
+            .unwrap_or_default();
+        Self { entries, position: None, file: Some(file) }
+    }
+
+    /// Add a sent command and start walking from the newest entry again
+    /// Repeating the last command does not add it twice.
+    pub fn push(&mut self, command: &str) {
+        self.position = None;
+        let command = command.trim();
+        if command.is_empty() || self.entries.last().is_some_and(|last| last == command) {
+            return;
+        }
+        self.entries.push(command.to_string());
+        let excess = self.entries.len().saturating_sub(MAX_ENTRIES);
+        self.entries.drain(..excess);
+        if let Err(e) = self.save(command, excess > 0) {
+            eprintln!("Error: The command history could not be saved: {}", e);
+        }
+    }
+
+    /// Step to the previous (older) command, returns [`None`] if there is none
+    pub fn older(&mut self) -> Option<&str> {
+        let position = match self.position {
+            Some(0) => 0,
+            Some(position) => position - 1,
+            None => self.entries.len().checked_sub(1)?,
+        };
+        self.position = Some(position);
+        self.entries.get(position).map(String::as_str)
+    }
+
+    /// Step to the next (newer) command, returns an empty command after the newest one
+    pub fn newer(&mut self) -> Option<&str> {
+        let position = self.position?;
+        if position + 1 < self.entries.len() {
+            self.position = Some(position + 1);
+            self.entries.get(position + 1).map(String::as_str)
+        } else {
+            self.position = None;
+            Some("")
+        }
+    }
+
+    /// Append `command` to the history file, or rewrite it if old entries have been dropped
+    fn save(&self, command: &str, rewrite: bool) -> std::io::Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        if let Some(directory) = file.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        if rewrite {
+            return std::fs::write(file, self.entries.join("\n") + "\n");
+        }
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(file)?;
+        writeln!(file, "{}", command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walk_and_persist() {
+        let file = std::env::temp_dir().join(format!("instrument-app-history-{}", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        let mut history = History::from_file(file.clone());
+        assert_eq!(history.older(), None);
+        history.push("*IDN?");
+        history.push(":OUTPut1 on");
+        history.push(":OUTPut1 on");
+
+        assert_eq!(history.older(), Some(":OUTPut1 on"));
+        assert_eq!(history.older(), Some("*IDN?"));
+        assert_eq!(history.older(), Some("*IDN?"));
+        assert_eq!(history.newer(), Some(":OUTPut1 on"));
+        assert_eq!(history.newer(), Some(""));
+        assert_eq!(history.newer(), None);
+
+        // The history is there again after a restart
+        let mut history = History::from_file(file.clone());
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(history.older(), Some(":OUTPut1 on"));
+        assert_eq!(history.older(), Some("*IDN?"));
+    }
+}
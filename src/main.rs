@@ -1,31 +1,121 @@
 //! Extensible cross-platform GUI application for controlling lab instruments over the network using SCPI commands.
-mod devices;
-mod networking;
 mod messages;
 mod styles;
 mod screens;
+mod history;
+mod watcher;
+mod cli;
+mod arguments;
+mod runner;
 
-type ScpiCommand = crate::devices::Command;
+type ScpiCommand = instrument_core::Command;
 
-use iced::{Column, Container, Element, Settings, Sandbox, window, alignment};
+use clap::Parser;
+use instrument_core::{devices, networking, simulator};
+use iced::{Application, Color, Column, Command, Container, Element, Settings, Subscription, Text, executor, window, alignment};
 use crate::{
     screens::toolbar::ToolBar,
     screens::settings::SettingsScreen,
     screens::command_selection::CommandScreen,
-    messages::{Message, ScreenType}};
+    screens::error::ErrorScreen,
+    screens::editor::EditorScreen,
+    screens::terminal::TerminalScreen,
+    screens::sequences::SequenceScreen,
+    screens::scripts::ScriptScreen,
+    screens::profiles::ProfilePicker,
+    screens::open::OpenScreen,
+    arguments::Arguments,
+    history::History,
+    messages::{EditorMessage, Message, OpenMessage, ScreenType, ScriptMessage, SettingsMessage, TerminalMessage}};
 
 /// Program entry function
 pub fn main() -> iced::Result {
-    // TODO: Add ability to read a config from CLI parameter
+    let arguments = Arguments::parse();
+    if arguments.simulate.is_some() || arguments.action.is_some() {
+        std::process::exit(run_headless(&arguments));
+    }
+    let (width, height) = arguments.window_size;
     MainScreen::run(Settings {
         window: window::Settings {
-            size: (700, 400),
+            size: (width, height),
             ..Default::default()
         },
-        ..Default::default()
+        ..Settings::with_flags(arguments)
     })
 }
 
+/// Run the simulator or the subcommand given in `arguments` instead of the GUI and return the exit code
+fn run_headless(arguments: &Arguments) -> i32 {
+    let config = match arguments.config().map(String::from).or_else(arguments::last_config) {
+        Some(config) => config,
+        None => {
+            eprintln!("Error: Please specify a config file or directory with --config");
+            return 2;
+        }
+    };
+    if let Some(action) = &arguments.action {
+        let mut args = action.to_args();
+        // The address may also be given in front of the subcommand
+        if let (Some(address), false) = (&arguments.address, args.iter().any(|arg| arg == "--address")) {
+            args.extend(["--address".to_string(), address.clone()]);
+        }
+        return cli::run(&config, &args);
+    }
+    let configs = match devices::Configuration::load_all(&config) {
+        Ok(configs) => configs,
+        Err(diagnostics) => {
+            diagnostics.iter().for_each(|diagnostic| eprintln!("Error: {}", diagnostic));
+            return -1;
+        }
+    };
+    let address = arguments.simulate.as_deref().unwrap_or_default();
+    let result = networking::parse_ip(address)
+        .and_then(|address| simulator::run(&configs, address).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+/// Screens of one device profile, each profile has a connection of its own
+struct Profile {
+    /// Name of the device
+    name: String,
+    /// The [`SettingsScreen`] where the connection to the device is established
+    settings_screen: SettingsScreen,
+    /// The [`CommandScreen`] where commands are put together and sent to the device
+    command_screen: CommandScreen,
+    /// The [`TerminalScreen`] where SCPI commands are typed directly
+    terminal_screen: TerminalScreen,
+    /// The [`SequenceScreen`] where the sequences of the config are run
+    sequence_screen: SequenceScreen,
+    /// The [`ScriptScreen`] where scripts driving the device are edited and run
+    script_screen: ScriptScreen,
+    /// The [`EditorScreen`] where the commands of the config file are edited
+    editor_screen: EditorScreen,
+    /// Patterns for the reply to `*IDN?` of the device
+    idn: Vec<String>,
+}
+
+impl Profile {
+    /// Set up the screens for `config`, which is profile number `index`
+    fn new(index: usize, config: devices::Configuration) -> Self {
+        Self {
+            name: config.device.name.clone(),
+            idn: config.device.idn.clone(),
+            settings_screen: SettingsScreen::from(&config.device, index),
+            terminal_screen: TerminalScreen::from(&config),
+            sequence_screen: SequenceScreen::from(&config),
+            script_screen: ScriptScreen::from(&config),
+            editor_screen: EditorScreen::from(&config),
+            command_screen: CommandScreen::from(config),
+        }
+    }
+}
+
 /// Data and screens held by the application root
 #[derive(Default)]
 struct MainScreen {
@@ -33,76 +123,423 @@ struct MainScreen {
     current_screen: ScreenType,
     /// The [`ToolBar`] displayed at the top row
     toolbar: ToolBar,
-    /// The [`SettingsScreen`] where the connection to the device is established
-    settings_screen: SettingsScreen,
-    /// The [`CommandScreen`] where commands are put together and sent to the device
-    command_screen: CommandScreen,
-    /// Window title
-    title: String,
+    /// The [`Profile`]s of all devices in the config
+    profiles: Vec<Profile>,
+    /// Names of all profiles, in the same order
+    profile_names: Vec<String>,
+    /// Index of the profile whose screens are shown
+    active_profile: usize,
+    /// The [`ProfilePicker`] shown above the settings screen if there are several profiles
+    profile_picker: ProfilePicker,
+    /// The [`ErrorScreen`] shown instead of all other screens if the config file could not be loaded
+    error_screen: Option<ErrorScreen>,
+    /// The [`OpenScreen`] shown instead of all other screens until a config has been picked
+    open_screen: Option<OpenScreen>,
+    /// Commands typed in the terminal of any profile, also from earlier runs
+    history: History,
+    /// Path of the config file or directory
+    config_path: String,
+    /// Paths of the files the config includes commands from, watched for changes like the config itself
+    include_files: Vec<String>,
+    /// Problems found when loading the config again while the app is running, the last valid config stays in use
+    config_error: Option<String>,
+    /// Whether the connection of the active profile made at launch is still pending,
+    /// the command screen is opened once it succeeds
+    autoconnecting: bool,
+    /// Whether the screen shown on start has been given on the command line, it is kept when connecting at launch
+    fixed_screen: bool,
+    /// Address of the first profile given on the command line, replaces the one of the config
+    address: Option<String>,
+    /// Connect the first profile on start, given on the command line
+    connect: bool,
+    /// Scaling factor of the widgets
+    scale: f64,
 }
 
 
-impl Sandbox for MainScreen {
+impl Application for MainScreen {
+    type Executor = executor::Default;
     type Message = Message;
+    type Flags = Arguments;
 
-    fn new() -> Self {
-        let args: Vec<String> = std::env::args().collect();
-        if args.len() < 2 {
-            eprintln!("Error: Please specify a config file as the first argument!");
-            std::process::exit(-1);
-        }
-
-        let config = devices::Configuration::from(&args[1]).unwrap();
-        Self {
-            command_screen: CommandScreen::from(config),
-            settings_screen: SettingsScreen::default(),
-            title: String::from("Instrument"),
+    fn new(arguments: Arguments) -> (Self, Command<Message>) {
+        let config_path = arguments.config().map(String::from).or_else(arguments::last_config);
+        let mut main_screen = Self {
+            current_screen: arguments.screen.clone().unwrap_or_default(),
+            fixed_screen: arguments.screen.is_some(),
+            address: arguments.address,
+            connect: arguments.connect,
+            scale: arguments.scale,
+            history: History::load(),
             ..Default::default()
-        }
+        };
+        // Without a config given or used before, one is picked first
+        let command = match config_path {
+            Some(config_path) => {
+                main_screen.config_path = config_path;
+                main_screen.load_config()
+            }
+            None => {
+                main_screen.open_screen = Some(OpenScreen::new());
+                Command::none()
+            }
+        };
+        (main_screen, command)
     }
 
-
     fn title(&self) -> String {
-        self.title.clone()
+        match self.profiles.get(self.active_profile) {
+            Some(profile) if self.profiles.len() > 1 => format!("Instrument - {}", profile.name),
+            _ => String::from("Instrument"),
+        }
     }
 
     /// Handles all [`Message`]s that are generated by the application
-    fn update(&mut self, message: Message) {
+    /// Device I/O is run as a background [`Command`] so the UI stays responsive
+    fn update(&mut self, message: Message) -> Command<Message> {
+        if let Message::Open(message) = message {
+            return self.open(message);
+        }
+        let active = self.active_profile;
+        let profile = match self.profiles.get_mut(active) {
+            Some(profile) => profile,
+            None => return match message {
+                Message::ReloadConfig | Message::ConfigChanged => self.load_config(),
+                _ => Command::none(),
+            },
+        };
         match message {
-            Message::ChangeView(v) => { self.current_screen = v }
-            Message::Settings(s) => { self.settings_screen.update(s) }
-            Message::Command(c) => { self.command_screen.update(c) }
-            Message::SendCommand(cmd) => {
-                match self.settings_screen.device_connection().send(cmd.as_bytes()) {
-                    Ok(n) => {
-                        let reply = self.settings_screen.device_connection().receive().unwrap_or("No Reply received!".into());
-                        self.command_screen.set_status_text(format!("Reply {} bytes: {}", n, reply));
-                    }
-                    Err(e) => self.command_screen.set_status_text(format!("Sending command failed! Please check connection.\nError: {}", e))
+            Message::ChangeView(v) => {
+                if v == ScreenType::Terminal {
+                    profile.terminal_screen.focus();
+                }
+                self.current_screen = v
+            }
+            Message::Settings(SettingsMessage::Connected(index, result)) => {
+                // Open the command screen once the connection of the active profile made at launch succeeds
+                if index == active && std::mem::take(&mut self.autoconnecting) && result.is_ok() {
+                    self.current_screen = ScreenType::Command;
+                }
+                if let Some(profile) = self.profiles.get_mut(index) {
+                    let command = profile.settings_screen.update(SettingsMessage::Connected(index, result));
+                    self.select_matching_profile(index);
+                    return command;
+                }
+            }
+            Message::Settings(s) => { return profile.settings_screen.update(s) }
+            Message::Command(c) => { profile.command_screen.update(c) }
+            Message::Editor(EditorMessage::Save) => {
+                if profile.editor_screen.save() {
+                    return self.reload_config();
+                }
+            }
+            Message::Editor(e) => { profile.editor_screen.update(e) }
+            Message::Terminal(TerminalMessage::Submit) => {
+                if let Some(request) = profile.terminal_screen.submit(&mut self.history) {
+                    return Command::perform(
+                        networking::execute(profile.settings_screen.device_connection(), request),
+                        move |result| Message::TerminalCompleted(active, result),
+                    );
+                }
+            }
+            Message::Terminal(t) => { profile.terminal_screen.update(t, &mut self.history) }
+            Message::Sequence(s) => {
+                profile.sequence_screen.update(s);
+                return self.next_sequence_step(active);
+            }
+            Message::SequenceStepCompleted(index, step, result) => {
+                if let Some(profile) = self.profiles.get_mut(index) {
+                    profile.sequence_screen.set_result(step, result);
+                    return self.next_sequence_step(index);
+                }
+            }
+            Message::Script(s) => { profile.script_screen.update(s) }
+            Message::ScriptOutput(index, line) => {
+                if let Some(profile) = self.profiles.get_mut(index) {
+                    profile.script_screen.add_output(line);
+                }
+            }
+            Message::ScriptFinished(index, result) => {
+                if let Some(profile) = self.profiles.get_mut(index) {
+                    profile.script_screen.set_finished(result);
+                }
+            }
+            Message::Open(_) => {}
+            Message::TerminalCompleted(index, result) => {
+                if let Some(profile) = self.profiles.get_mut(index) {
+                    profile.terminal_screen.set_reply(result);
                 }
             }
+            Message::SendCommand(request) => {
+                profile.command_screen.set_pending(true);
+                profile.command_screen.set_status_text(format!("Sending {}...", request.command.trim_end()));
+                // The selection may change until the reply arrives
+                let unit = profile.command_screen.reply_unit();
+                return Command::perform(
+                    networking::execute(profile.settings_screen.device_connection(), request),
+                    move |result| Message::CommandCompleted(active, unit.clone(), result),
+                );
+            }
+            Message::CommandCompleted(index, unit, result) => {
+                // The reply goes to the profile the command was sent with, even if another one is active by now
+                if let Some(profile) = self.profiles.get_mut(index) {
+                    profile.command_screen.set_pending(false);
+                    profile.command_screen.set_reply(result, unit.as_deref());
+                }
+            }
+            Message::ProfileSelected(name) => {
+                if let Some(index) = self.profile_names.iter().position(|n| *n == name) {
+                    self.active_profile = index;
+                    self.autoconnecting = false;
+                }
+            }
+            Message::ConnectAll => {
+                return Command::batch(self.profiles.iter_mut().map(|profile| profile.settings_screen.connect()));
+            }
+            Message::ReloadConfig => { return self.load_config() }
+            Message::ConfigChanged => { return self.reload_config() }
+            Message::ConfigWatchFailed(e) => {
+                self.config_error = Some(format!("Changes to the config are not picked up: {}", e));
+            }
         }
+        Command::none()
     }
 
     /// Show the correct content depending on the application state
-    fn view(&mut self) -> Element<Message> {
-        Container::new(
-            Column::new()
-                .padding(8)
-                .spacing(8)
-                .align_items(alignment::Alignment::Center)
-                .push(self.toolbar.view(&self.current_screen))
-                .push(match &self.current_screen {
-                    ScreenType::Settings => self.settings_screen.view(),
-                    ScreenType::Command => self.command_screen.view(),
-                })
-        )
-            .into()
+    fn view(&mut self) -> Element<'_, Message> {
+        if let Some(open_screen) = &mut self.open_screen {
+            return open_screen.view().into();
+        }
+        if let Some(error_screen) = &mut self.error_screen {
+            return error_screen.view().into();
+        }
+        let mut column = Column::new()
+            .padding(8)
+            .spacing(8)
+            .align_items(alignment::Alignment::Center)
+            .push(self.toolbar.view(&self.current_screen));
+        if let Some(error) = &self.config_error {
+            column = column.push(Text::new(error).size(16).color(Color::from_rgb(0.8, 0.0, 0.0)));
+        }
+        // With several devices, the settings screen starts with picking the profile
+        if let (ScreenType::Settings, true) = (&self.current_screen, self.profiles.len() > 1) {
+            let statuses = self.profiles.iter().map(|p| p.settings_screen.connection_status().to_string()).collect();
+            column = column.push(self.profile_picker.view(&self.profile_names, self.active_profile, statuses));
+        }
+        if let Some(profile) = self.profiles.get_mut(self.active_profile) {
+            column = column.push(match &self.current_screen {
+                ScreenType::Settings => profile.settings_screen.view(),
+                ScreenType::Command => profile.command_screen.view(),
+                ScreenType::Terminal => profile.terminal_screen.view(),
+                ScreenType::Sequences => profile.sequence_screen.view(),
+                ScreenType::Scripts => profile.script_screen.view(),
+                ScreenType::Editor => profile.editor_screen.view(),
+            });
+        }
+        Container::new(column).into()
+    }
+
+    /// Load the config again whenever one of its files changes, pass keys to the terminal and the script editor while
+    /// they are shown and run the scripts started in any profile
+    fn subscription(&self) -> Subscription<Message> {
+        if self.config_path.is_empty() {
+            return Subscription::none();
+        }
+        let mut subscriptions = vec![watcher::config_changes(self.config_path.clone(), self.include_files.clone())];
+        match self.current_screen {
+            ScreenType::Terminal => subscriptions.push(iced_native::subscription::events_with(terminal_key)),
+            ScreenType::Scripts => subscriptions.push(iced_native::subscription::events_with(script_key)),
+            _ => {}
+        }
+        subscriptions.extend(self.profiles.iter().enumerate().map(|(index, profile)| {
+            profile.script_screen.subscription(index, profile.settings_screen.device_connection())
+        }));
+        Subscription::batch(subscriptions)
     }
 
     /// Set the scaling factor, higher number corresponds to "zooming in" on the widgets
     fn scale_factor(&self) -> f64 {
-        1.0
+        self.scale
+    }
+}
+
+/// Turn the keys for history and completion into [`TerminalMessage`]s, other events are left to the widgets
+fn terminal_key(event: iced_native::Event, _status: iced_native::event::Status) -> Option<Message> {
+    use iced_native::keyboard::{Event::KeyPressed, KeyCode};
+    match event {
+        iced_native::Event::Keyboard(KeyPressed { key_code, .. }) => match key_code {
+            KeyCode::Up => Some(Message::Terminal(TerminalMessage::HistoryOlder)),
+            KeyCode::Down => Some(Message::Terminal(TerminalMessage::HistoryNewer)),
+            KeyCode::Tab => Some(Message::Terminal(TerminalMessage::Complete)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Turn Up and Down into [`ScriptMessage`]s moving between the lines of the script, other events are left to the widgets
+fn script_key(event: iced_native::Event, _status: iced_native::event::Status) -> Option<Message> {
+    use iced_native::keyboard::{Event::KeyPressed, KeyCode};
+    match event {
+        iced_native::Event::Keyboard(KeyPressed { key_code: KeyCode::Up, .. }) => Some(Message::Script(ScriptMessage::FocusPrevious)),
+        iced_native::Event::Keyboard(KeyPressed { key_code: KeyCode::Down, .. }) => Some(Message::Script(ScriptMessage::FocusNext)),
+        _ => None,
+    }
+}
+
+/// Paths of the files any of `configs` includes commands from, each path once
+fn include_files(configs: &[devices::Configuration]) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    for file in configs.iter().flat_map(|config| config.include_files()) {
+        if !files.contains(&file) {
+            files.push(file);
+        }
     }
+    files
 }
 
+impl MainScreen {
+    /// Load the config file or directory and set up the screens of every profile, or show the problems found in it
+    /// Connects to the configured devices right away if `autoconnect` is set.
+    fn load_config(&mut self) -> Command<Message> {
+        let mut configs = match devices::Configuration::load_all(&self.config_path) {
+            Ok(configs) => configs,
+            Err(diagnostics) => {
+                self.error_screen = Some(ErrorScreen::from(&diagnostics));
+                return Command::none();
+            }
+        };
+        arguments::remember_config(&self.config_path);
+        self.include_files = include_files(&configs);
+        // The address and connecting given on the command line apply to the first profile
+        if let Some(config) = configs.first_mut() {
+            if let Some(address) = &self.address {
+                config.device.address = address.clone();
+            }
+            config.device.autoconnect |= self.connect;
+        }
+        self.error_screen = None;
+        self.config_error = None;
+        self.active_profile = 0;
+        self.profile_names = configs.iter().map(|config| config.device.name.clone()).collect();
+        let autoconnect = configs.first().is_some_and(|config| config.device.autoconnect);
+        let mut commands = Vec::new();
+        self.profiles = configs.into_iter().enumerate().map(|(index, config)| {
+            let autoconnect = config.device.autoconnect;
+            let mut profile = Profile::new(index, config);
+            if autoconnect {
+                commands.push(profile.settings_screen.connect());
+            }
+            profile
+        }).collect();
+        // Only wait for the connection made at launch if the configured address is valid
+        self.autoconnecting = autoconnect
+            && !self.fixed_screen
+            && self.profiles.first().is_some_and(|profile| profile.settings_screen.get_address().is_some());
+        Command::batch(commands)
+    }
+
+    /// Handle the [`OpenMessage`]s of the config picker, opening the picked config
+    fn open(&mut self, message: OpenMessage) -> Command<Message> {
+        let open_screen = match &mut self.open_screen {
+            Some(open_screen) => open_screen,
+            None => return Command::none(),
+        };
+        if let OpenMessage::Open = message {
+            let path = open_screen.path().trim().to_string();
+            if !std::path::Path::new(&path).exists() {
+                open_screen.set_error(format!("{} does not exist", path));
+                return Command::none();
+            }
+            self.open_screen = None;
+            self.config_path = path;
+            return self.load_config();
+        }
+        open_screen.update(message);
+        Command::none()
+    }
+
+    /// Load the config again after it has been changed, keeping the connections and selections that are still valid
+    /// Profiles are kept by name, new ones are added without connecting. If the config has become invalid,
+    /// the problems are shown in a banner and the last valid config stays in use.
+    fn reload_config(&mut self) -> Command<Message> {
+        if self.error_screen.is_some() || self.profiles.is_empty() {
+            return self.load_config();
+        }
+        let configs = match devices::Configuration::load_all(&self.config_path) {
+            Ok(configs) => configs,
+            Err(diagnostics) => {
+                let problems: Vec<String> = diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+                self.config_error = Some(format!("The changed config could not be loaded:\n{}", problems.join("\n")));
+                return Command::none();
+            }
+        };
+        self.config_error = None;
+        self.include_files = include_files(&configs);
+
+        let active_name = self.profile_names.get(self.active_profile).cloned();
+        let mut previous = std::mem::take(&mut self.profiles);
+        self.profiles = configs.into_iter().enumerate().map(|(index, config)| {
+            match previous.iter().position(|profile| profile.name == config.device.name) {
+                Some(position) => {
+                    let mut profile = previous.swap_remove(position);
+                    profile.idn = config.device.idn.clone();
+                    profile.settings_screen.set_profile(index);
+                    profile.editor_screen.reload(&config);
+                    profile.terminal_screen.reload(&config);
+                    profile.sequence_screen.reload(&config);
+                    profile.script_screen.reload(&config);
+                    profile.command_screen.reload(config);
+                    profile
+                }
+                None => Profile::new(index, config),
+            }
+        }).collect();
+        self.profile_names = self.profiles.iter().map(|profile| profile.name.clone()).collect();
+        self.active_profile = active_name
+            .and_then(|name| self.profile_names.iter().position(|other| *other == name))
+            .unwrap_or(0);
+        Command::none()
+    }
+
+    /// Run the next step of the sequence running in profile `index` as a background [`Command`], if there is one
+    fn next_sequence_step(&mut self, index: usize) -> Command<Message> {
+        let profile = match self.profiles.get_mut(index) {
+            Some(profile) => profile,
+            None => return Command::none(),
+        };
+        match profile.sequence_screen.next_step() {
+            Some((step, prepared)) => Command::perform(
+                devices::sequence::run(profile.settings_screen.device_connection(), prepared),
+                move |result| Message::SequenceStepCompleted(index, step, result),
+            ),
+            None => Command::none(),
+        }
+    }
+
+    /// Hand the device connected by profile `index` over to the profile its identity matches
+    /// Nothing changes if the device matches profile `index` itself, no other profile or only one that is already connected.
+    fn select_matching_profile(&mut self, index: usize) {
+        let identity = match self.profiles[index].settings_screen.identity() {
+            Some(identity) if !identity.matches(&self.profiles[index].idn) => identity.clone(),
+            _ => return,
+        };
+        let target = self.profiles.iter()
+            .position(|profile| identity.matches(&profile.idn) && !profile.settings_screen.is_busy());
+        let target = match target {
+            Some(target) => target,
+            None => return,
+        };
+        let (source, target_profile) = if index < target {
+            let (left, right) = self.profiles.split_at_mut(target);
+            (&mut left[index], &mut right[0])
+        } else {
+            let (left, right) = self.profiles.split_at_mut(index);
+            (&mut right[0], &mut left[target])
+        };
+        source.settings_screen.hand_over(&mut target_profile.settings_screen);
+        if self.active_profile == index {
+            self.active_profile = target;
+        }
+    }
+}
@@ -1,7 +1,8 @@
 //! Collection of Message types used througout the application
-use std::net::SocketAddr;
+use std::path::PathBuf;
+use instrument_core::networking::{ConnectionSettings, Peer, Reply, Request, TransportKind};
 
-type ScpiCommand = crate::devices::Command;
+type ScpiCommand = instrument_core::Command;
 
 /// Messages that are passed to and handled by the main application
 #[derive(Debug, Clone)]
@@ -12,15 +13,52 @@ pub enum Message{
     Settings(SettingsMessage),
     /// Wrapper for [`CommandMessage`] that should be passed to the command screen
     Command(CommandMessage),
+    /// Wrapper for [`EditorMessage`] that should be passed to the config editor
+    Editor(EditorMessage),
+    /// Wrapper for [`TerminalMessage`] that should be passed to the terminal
+    Terminal(TerminalMessage),
+    /// Wrapper for [`SequenceMessage`] that should be passed to the sequence runner
+    Sequence(SequenceMessage),
+    /// A step of the running sequence has been run in the background, contains the index of the device profile,
+    /// the index of the step and the [`Reply`] or an error message
+    SequenceStepCompleted(usize, usize, Result<Reply, String>),
+    /// Wrapper for [`ScriptMessage`] that should be passed to the script screen
+    Script(ScriptMessage),
+    /// The running script has written a line, contains the index of the device profile and the line
+    ScriptOutput(usize, String),
+    /// The running script has ended, contains the index of the device profile and the error that stopped it, if any
+    ScriptFinished(usize, Result<(), String>),
+    /// Wrapper for [`OpenMessage`] that should be passed to the config picker
+    Open(OpenMessage),
+    /// The background task sending a command typed in the terminal has finished, contains the index
+    /// of the device profile and the [`Reply`] or an error message
+    TerminalCompleted(usize, Result<Reply, String>),
     /// Contains the command to be sent to the device
-    SendCommand(String)
+    SendCommand(Request),
+    /// The background task sending a command has finished, contains the index of the device profile
+    /// the command was sent with, the unit of its replies and the [`Reply`] or an error message
+    CommandCompleted(usize, Option<String>, Result<Reply, String>),
+    /// The device profile with the given name has been picked as the active one
+    ProfileSelected(String),
+    /// Connect to the devices of all profiles that are not connected yet
+    ConnectAll,
+    /// The config file should be loaded again, e.g. after fixing the problems found in it
+    ReloadConfig,
+    /// A config file has been changed on disk
+    ConfigChanged,
+    /// The config files cannot be watched for changes, contains the reason
+    ConfigWatchFailed(String),
 }
 
 /// Represents all available types of screens/views
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, clap::ValueEnum)]
 pub enum ScreenType {
     Settings,
     Command,
+    Terminal,
+    Sequences,
+    Scripts,
+    Editor,
 }
 
 impl Default for ScreenType {
@@ -37,8 +75,12 @@ pub enum CommandMessage {
     ChannelSelected(u8),
     /// An argument for the command has been selected from the dropdown menu
     ArgumentSelected(String),
-    /// A freetext argument for the command has been entered
-    FreetextEntered(String)
+    /// A value for the placeholder with the given name has been entered
+    PlaceholderEntered(String, String),
+    /// The hex view of a binary reply has been switched on or off
+    HexViewToggled(bool),
+    /// Checking the error queue after every command has been switched on or off
+    CheckErrorsToggled(bool),
 }
 
 /// Represents all available messages generated by the settings screen
@@ -48,7 +90,106 @@ pub enum SettingsMessage {
     IpEntered(String),
     /// A port has been entered
     PortEntered(String),
-    /// Connection to the [`SocketAddr`] has been requested
-    Connect(SocketAddr)
+    /// A [`TransportKind`] has been selected
+    TransportSelected(TransportKind),
+    /// Connection to the address using the [`ConnectionSettings`] has been requested
+    Connect(ConnectionSettings, String),
+    /// The background connection attempt has finished, contains the index of the device profile
+    /// and the connected [`Peer`] or an error message
+    Connected(usize, Result<Peer, String>),
+}
+
+/// Represents all available messages generated by the config editor
+#[derive(Debug, Clone)]
+pub enum EditorMessage {
+    /// The command at the index has been selected for editing
+    CommandSelected(usize),
+    /// The name of the selected command has been edited
+    NameEntered(String),
+    /// The SCPI template of the selected command has been edited
+    ScpiEntered(String),
+    /// The selected command has been made per-channel or global
+    ChannelToggled(bool),
+    /// The value at the index has been edited
+    ValueEntered(usize, String),
+    /// An empty value has been added to the selected command
+    ValueAdded,
+    /// The value at the index has been removed
+    ValueRemoved(usize),
+    /// A new command has been added after the selected one
+    CommandAdded,
+    /// The selected command has been deleted
+    CommandDeleted,
+    /// The selected command has been moved up (`true`) or down (`false`)
+    CommandMoved(bool),
+    /// Saving the commands to the config file has been requested
+    Save,
+    /// Discarding all changes has been requested
+    Revert,
 }
 
+/// Represents all available messages generated by the terminal
+#[derive(Debug, Clone)]
+pub enum TerminalMessage {
+    /// The command being typed has been edited
+    InputChanged(String),
+    /// Sending the typed command has been requested
+    Submit,
+    /// The previous command of the history has been requested
+    HistoryOlder,
+    /// The next command of the history has been requested
+    HistoryNewer,
+    /// Completing the typed command has been requested
+    Complete,
+    /// Clearing the scrollback has been requested
+    Clear,
+}
+
+/// Represents all available messages generated by the config picker
+#[derive(Debug, Clone)]
+pub enum OpenMessage {
+    /// The path of the config has been typed
+    PathEntered(String),
+    /// A directory or config file has been clicked in the list
+    EntrySelected(PathBuf),
+    /// Show the parent directory
+    Up,
+    /// The config at the path should be opened
+    Open,
+}
+
+/// Represents all available messages generated by the sequence runner
+#[derive(Debug, Clone)]
+pub enum SequenceMessage {
+    /// The sequence with the given index has been selected
+    SequenceSelected(usize),
+    /// Run the selected sequence
+    Run,
+    /// Stop the running sequence after the current step
+    Stop,
+}
+
+/// Represents all available messages generated by the script screen
+#[derive(Debug, Clone)]
+pub enum ScriptMessage {
+    /// The path of the script file has been typed
+    PathEntered(String),
+    /// Loading the script from the file has been requested
+    Open,
+    /// Saving the script to the file has been requested
+    Save,
+    /// The line at the index has been edited
+    LineChanged(usize, String),
+    /// Enter has been pressed in the line at the index
+    LineSubmitted(usize),
+    /// Moving to the line above the focused one has been requested
+    FocusPrevious,
+    /// Moving to the line below the focused one has been requested
+    FocusNext,
+    /// Run the script
+    Run,
+    /// Cancel the running script
+    Cancel,
+    /// Clearing the output has been requested
+    Clear,
+}
@@ -0,0 +1,63 @@
+//! Runs scripts in the background and reports the lines they write while they are running
+use crate::messages::Message;
+use iced::{futures::{channel::mpsc, StreamExt}, Subscription};
+use instrument_core::{devices, networking::SharedConnection, script};
+use std::sync::{atomic::AtomicBool, Arc};
+
+/// A script to run against the device of a profile
+pub struct ScriptRun {
+    /// Index of the device profile the script runs for
+    pub profile: usize,
+    /// Number telling the runs apart, a new number starts a new run
+    pub id: usize,
+    pub source: String,
+    pub config: devices::Configuration,
+    pub connection: SharedConnection,
+    /// Query reading the error queue after each command, [`None`] to skip the check
+    pub error_query: Option<String>,
+    /// Set to stop the script
+    pub cancel: Arc<AtomicBool>,
+}
+
+/// What the thread running a script reports
+enum Event {
+    Output(String),
+    Finished(Result<(), String>),
+}
+
+/// State of the subscription returned by [`script_run`]
+enum State {
+    /// The script has not been started yet
+    Starting(Box<ScriptRun>),
+    /// The script is running and reports its output through the receiver
+    Running(mpsc::UnboundedReceiver<Event>),
+    /// The script has ended, nothing more will be reported
+    Finished,
+}
+
+/// Subscription that runs the script of `run` on a thread of its own and sends a [`Message::ScriptOutput`] for
+/// every line it writes and [`Message::ScriptFinished`] when it ends
+pub fn script_run(run: ScriptRun) -> Subscription<Message> {
+    let profile = run.profile;
+    iced_native::subscription::unfold(run.id, State::Starting(Box::new(run)), move |state| async move {
+        match state {
+            State::Starting(run) => {
+                let (sender, receiver) = mpsc::unbounded();
+                std::thread::spawn(move || {
+                    let output = sender.clone();
+                    let result = script::run(&run.source, &run.config, run.connection, run.error_query, run.cancel, move |line| {
+                        let _ = output.unbounded_send(Event::Output(line.to_string()));
+                    });
+                    let _ = sender.unbounded_send(Event::Finished(result));
+                });
+                (None, State::Running(receiver))
+            }
+            State::Running(mut receiver) => match receiver.next().await {
+                Some(Event::Output(line)) => (Some(Message::ScriptOutput(profile, line)), State::Running(receiver)),
+                Some(Event::Finished(result)) => (Some(Message::ScriptFinished(profile, result)), State::Finished),
+                None => (Some(Message::ScriptFinished(profile, Err("The script has crashed".into()))), State::Finished),
+            },
+            State::Finished => iced::futures::future::pending().await,
+        }
+    })
+}
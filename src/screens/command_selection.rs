@@ -1,5 +1,21 @@
-use crate::{styles::{self, ui_button}, ScpiCommand, devices, messages::{*, CommandMessage::*}};
-use iced::{Container, Text, button, Length, alignment::Alignment, Row, Column, PickList, pick_list, TextInput, text_input};
+use crate::{styles::{self, ui_button}, ScpiCommand, messages::{*, CommandMessage::*}};
+use instrument_core::{devices, networking::{self, block, Reply, Response}};
+use std::collections::HashMap;
+use iced::{Color, Container, Text, button, Length, alignment::Alignment, Row, Column, PickList, pick_list, TextInput, text_input, Checkbox, Scrollable, scrollable};
+
+/// Number of bytes of a binary reply shown in the hex view
+const HEX_VIEW_LIMIT: usize = 4096;
+
+/// Value entered for a placeholder like `<FREQ>` and the state of its textbox
+#[derive(Default)]
+struct PlaceholderInput {
+    /// Name of the placeholder without angle brackets
+    name: String,
+    /// Content of the textbox
+    value: String,
+    /// State of the textbox
+    state: text_input::State,
+}
 
 /// Textfield, button and dropdown states held by the settings screen
 #[derive(Default)]
@@ -24,72 +40,149 @@ pub struct CommandScreen {
     argument_selection: String,
     /// State of the picklist for arguments
     argument_list: pick_list::State<String>,
-    /// Content of the freetext textbox
-    freetext: String,
-    /// State of the freetext textbox
-    freetext_state: text_input::State,
+    /// Inputs for the placeholders of the selected command and argument, in order of appearance
+    placeholders: Vec<PlaceholderInput>,
 
     /// Currently selected complete command
     current_command: String,
+    /// Why the entered values do not make a valid command, [`None`] if they do
+    command_error: Option<String>,
     /// Status message
     status: String,
+    /// Whether a command is currently in flight and its reply is pending
+    pending: bool,
+    /// Data of the last reply, if it was binary
+    binary_reply: Option<Vec<u8>>,
+    /// Whether the hex view of a binary reply is shown
+    show_hex: bool,
+    /// State of the scrollable hex view
+    hex_view_state: scrollable::State,
+    /// Whether the error queue is drained after every command
+    check_errors: bool,
+    /// Query for the oldest entry of the device's error queue
+    error_query: String,
 }
 
 impl CommandScreen {
     pub fn from(config: devices::Configuration) -> Self {
+        // The config is validated to have commands with values, but rather show an empty screen than panic
+        let first = config.commands.first().cloned().unwrap_or_default();
         let mut command_screen = Self {
             commands: config.commands.clone(),
-            command_selection: config.commands[0].clone(),
             send_button_state: button::State::default(),
-            channels: (1..=config.device.channels.clone()).collect(),
+            channels: (1..=config.device.channels).collect(),
             channel_selection: 1,
-            arguments: config.commands[0].values.clone(),
-            argument_selection: config.commands[0].values[0].clone(),
+            arguments: first.values.clone(),
+            argument_selection: first.values.first().cloned().unwrap_or_default(),
+            command_selection: first,
             status: "Choose a command to start!".into(),
+            check_errors: config.device.check_errors,
+            error_query: config.device.error_query.clone(),
             ..Default::default()
         };
-        command_screen.current_command = command_screen.get_command();
+        command_screen.update_placeholders();
+        command_screen.update_command();
         command_screen
     }
+    /// Take over the commands and channels of `config` after it has been loaded again
+    /// The selected command, channel, argument and entered values are kept as far as they still exist.
+    pub fn reload(&mut self, config: devices::Configuration) {
+        self.commands = config.commands;
+        self.channels = (1..=config.device.channels).collect();
+        self.error_query = config.device.error_query;
+        if !self.channels.contains(&self.channel_selection) {
+            self.channel_selection = 1;
+        }
+        let selection = match self.commands.iter().find(|cmd| cmd.name == self.command_selection.name) {
+            Some(cmd) => cmd.clone(),
+            None => {
+                // Start over with the defaults of the first command, like when selecting it
+                self.placeholders.clear();
+                self.commands.first().cloned().unwrap_or_default()
+            }
+        };
+        if !selection.values.contains(&self.argument_selection) {
+            self.argument_selection = selection.values.first().cloned().unwrap_or_default();
+        }
+        self.arguments = selection.values.clone();
+        self.command_selection = selection;
+        self.update_placeholders();
+        self.update_command();
+    }
+
     /// Display a the command selection screen using dropdown selection lists and textboxes
     /// Provides information about the response the device sent.
-    pub fn view(&mut self) -> Container<Message> {
+    pub fn view(&mut self) -> Container<'_, Message> {
         // Create a status text label from the current status
-        let status_text = Text::new(format!(
-            "{}", &self.status
-        )).size(20);
+        let status_text = Text::new(&self.status).size(20);
 
         // Create a text label that displays the SCPI command that will be sent to the device
-        let command_text = Text::new(format!(
-            "{}", &self.current_command
-        )).size(40);
+        let command_text = Text::new(&self.current_command).size(40);
+        // Explain why the command cannot be sent, if the entered values are invalid
+        let error_text = Text::new(self.command_error.as_deref().unwrap_or_default())
+            .color(Color::from_rgb(0.8, 0.0, 0.0));
 
         // Create a submit button that sends a message to the application root containing the selected SCPI command
-        let submit_button = ui_button(&mut self.send_button_state, "Submit".into(), styles::Button::Submit)
-                .on_press(Message::SendCommand(self.current_command.clone()));
-
-        // Create a textbox for freetext entry if the command argument contains a "<TXT>"
-        // if not, add an empty row to avoid re-arranging the UI whenever this switches
-        let freetext =
-            if self.argument_selection.contains("<TXT>") {
-                Row::new().push(
-                    TextInput::new(&mut self.freetext_state, "TXT", &self.freetext, |txt| { Message::Command(CommandMessage::FreetextEntered(txt)) })
-                        .width(Length::Units(150))
-                        .style(styles::Textbox::Freetext)
-                        .padding(5)
-                        .on_submit(Message::SendCommand(self.current_command.clone()))
-                )
-            } else {
-                Row::new().push(iced::widget::Space::new(Length::Units(150), Length::Units(30)))
-            };
+        // While a command is in flight or invalid, the button is disabled so nothing wrong is sent and commands do not pile up
+        let request = self.get_request();
+        let sendable = !self.pending && self.command_error.is_none();
+        let mut submit_button = ui_button(&mut self.send_button_state, "Submit".into(), styles::Button::Submit);
+        if sendable {
+            submit_button = submit_button.on_press(Message::SendCommand(request.clone()));
+        }
+
+        // Create a labelled textbox for every placeholder of the command like "<FREQ>"
+        // if there are none, add an empty row to avoid re-arranging the UI whenever this switches
+        // The accepted values of a typed parameter are shown next to it
+        let mut inputs = Column::new().spacing(10).align_items(Alignment::Center);
+        if self.placeholders.is_empty() {
+            inputs = inputs.push(iced::widget::Space::new(Length::Units(150), Length::Units(30)));
+        }
+        for placeholder in self.placeholders.iter_mut() {
+            let parameter = self.command_selection.parameters.get(&placeholder.name);
+            let placeholder_text = parameter.and_then(|p| p.unit.as_deref()).unwrap_or(&placeholder.name);
+            let name = placeholder.name.clone();
+            let mut input = TextInput::new(&mut placeholder.state, placeholder_text, &placeholder.value, move |value| { Message::Command(CommandMessage::PlaceholderEntered(name.clone(), value)) })
+                .width(Length::Units(150))
+                .style(styles::Textbox::Freetext)
+                .padding(5);
+            if sendable {
+                input = input.on_submit(Message::SendCommand(request.clone()));
+            }
+            inputs = inputs.push(
+                Row::new()
+                    .spacing(10)
+                    .align_items(Alignment::Center)
+                    .push(Text::new(devices::command::placeholder_label(&self.command_selection, &placeholder.name)).size(16).width(Length::Units(120)))
+                    .push(input)
+                    .push(Text::new(parameter.map(|p| p.hint()).unwrap_or_default()).size(16).width(Length::Units(200)))
+            );
+        }
+
+        // Offer a hex view of binary replies instead of printing them as text
+        let mut binary_view = Column::new().spacing(10).align_items(Alignment::Center);
+        if let Some(data) = &self.binary_reply {
+            binary_view = binary_view.push(
+                Checkbox::new(self.show_hex, "Show hex", |show| Message::Command(CommandMessage::HexViewToggled(show)))
+            );
+            if self.show_hex {
+                binary_view = binary_view.push(
+                    Scrollable::new(&mut self.hex_view_state)
+                        .height(Length::Units(150))
+                        .push(Text::new(block::hex_dump(data, HEX_VIEW_LIMIT)).size(14))
+                );
+            }
+        }
 
         // Build the container from the above widgets and add the appropriate dropdown menus
         Container::new(
             Column::new()
                 .align_items(Alignment::Center)
                 .push(status_text)
+                .push(binary_view)
                 .spacing(20)
                 .push(command_text)
+                .push(error_text)
                 .spacing(20)
                 .push(
                     Row::new().
@@ -105,7 +198,7 @@ impl CommandScreen {
                             PickList::new(
                                 &mut self.channel_list,
                                 &self.channels,
-                                Some(self.channel_selection.clone()),
+                                Some(self.channel_selection),
                                 |chan| Message::Command(CommandMessage::ChannelSelected(chan)),
                             ).width(Length::Units(100))
                         ).spacing(20)
@@ -118,9 +211,13 @@ impl CommandScreen {
                             ).width(Length::Units(200))
                         )
                 )
-                .push(freetext)
+                .push(inputs)
                 .push(
-                    submit_button
+                    Row::new()
+                        .spacing(20)
+                        .align_items(Alignment::Center)
+                        .push(submit_button)
+                        .push(Checkbox::new(self.check_errors, "Check errors", |check| Message::Command(CommandMessage::CheckErrorsToggled(check))))
                 )
         )
             .width(Length::Fill)
@@ -133,27 +230,123 @@ impl CommandScreen {
     pub fn update(&mut self, message: CommandMessage) {
         match message {
             CommandSelected(cmd) => {
-                println!("{}", &cmd.name);
                 self.command_selection = cmd.clone();
                 self.arguments = cmd.values.clone();
-                self.argument_selection = self.arguments[0].clone();
+                self.argument_selection = self.arguments.first().cloned().unwrap_or_default();
                 self.argument_list = pick_list::State::default();
+                // Start over with the defaults of the new command
+                self.placeholders.clear();
+                self.update_placeholders();
             }
             ChannelSelected(chan) => self.channel_selection = chan,
-            ArgumentSelected(arg) => self.argument_selection = arg,
-            FreetextEntered(txt) => self.freetext = txt,
+            ArgumentSelected(arg) => {
+                self.argument_selection = arg;
+                self.update_placeholders();
+            }
+            PlaceholderEntered(name, value) => {
+                if let Some(placeholder) = self.placeholders.iter_mut().find(|p| p.name == name) {
+                    placeholder.value = value;
+                }
+            }
+            HexViewToggled(show) => self.show_hex = show,
+            CheckErrorsToggled(check) => self.check_errors = check,
         }
-        self.current_command = self.get_command();
+        self.update_command();
     }
 
     /// Returns a complete SCPI command from the selected/entered values on the screen
-    pub fn get_command(&self) -> String {
-        devices::command::make_scpi_command(self.command_selection.clone(), self.channel_selection, &self.argument_selection, &self.freetext).unwrap()
+    /// Returns an error if the entered values are not valid for the command.
+    pub fn get_command(&self) -> Result<String, String> {
+        let inputs: HashMap<String, String> = self.placeholders.iter()
+            .map(|p| (p.name.clone(), p.value.clone()))
+            .collect();
+        devices::command::make_scpi_command(self.command_selection.clone(), self.channel_selection, &self.argument_selection, &inputs)
+    }
+
+    /// Rebuild the displayed command, or show why it cannot be built
+    fn update_command(&mut self) {
+        match self.get_command() {
+            Ok(command) => {
+                self.current_command = command;
+                self.command_error = None;
+            }
+            Err(e) => {
+                // Show the command with its placeholders while the entered values are invalid
+                self.current_command = format!("{}{}", self.command_selection.scpi.replace("<CH>", &self.channel_selection.to_string()), self.argument_selection);
+                self.command_error = Some(e);
+            }
+        }
+    }
+
+    /// Create an input for every placeholder of the selected command and argument
+    /// Values already entered for a placeholder of the same name are kept, new ones start with the default value.
+    fn update_placeholders(&mut self) {
+        let template = format!("{}{}", self.command_selection.scpi, self.argument_selection);
+        let mut previous = std::mem::take(&mut self.placeholders);
+        for name in devices::command::placeholders(&template) {
+            let input = match previous.iter().position(|p| p.name == name) {
+                Some(index) => previous.swap_remove(index),
+                None => PlaceholderInput {
+                    value: self.command_selection.parameters.get(&name)
+                        .and_then(|parameter| parameter.default.clone())
+                        .unwrap_or_default(),
+                    name,
+                    ..Default::default()
+                },
+            };
+            self.placeholders.push(input);
+        }
+    }
+
+    /// Returns the [`networking::Request`] for the currently selected complete command
+    /// The error queue is checked afterwards if enabled.
+    pub fn get_request(&self) -> networking::Request {
+        let mut request = devices::command::make_request(&self.command_selection, self.current_command.clone());
+        if self.check_errors {
+            request.error_query = Some(self.error_query.clone());
+        }
+        request
     }
 
     /// Set the status text that is displayed above the scpi command
     pub fn set_status_text(&mut self, status: String) {
         self.status = status
     }
+
+    /// Unit numeric replies to the selected command are shown in, to be passed to [`CommandScreen::set_reply`]
+    pub fn reply_unit(&self) -> Option<String> {
+        self.command_selection.reply_unit().map(String::from)
+    }
+
+    /// Show the [`Reply`] to the last command, or the error that occurred while sending it
+    /// Numbers are shown in `unit`, the unit of the command that has been sent.
+    pub fn set_reply(&mut self, result: Result<Reply, String>, unit: Option<&str>) {
+        self.binary_reply = None;
+        self.status = match result {
+            Ok(reply) => {
+                let mut status = match reply.response {
+                    Some(Response::Text(text)) => {
+                        format!("Reply: {}", devices::number::format_reply(&text, unit))
+                    }
+                    Some(Response::Block(data)) => {
+                        let status = format!("Binary reply: {} bytes", data.len());
+                        self.binary_reply = Some(data);
+                        status
+                    }
+                    None => format!("Sent {} bytes", reply.sent),
+                };
+                if !reply.errors.is_empty() {
+                    status.push_str(&format!("\nDevice errors: {}", reply.errors.join("; ")));
+                }
+                status
+            }
+            Err(e) => format!("Sending command failed! Please check connection.\nError: {}", e),
+        }
+    }
+
+    /// Mark the screen as waiting for a reply (`true`) or ready to send the next command (`false`)
+    pub fn set_pending(&mut self, pending: bool) {
+        self.pending = pending
+    }
 }
 
@@ -0,0 +1,272 @@
+//! Provides an editor for the commands of the config file that saves them back to it
+
+use crate::{styles::{self, ui_button}, messages::{*, EditorMessage::*}};
+use instrument_core::devices::{self, validation::{self, Diagnostic}, writer::{self, EditedCommand}};
+use iced::{button, scrollable, text_input, alignment::Alignment, Button, Checkbox, Color, Column, Container, Length, Row, Scrollable, Text, TextInput};
+
+/// A value of the selected command and the states of its textbox and remove button
+#[derive(Default)]
+struct ValueInput {
+    state: text_input::State,
+    remove_button_state: button::State,
+}
+
+/// Commands being edited and the states of the widgets of the editor screen
+#[derive(Default)]
+pub struct EditorScreen {
+    /// Path of the config file the commands are saved to
+    file: String,
+    /// Index of the `[[devices]]` entry in `file`, [`None`] for a file with a single `[device]`
+    entry: Option<usize>,
+    /// Whether the device includes command sets, so it may do without commands of its own
+    has_includes: bool,
+    /// Commands written in the config file, as edited so far
+    commands: Vec<EditedCommand>,
+    /// Index of the command shown in the form
+    selected: usize,
+    /// States of the buttons selecting a command
+    command_button_states: Vec<button::State>,
+    /// State of the scrollable list of commands
+    list_state: scrollable::State,
+    name_state: text_input::State,
+    scpi_state: text_input::State,
+    /// States of the widgets for each value of the selected command
+    value_inputs: Vec<ValueInput>,
+    add_value_button_state: button::State,
+    add_button_state: button::State,
+    delete_button_state: button::State,
+    up_button_state: button::State,
+    down_button_state: button::State,
+    save_button_state: button::State,
+    revert_button_state: button::State,
+    /// Problems found in the edited commands, they have to be fixed before saving
+    diagnostics: Vec<Diagnostic>,
+    /// Whether there are changes that have not been saved yet
+    changed: bool,
+    /// Status message
+    status: String,
+}
+
+impl EditorScreen {
+    /// Creates a new editor for the commands written in the config file `config` was loaded from
+    pub fn from(config: &devices::Configuration) -> Self {
+        let mut editor_screen = Self {
+            file: config.file.clone(),
+            entry: config.entry,
+            has_includes: !config.device.include.is_empty(),
+            ..Default::default()
+        };
+        editor_screen.load();
+        editor_screen
+    }
+
+    /// Read the commands from the config file again, discarding all changes
+    /// The selection is kept as far as possible.
+    fn load(&mut self) {
+        match devices::Configuration::load_commands(&self.file, self.entry) {
+            Ok(commands) => {
+                self.commands = commands.into_iter()
+                    .enumerate()
+                    .map(|(index, command)| EditedCommand { origin: Some(index), command })
+                    .collect();
+                self.status = format!("Editing {}", self.file);
+            }
+            Err(e) => {
+                self.commands.clear();
+                self.status = format!("The commands could not be loaded: {}", e);
+            }
+        }
+        self.changed = false;
+        self.select(self.selected);
+        self.validate();
+    }
+
+    /// Display the list of commands, a form for the selected one and the problems found in them
+    pub fn view(&mut self) -> Container<'_, Message> {
+        // List the commands by name, the selected one is highlighted
+        let selected = self.selected;
+        let list = self.commands.iter().zip(self.command_button_states.iter_mut()).enumerate().fold(
+            Scrollable::new(&mut self.list_state).spacing(4).height(Length::Units(250)).width(Length::Units(220)),
+            |list, (index, (edited, state))| {
+                let name = if edited.command.name.is_empty() { "(unnamed)" } else { &edited.command.name };
+                list.push(
+                    Button::new(state, Text::new(name).size(16))
+                        .width(Length::Fill)
+                        .on_press(Message::Editor(CommandSelected(index)))
+                        .style(if index == selected { styles::Button::ActiveControl } else { styles::Button::Control })
+                )
+            },
+        );
+        let has_selection = selected < self.commands.len();
+        let list_buttons = Row::new()
+            .spacing(4)
+            .push(small_button(&mut self.add_button_state, "Add", true, CommandAdded))
+            .push(small_button(&mut self.delete_button_state, "Delete", has_selection, CommandDeleted))
+            .push(small_button(&mut self.up_button_state, "Up", selected > 0 && has_selection, CommandMoved(true)))
+            .push(small_button(&mut self.down_button_state, "Down", selected + 1 < self.commands.len(), CommandMoved(false)));
+
+        // The form for the selected command
+        let mut form = Column::new().spacing(10).width(Length::Units(400));
+        if let Some(edited) = self.commands.get(selected) {
+            let command = &edited.command;
+            form = form
+                .push(labelled("Name", TextInput::new(&mut self.name_state, "Name", &command.name, |name| Message::Editor(NameEntered(name))).padding(5)))
+                .push(labelled("SCPI", TextInput::new(&mut self.scpi_state, ":SOURce<CH>:FREQ", &command.scpi, |scpi| Message::Editor(ScpiEntered(scpi))).padding(5)))
+                .push(Checkbox::new(command.channel, "Per channel (<CH>)", |channel| Message::Editor(ChannelToggled(channel))));
+            for (index, (value, input)) in command.values.iter().zip(self.value_inputs.iter_mut()).enumerate() {
+                form = form.push(
+                    Row::new()
+                        .spacing(10)
+                        .align_items(Alignment::Center)
+                        .push(Text::new(if index == 0 { "Values" } else { "" }).size(16).width(Length::Units(60)))
+                        .push(TextInput::new(&mut input.state, "(empty)", value, move |value| Message::Editor(ValueEntered(index, value))).padding(5))
+                        .push(small_button(&mut input.remove_button_state, "-", true, ValueRemoved(index)))
+                );
+            }
+            form = form.push(small_button(&mut self.add_value_button_state, "Add value", true, ValueAdded));
+        }
+
+        // Problems have to be fixed before the commands can be saved
+        let problems = self.diagnostics.iter().fold(
+            Column::new().spacing(4),
+            |column, diagnostic| column.push(Text::new(&diagnostic.message).size(16).color(Color::from_rgb(0.8, 0.0, 0.0))),
+        );
+        let mut save_button = ui_button(&mut self.save_button_state, "Save".into(), styles::Button::Submit);
+        let mut revert_button = ui_button(&mut self.revert_button_state, "Revert".into(), styles::Button::Cancel);
+        if self.changed {
+            revert_button = revert_button.on_press(Message::Editor(Revert));
+            if self.diagnostics.is_empty() {
+                save_button = save_button.on_press(Message::Editor(Save));
+            }
+        }
+
+        Container::new(
+            Column::new()
+                .align_items(Alignment::Center)
+                .spacing(20)
+                .push(Text::new(&self.status).size(20))
+                .push(
+                    Row::new()
+                        .spacing(20)
+                        .push(Column::new().spacing(10).push(list).push(list_buttons))
+                        .push(form)
+                )
+                .push(problems)
+                .push(Row::new().spacing(20).push(save_button).push(revert_button))
+        )
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+    }
+
+    /// Update the commands according to generated [`EditorMessage`]s
+    /// Saving is done by [`EditorScreen::save`], so the application root can reload the config afterwards.
+    pub fn update(&mut self, message: EditorMessage) {
+        let selected = self.selected;
+        match message {
+            CommandSelected(index) => {
+                self.select(index);
+                return;
+            }
+            Revert => {
+                self.load();
+                return;
+            }
+            CommandAdded => {
+                let index = (selected + 1).min(self.commands.len());
+                let command = devices::Command { name: "New command".into(), values: vec![String::new()], ..Default::default() };
+                self.commands.insert(index, EditedCommand { origin: None, command });
+                self.select(index);
+            }
+            CommandDeleted if selected < self.commands.len() => {
+                self.commands.remove(selected);
+                self.select(selected.min(self.commands.len().saturating_sub(1)));
+            }
+            CommandMoved(true) if selected > 0 && selected < self.commands.len() => {
+                self.commands.swap(selected, selected - 1);
+                self.select(selected - 1);
+            }
+            CommandMoved(false) if selected + 1 < self.commands.len() => {
+                self.commands.swap(selected, selected + 1);
+                self.select(selected + 1);
+            }
+            Save | CommandDeleted | CommandMoved(_) => return,
+            _ => {
+                let command = match self.commands.get_mut(selected) {
+                    Some(edited) => &mut edited.command,
+                    None => return,
+                };
+                match message {
+                    NameEntered(name) => command.name = name,
+                    ScpiEntered(scpi) => command.scpi = scpi,
+                    ChannelToggled(channel) => command.channel = channel,
+                    ValueEntered(index, value) => if let Some(old) = command.values.get_mut(index) { *old = value },
+                    ValueAdded => command.values.push(String::new()),
+                    ValueRemoved(index) if index < command.values.len() => { command.values.remove(index); }
+                    _ => return,
+                }
+                self.value_inputs.resize_with(command.values.len(), Default::default);
+            }
+        }
+        self.changed = true;
+        self.validate();
+    }
+
+    /// Write the commands to the config file if they are valid, returns whether they have been saved
+    pub fn save(&mut self) -> bool {
+        if !self.diagnostics.is_empty() {
+            return false;
+        }
+        match writer::save_commands(&self.file, self.entry, &self.commands) {
+            Ok(()) => {
+                self.load();
+                self.status = format!("Saved to {}", self.file);
+                true
+            }
+            Err(e) => {
+                self.status = format!("Saving failed: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Read the commands again after the config file has been loaded anew, unless there are unsaved changes
+    pub fn reload(&mut self, config: &devices::Configuration) {
+        self.has_includes = !config.device.include.is_empty();
+        if !self.changed {
+            self.load();
+        }
+    }
+
+    /// Show command number `index` in the form
+    fn select(&mut self, index: usize) {
+        self.selected = index.min(self.commands.len().saturating_sub(1));
+        self.command_button_states.resize_with(self.commands.len(), Default::default);
+        let values = self.commands.get(self.selected).map_or(0, |edited| edited.command.values.len());
+        self.value_inputs.resize_with(values, Default::default);
+    }
+
+    /// Check the edited commands for the same mistakes as the config file is checked for when it is loaded
+    fn validate(&mut self) {
+        self.diagnostics = validation::validate_commands(
+            &self.commands.iter().map(|edited| edited.command.clone()).collect::<Vec<_>>(), &self.file, "");
+        if self.commands.is_empty() && !self.has_includes {
+            self.diagnostics.push(Diagnostic::new(&self.file, None, "Add at least one command".into()));
+        }
+    }
+}
+
+/// A textbox with a label in front of it
+fn labelled<'a>(label: &str, input: TextInput<'a, Message>) -> Row<'a, Message> {
+    Row::new()
+        .spacing(10)
+        .align_items(Alignment::Center)
+        .push(Text::new(label).size(16).width(Length::Units(60)))
+        .push(input)
+}
+
+/// A small control button sending `message` to the editor if `enabled`
+fn small_button<'a>(state: &'a mut button::State, label: &str, enabled: bool, message: EditorMessage) -> Button<'a, Message> {
+    let button = Button::new(state, Text::new(label).size(16)).padding(6).style(styles::Button::Control);
+    if enabled { button.on_press(Message::Editor(message)) } else { button }
+}
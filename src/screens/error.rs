@@ -0,0 +1,47 @@
+//! Provides a screen listing the problems found in the config file, shown instead of the other screens
+use crate::{styles::{self, ui_button}, messages::*};
+use instrument_core::devices::validation::Diagnostic;
+use iced::{button, scrollable, alignment::Alignment, Color, Column, Container, Length, Scrollable, Text};
+
+/// Problems found in the config file and the states of the widgets listing them
+#[derive(Default)]
+pub struct ErrorScreen {
+    /// Descriptions of the problems including file and line
+    errors: Vec<String>,
+    /// State of the scrollable list of problems
+    scroll_state: scrollable::State,
+    /// State of the button that loads the config file again
+    reload_button_state: button::State,
+}
+
+impl ErrorScreen {
+    /// Creates a new error screen listing `diagnostics`
+    pub fn from(diagnostics: &[Diagnostic]) -> Self {
+        Self {
+            errors: diagnostics.iter().map(Diagnostic::to_string).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Display the list of problems and a button to reload the config after fixing them
+    pub fn view(&mut self) -> Container<'_, Message> {
+        let errors = self.errors.iter().fold(
+            Scrollable::new(&mut self.scroll_state).spacing(8).height(Length::Units(300)),
+            |list, error| list.push(Text::new(error).size(16).color(Color::from_rgb(0.8, 0.0, 0.0))),
+        );
+        Container::new(
+            Column::new()
+                .align_items(Alignment::Center)
+                .spacing(20)
+                .push(Text::new("The config file could not be loaded").size(30))
+                .push(errors)
+                .push(ui_button(&mut self.reload_button_state, "Reload".into(), styles::Button::Submit)
+                    .on_press(Message::ReloadConfig))
+        )
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(20)
+            .center_x()
+            .center_y()
+    }
+}
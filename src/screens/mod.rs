@@ -5,5 +5,26 @@ pub mod settings;
 /// Command selection screen where users build an SCPI command and send it to the device
 pub mod command_selection;
 
+/// Terminal screen where users type SCPI commands directly
+pub mod terminal;
+
+/// Sequence screen where users run the sequences of the config and follow the progress of each step
+pub mod sequences;
+
+/// Script screen where users edit Rhai scripts and run them against the device
+pub mod scripts;
+
+/// Config editor screen where users add, edit, reorder and delete commands and save them to the config file
+pub mod editor;
+
 /// Toolbar that is always displayed at the top of the screen and allows for switching between the other screens
-pub mod toolbar;
\ No newline at end of file
+pub mod toolbar;
+
+/// Error screen listing the problems found in the config file
+pub mod error;
+
+/// Picker for the active device profile when the config holds several devices
+pub mod profiles;
+
+/// Picker for the config file or directory, shown if none has been given on the command line or used before
+pub mod open;
@@ -0,0 +1,126 @@
+//! Provides a screen to pick the config file or directory, shown if none has been given or used before
+use crate::{styles::{self, ui_button}, messages::{*, OpenMessage::*}};
+use iced::{button, scrollable, text_input, alignment::Alignment, Button, Color, Column, Container, Length, Row, Scrollable, Text, TextInput};
+use std::path::{Path, PathBuf};
+
+/// Path of the config to open, the entries of its directory and the states of the widgets
+#[derive(Default)]
+pub struct OpenScreen {
+    /// Config file or directory typed or picked so far
+    path: String,
+    path_state: text_input::State,
+    /// Sub-directories and config files of the directory shown, each with the state of its button
+    entries: Vec<(PathBuf, button::State)>,
+    list_state: scrollable::State,
+    up_button_state: button::State,
+    open_button_state: button::State,
+    /// Why the picked config could not be opened
+    error: Option<String>,
+}
+
+impl OpenScreen {
+    /// Creates a new picker listing the current directory
+    pub fn new() -> Self {
+        let mut screen = Self::default();
+        let directory = std::env::current_dir().unwrap_or_default();
+        screen.show(&directory);
+        screen
+    }
+
+    /// Display the path input and the entries of the directory shown
+    pub fn view(&mut self) -> Container<'_, Message> {
+        let list = self.entries.iter_mut().fold(
+            Scrollable::new(&mut self.list_state).spacing(4).height(Length::Units(300)).width(Length::Fill),
+            |list, (path, state)| {
+                let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                let label = if path.is_dir() { format!("{}/", name) } else { name };
+                list.push(
+                    Button::new(state, Text::new(label).size(16))
+                        .width(Length::Fill)
+                        .on_press(Message::Open(EntrySelected(path.clone())))
+                )
+            },
+        );
+
+        let mut column = Column::new()
+            .spacing(10)
+            .push(Text::new("Open a config file or a directory of config files").size(24))
+            .push(
+                Row::new()
+                    .spacing(10)
+                    .align_items(Alignment::Center)
+                    .push(ui_button(&mut self.up_button_state, "Up".into(), styles::Button::Control)
+                        .on_press(Message::Open(Up)))
+                    .push(TextInput::new(&mut self.path_state, "Path of the config", &self.path, |path| Message::Open(PathEntered(path)))
+                        .padding(5)
+                        .on_submit(Message::Open(Open))
+                        .style(styles::Textbox::Freetext))
+                    .push(ui_button(&mut self.open_button_state, "Open".into(), styles::Button::Submit)
+                        .on_press(Message::Open(Open)))
+            )
+            .push(list);
+        if let Some(error) = &self.error {
+            column = column.push(Text::new(error).size(16).color(Color::from_rgb(0.8, 0.0, 0.0)));
+        }
+        Container::new(column)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(20)
+    }
+
+    /// Update the path and the entries listed according to generated [`OpenMessage`]s
+    /// Opening is done by the application root, which takes the path from [`OpenScreen::path`].
+    pub fn update(&mut self, message: OpenMessage) {
+        match message {
+            PathEntered(path) => {
+                self.path = path;
+                if Path::new(&self.path).is_dir() {
+                    let directory = PathBuf::from(&self.path);
+                    self.entries = list(&directory);
+                }
+            }
+            EntrySelected(path) if path.is_dir() => self.show(&path),
+            EntrySelected(path) => self.path = path.to_string_lossy().into_owned(),
+            Up => {
+                let path = PathBuf::from(&self.path);
+                let directory = if path.is_dir() { path.as_path() } else { path.parent().unwrap_or(&path) };
+                if let Some(parent) = directory.parent() {
+                    let parent = parent.to_path_buf();
+                    self.show(&parent);
+                }
+            }
+            Open => {}
+        }
+        self.error = None;
+    }
+
+    /// The config file or directory to open
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Show why the picked config could not be opened
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    /// Pick `directory` and list its entries
+    fn show(&mut self, directory: &Path) {
+        self.path = directory.to_string_lossy().into_owned();
+        self.entries = list(directory);
+    }
+}
+
+/// Returns the sub-directories and config files of `directory`, directories first and each sorted by name
+/// Hidden entries are left out.
+fn list(directory: &Path) -> Vec<(PathBuf, button::State)> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(directory)
+        .map(|entries| entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect())
+        .unwrap_or_default();
+    paths.retain(|path| {
+        let hidden = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        !hidden && (path.is_dir() || path.extension().is_some_and(|extension| extension == "toml"))
+    });
+    paths.sort_by_key(|path| (!path.is_dir(), path.file_name().map(|name| name.to_ascii_lowercase())));
+    paths.into_iter().map(|path| (path, button::State::default())).collect()
+}
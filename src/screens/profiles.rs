@@ -0,0 +1,41 @@
+//! Provides a picker for the active device profile when the config holds several devices
+use crate::{styles::{self, ui_button}, messages::*};
+use iced::{button, pick_list, alignment::Alignment, Color, Column, Container, Length, PickList, Row, Text};
+
+/// Dropdown and button states of the profile picker
+#[derive(Default)]
+pub struct ProfilePicker {
+    /// State of the picklist for profiles
+    profile_list_state: pick_list::State<String>,
+    /// State of the button connecting to all devices at once
+    connect_all_button_state: button::State,
+}
+
+impl ProfilePicker {
+    /// Display a dropdown to pick the active profile out of `names`, a button to connect to all of them
+    /// and the connection status of each profile from `statuses`
+    pub fn view<'a>(&'a mut self, names: &'a [String], active: usize, statuses: Vec<String>) -> Container<'a, Message> {
+        let picker = PickList::new(&mut self.profile_list_state,
+                                   names,
+                                   names.get(active).cloned(),
+                                   Message::ProfileSelected)
+            .width(Length::Units(200));
+        let connect_all = ui_button(&mut self.connect_all_button_state, "Connect all".into(), styles::Button::Submit)
+            .width(Length::Units(120))
+            .on_press(Message::ConnectAll);
+
+        let overview = names.iter().zip(statuses).fold(
+            Column::new().spacing(4).align_items(Alignment::Center),
+            |column, (name, status)| column.push(Text::new(format!("{}: {}", name, status)).size(16).color(Color::BLACK)),
+        );
+        Container::new(
+            Column::new()
+                .align_items(Alignment::Center)
+                .spacing(10)
+                .push(Row::new().spacing(20).align_items(Alignment::Center).push(picker).push(connect_all))
+                .push(overview)
+        )
+            .width(Length::Fill)
+            .center_x()
+    }
+}
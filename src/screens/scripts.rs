@@ -0,0 +1,267 @@
+//! Provides an editor for Rhai scripts driving the device, running them with their output shown in a console
+use crate::{styles::{self, ui_button}, messages::{*, ScriptMessage::*}, runner::{self, ScriptRun}};
+use iced::{button, scrollable, text_input, alignment::Alignment, Color, Column, Container, Length, Row, Scrollable, Subscription, Text, TextInput};
+use instrument_core::{devices, networking::SharedConnection, script};
+use std::sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc};
+
+/// Highest number of lines kept in the output console
+const MAX_LINES: usize = 1000;
+
+/// Number of scripts started so far in any profile, telling the runs apart even if the profiles are reordered
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// A line of the script being edited
+#[derive(Default)]
+struct Line {
+    text: String,
+    state: text_input::State,
+}
+
+/// Script, output and the states of the widgets of the script screen
+pub struct ScriptScreen {
+    /// Config whose commands the script uses
+    config: devices::Configuration,
+    /// Path of the script file
+    path: String,
+    path_state: text_input::State,
+    open_button_state: button::State,
+    save_button_state: button::State,
+    /// Lines of the script, each edited in an input of its own
+    lines: Vec<Line>,
+    editor_state: scrollable::State,
+    /// Lines written by the scripts run so far, paired with whether they report an error
+    output: Vec<(String, bool)>,
+    output_state: scrollable::State,
+    run_button_state: button::State,
+    cancel_button_state: button::State,
+    clear_button_state: button::State,
+    /// Number of the running script and the flag cancelling it, [`None`] if no script is running
+    running: Option<(usize, Arc<AtomicBool>)>,
+}
+
+impl ScriptScreen {
+    /// Creates a new script screen for the commands of `config`
+    pub fn from(config: &devices::Configuration) -> Self {
+        Self {
+            config: config.clone(),
+            path: String::new(),
+            path_state: text_input::State::default(),
+            open_button_state: button::State::default(),
+            save_button_state: button::State::default(),
+            lines: vec![Line::default()],
+            editor_state: scrollable::State::default(),
+            output: Vec::new(),
+            output_state: scrollable::State::default(),
+            run_button_state: button::State::default(),
+            cancel_button_state: button::State::default(),
+            clear_button_state: button::State::default(),
+            running: None,
+        }
+    }
+
+    /// Use the commands of `config` for the next run after it has been loaded again
+    pub fn reload(&mut self, config: &devices::Configuration) {
+        self.config = config.clone();
+    }
+
+    /// Display the file controls, the script and the output console
+    pub fn view(&mut self) -> Container<'_, Message> {
+        let editor = self.lines.iter_mut().enumerate().fold(
+            Scrollable::new(&mut self.editor_state).height(Length::Units(220)).width(Length::Fill),
+            |editor, (index, line)| {
+                editor.push(
+                    Row::new()
+                        .align_items(Alignment::Center)
+                        .push(Text::new(format!("{:>3}", index + 1)).size(14).width(Length::Units(32)))
+                        .push(TextInput::new(&mut line.state, "", &line.text, move |text| Message::Script(LineChanged(index, text)))
+                            .on_submit(Message::Script(LineSubmitted(index)))
+                            .size(16)
+                            .padding(2))
+                )
+            },
+        );
+        let output = self.output.iter().fold(
+            Scrollable::new(&mut self.output_state).spacing(2).height(Length::Units(120)).width(Length::Fill),
+            |output, (line, error)| {
+                let color = if *error { Color::from_rgb(0.8, 0.0, 0.0) } else { Color::BLACK };
+                output.push(Text::new(line).size(14).color(color))
+            },
+        );
+
+        let mut run_button = ui_button(&mut self.run_button_state, "Run".into(), styles::Button::Submit);
+        let mut cancel_button = ui_button(&mut self.cancel_button_state, "Cancel".into(), styles::Button::Cancel);
+        if self.running.is_some() {
+            cancel_button = cancel_button.on_press(Message::Script(Cancel));
+        } else {
+            run_button = run_button.on_press(Message::Script(Run));
+        }
+
+        Container::new(
+            Column::new()
+                .spacing(10)
+                .push(
+                    Row::new()
+                        .spacing(10)
+                        .align_items(Alignment::Center)
+                        .push(TextInput::new(&mut self.path_state, "Path of the script, e.g. scripts/sweep.rhai", &self.path, |path| Message::Script(PathEntered(path)))
+                            .padding(5)
+                            .style(styles::Textbox::Freetext))
+                        .push(ui_button(&mut self.open_button_state, "Open".into(), styles::Button::Control).on_press(Message::Script(Open)))
+                        .push(ui_button(&mut self.save_button_state, "Save".into(), styles::Button::Control).on_press(Message::Script(Save)))
+                )
+                .push(editor)
+                .push(
+                    Row::new()
+                        .spacing(10)
+                        .align_items(Alignment::Center)
+                        .push(run_button)
+                        .push(cancel_button)
+                        .push(ui_button(&mut self.clear_button_state, "Clear".into(), styles::Button::Control)
+                            .on_press(Message::Script(Clear)))
+                        .push(Text::new("Enter: new line, Backspace on an empty line: remove it, Up/Down: move between lines").size(14))
+                )
+                .push(output)
+        )
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(10)
+    }
+
+    /// Edit, load and save the script and start or cancel it according to generated [`ScriptMessage`]s
+    /// The script itself is run by the subscription returned by [`ScriptScreen::subscription`].
+    pub fn update(&mut self, message: ScriptMessage) {
+        match message {
+            PathEntered(path) => self.path = path,
+            Open => match std::fs::read_to_string(self.path.trim()) {
+                Ok(text) => {
+                    self.lines = text.lines().map(|text| Line { text: text.to_string(), ..Default::default() }).collect();
+                    if self.lines.is_empty() {
+                        self.lines.push(Line::default());
+                    }
+                }
+                Err(e) => self.write(format!("{} could not be read: {}", self.path.trim(), e), true),
+            },
+            Save => {
+                let result = match self.path.trim() {
+                    "" => Err("Enter the path of the script to save it".to_string()),
+                    path => std::fs::write(path, format!("{}\n", self.source())).map_err(|e| format!("{} could not be saved: {}", path, e)),
+                };
+                match result {
+                    Ok(()) => self.write(format!("Saved {}", self.path.trim()), false),
+                    Err(e) => self.write(e, true),
+                }
+            }
+            LineChanged(index, text) => self.change_line(index, text),
+            LineSubmitted(index) => {
+                // The new line keeps the indentation of the one above
+                let indentation: String = self.lines[index].text.chars().take_while(|c| c.is_whitespace()).collect();
+                self.lines.insert(index + 1, Line { text: indentation, ..Default::default() });
+                self.focus(index + 1);
+            }
+            FocusPrevious => {
+                if let Some(index) = self.focused().filter(|index| *index > 0) {
+                    self.focus(index - 1);
+                }
+            }
+            FocusNext => {
+                if let Some(index) = self.focused().filter(|index| index + 1 < self.lines.len()) {
+                    self.focus(index + 1);
+                }
+            }
+            Run => {
+                if self.running.is_none() {
+                    let id = RUNS.fetch_add(1, Ordering::Relaxed);
+                    self.running = Some((id, Arc::new(AtomicBool::new(false))));
+                    let name = if self.path.trim().is_empty() { "script" } else { self.path.trim() };
+                    self.write(format!("Running {}...", name), false);
+                }
+            }
+            Cancel => {
+                if let Some((_, cancel)) = &self.running {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            }
+            Clear => self.output.clear(),
+        }
+    }
+
+    /// Subscription running the script of profile number `profile` on `connection` while it is running
+    pub fn subscription(&self, profile: usize, connection: SharedConnection) -> Subscription<Message> {
+        match &self.running {
+            Some((id, cancel)) => runner::script_run(ScriptRun {
+                profile,
+                id: *id,
+                source: self.source(),
+                config: self.config.clone(),
+                connection,
+                error_query: self.config.device.check_errors.then(|| self.config.device.error_query.clone()),
+                cancel: cancel.clone(),
+            }),
+            None => Subscription::none(),
+        }
+    }
+
+    /// Show a line written by the running script
+    pub fn add_output(&mut self, line: String) {
+        self.write(line, false);
+    }
+
+    /// Show how the running script has ended
+    pub fn set_finished(&mut self, result: Result<(), String>) {
+        self.running = None;
+        match result {
+            Ok(()) => self.write("Script finished".into(), false),
+            Err(e) if e == script::CANCELLED => self.write("Script cancelled".into(), true),
+            Err(e) => self.write(e, true),
+        }
+    }
+
+    /// The script as a single text
+    fn source(&self) -> String {
+        self.lines.iter().map(|line| line.text.as_str()).collect::<Vec<&str>>().join("\n")
+    }
+
+    /// Apply an edit of the line at `index`, pasted text is split into lines
+    fn change_line(&mut self, index: usize, text: String) {
+        if text.contains('\n') {
+            let mut pasted: Vec<Line> = text.lines().map(|text| Line { text: text.to_string(), ..Default::default() }).collect();
+            if pasted.is_empty() {
+                pasted.push(Line::default());
+            }
+            let last = index + pasted.len() - 1;
+            self.lines.splice(index..=index, pasted);
+            self.focus(last);
+        } else if text.is_empty() && self.lines[index].text.is_empty() && self.lines.len() > 1 {
+            // Backspace or Delete on an empty line, which does not change it
+            self.lines.remove(index);
+            self.focus(index.saturating_sub(1));
+        } else {
+            self.lines[index].text = text;
+        }
+    }
+
+    /// Index of the line being edited
+    fn focused(&self) -> Option<usize> {
+        self.lines.iter().position(|line| line.state.is_focused())
+    }
+
+    /// Move the cursor to the end of the line at `index`
+    fn focus(&mut self, index: usize) {
+        for line in &mut self.lines {
+            line.state.unfocus();
+        }
+        if let Some(line) = self.lines.get_mut(index) {
+            line.state.focus();
+            line.state.move_cursor_to_end();
+        }
+    }
+
+    /// Append a line to the output, dropping the oldest ones beyond [`MAX_LINES`]
+    fn write(&mut self, line: String, error: bool) {
+        self.output.push((line, error));
+        if self.output.len() > MAX_LINES {
+            self.output.drain(..self.output.len() - MAX_LINES);
+        }
+        self.output_state.snap_to(1.0);
+    }
+}
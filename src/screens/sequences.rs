@@ -0,0 +1,234 @@
+//! Provides a screen running the sequences of the config step by step, showing the progress and reply of each step
+use crate::{styles::{self, ui_button}, messages::{*, SequenceMessage::*}};
+use iced::{button, scrollable, alignment::Alignment, Button, Color, Column, Container, Length, Row, Scrollable, Text};
+use instrument_core::{devices::{self, sequence::PreparedStep}, networking::{Reply, Response}};
+
+/// Progress of a step of the selected sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Progress {
+    /// Not run yet, or not run because the sequence has been stopped
+    Waiting,
+    /// Sent to the device, the reply is pending
+    Running,
+    /// Completed, contains the reply if there is one
+    Done(String),
+    /// Failed, contains the error message
+    Failed(String),
+}
+
+/// Sequences of the config and the progress of the one selected
+pub struct SequenceScreen {
+    /// Config the commands of the steps are built from
+    config: devices::Configuration,
+    /// Index of the selected sequence
+    selected: usize,
+    sequence_button_states: Vec<button::State>,
+    list_state: scrollable::State,
+    steps_state: scrollable::State,
+    run_button_state: button::State,
+    stop_button_state: button::State,
+    /// Progress of each step of the selected sequence
+    progress: Vec<Progress>,
+    /// Index of the step to run next while the sequence is running, [`None`] if it is not running
+    next: Option<usize>,
+    /// Whether a step is currently in flight and its reply is pending
+    pending: bool,
+}
+
+impl SequenceScreen {
+    /// Creates a new screen for the sequences of `config`
+    pub fn from(config: &devices::Configuration) -> Self {
+        let mut screen = Self {
+            config: config.clone(),
+            selected: 0,
+            sequence_button_states: Vec::new(),
+            list_state: scrollable::State::default(),
+            steps_state: scrollable::State::default(),
+            run_button_state: button::State::default(),
+            stop_button_state: button::State::default(),
+            progress: Vec::new(),
+            next: None,
+            pending: false,
+        };
+        screen.select(0);
+        screen
+    }
+
+    /// Use the sequences of `config` after it has been loaded again, keeping the selected sequence by name
+    /// A running sequence is stopped if its steps have changed.
+    pub fn reload(&mut self, config: &devices::Configuration) {
+        let old = self.config.sequences.get(self.selected).cloned();
+        self.config = config.clone();
+        self.sequence_button_states = config.sequences.iter().map(|_| button::State::default()).collect();
+        let kept = old.as_ref().and_then(|old| config.sequences.iter().position(|sequence| sequence.name == old.name));
+        self.selected = kept.unwrap_or(0);
+        if config.sequences.get(self.selected) != old.as_ref() {
+            self.next = None;
+            self.progress = vec![Progress::Waiting; self.steps().len()];
+        }
+    }
+
+    /// Display the list of sequences and the steps of the selected one with their progress
+    pub fn view(&mut self) -> Container<'_, Message> {
+        let running = self.is_running();
+        let selected = self.selected;
+        let list = self.config.sequences.iter().zip(self.sequence_button_states.iter_mut()).enumerate().fold(
+            Scrollable::new(&mut self.list_state).spacing(4).height(Length::Units(330)).width(Length::Units(220)),
+            |list, (index, (sequence, state))| {
+                let mut button = Button::new(state, Text::new(&sequence.name).size(16)).width(Length::Fill);
+                if !running {
+                    button = button.on_press(Message::Sequence(SequenceSelected(index)));
+                }
+                let style = if index == selected { styles::Button::ActiveControl } else { styles::Button::Control };
+                list.push(button.style(style))
+            },
+        );
+
+        let sequence = match self.config.sequences.get(self.selected) {
+            Some(sequence) => sequence,
+            None => return Container::new(Text::new("No sequences defined, add [[sequences]] to the config to run them here"))
+                .width(Length::Fill)
+                .padding(20),
+        };
+        let steps = sequence.steps.iter().zip(self.progress.iter()).enumerate().fold(
+            Scrollable::new(&mut self.steps_state).spacing(8).height(Length::Units(270)).width(Length::Fill),
+            |steps, (index, (step, progress))| {
+                let (status, reply, color) = match progress {
+                    Progress::Waiting => ("", "", Color::BLACK),
+                    Progress::Running => ("running...", "", Color::BLACK),
+                    Progress::Done(reply) => ("done", reply.as_str(), Color::from_rgb(0.0, 0.5, 0.0)),
+                    Progress::Failed(error) => ("failed", error.as_str(), Color::from_rgb(0.8, 0.0, 0.0)),
+                };
+                let mut column = Column::new()
+                    .push(Row::new()
+                        .spacing(10)
+                        .push(Text::new(format!("{}. {}", index + 1, step)).size(16).width(Length::Fill))
+                        .push(Text::new(status).size(16).color(color)));
+                if !reply.is_empty() {
+                    column = column.push(Text::new(reply).size(14).color(color));
+                }
+                steps.push(column)
+            },
+        );
+
+        let mut run_button = ui_button(&mut self.run_button_state, "Run".into(), styles::Button::Submit);
+        let mut stop_button = ui_button(&mut self.stop_button_state, "Stop".into(), styles::Button::Cancel);
+        if running {
+            stop_button = stop_button.on_press(Message::Sequence(Stop));
+        } else {
+            run_button = run_button.on_press(Message::Sequence(Run));
+        }
+        let mode = if sequence.stop_on_error { "Stops at the first failing step" } else { "Runs all steps, even if one fails" };
+
+        Container::new(
+            Row::new()
+                .spacing(20)
+                .push(list)
+                .push(
+                    Column::new()
+                        .spacing(10)
+                        .push(Text::new(&sequence.name).size(24))
+                        .push(Text::new(mode).size(14))
+                        .push(steps)
+                        .push(Row::new().spacing(10).align_items(Alignment::Center).push(run_button).push(stop_button))
+                )
+        )
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(10)
+    }
+
+    /// Update the selection and start or stop the selected sequence according to generated [`SequenceMessage`]s
+    /// The steps are run by the application root, which takes them from [`SequenceScreen::next_step`].
+    pub fn update(&mut self, message: SequenceMessage) {
+        match message {
+            SequenceSelected(index) if !self.is_running() => self.select(index),
+            SequenceSelected(_) => {}
+            Run if !self.is_running() => {
+                self.progress = vec![Progress::Waiting; self.steps().len()];
+                self.next = Some(0);
+            }
+            Run => {}
+            Stop => self.next = None,
+        }
+    }
+
+    /// Take the next step of the running sequence to run it, returns [`None`] if there is none or a step is in flight
+    /// Steps whose command cannot be built fail right away.
+    pub fn next_step(&mut self) -> Option<(usize, PreparedStep)> {
+        if self.pending {
+            return None;
+        }
+        let sequence = self.config.sequences.get(self.selected)?;
+        let check_errors = self.config.device.check_errors || sequence.stop_on_error;
+        let error_query = check_errors.then(|| self.config.device.error_query.clone());
+        loop {
+            let index = self.next?;
+            let prepared = match self.steps().get(index) {
+                Some(step) => step.prepare(&self.config, error_query.as_deref()),
+                None => {
+                    self.next = None;
+                    return None;
+                }
+            };
+            self.next = Some(index + 1);
+            match prepared {
+                Ok(prepared) => {
+                    self.progress[index] = Progress::Running;
+                    self.pending = true;
+                    return Some((index, prepared));
+                }
+                Err(e) => self.fail(index, e),
+            }
+        }
+    }
+
+    /// Show the [`Reply`] to step number `index`, or the error that occurred while running it
+    pub fn set_result(&mut self, index: usize, result: Result<Reply, String>) {
+        self.pending = false;
+        let reply = match result {
+            Ok(reply) if !reply.errors.is_empty() => Err(format!("The device reported {}", reply.errors.join(", "))),
+            Ok(reply) => Ok(match reply.response {
+                Some(Response::Text(text)) => text.lines().collect::<Vec<&str>>().join("; "),
+                Some(Response::Block(data)) => format!("Binary reply: {} bytes", data.len()),
+                None => String::new(),
+            }),
+            Err(e) => Err(e),
+        };
+        match reply {
+            Ok(reply) => {
+                if let Some(progress) = self.progress.get_mut(index) {
+                    *progress = Progress::Done(reply);
+                }
+            }
+            Err(e) => self.fail(index, e),
+        }
+    }
+
+    /// Whether the selected sequence is running
+    fn is_running(&self) -> bool {
+        self.next.is_some() || self.pending
+    }
+
+    /// Mark step number `index` as failed, which stops the sequence if it should stop on errors
+    fn fail(&mut self, index: usize, error: String) {
+        if let Some(progress) = self.progress.get_mut(index) {
+            *progress = Progress::Failed(error);
+        }
+        if self.config.sequences.get(self.selected).is_some_and(|sequence| sequence.stop_on_error) {
+            self.next = None;
+        }
+    }
+
+    /// Select sequence number `index` and clear the progress
+    fn select(&mut self, index: usize) {
+        self.sequence_button_states = self.config.sequences.iter().map(|_| button::State::default()).collect();
+        self.selected = index;
+        self.progress = vec![Progress::Waiting; self.steps().len()];
+    }
+
+    /// Steps of the selected sequence
+    fn steps(&self) -> &[devices::sequence::Step] {
+        self.config.sequences.get(self.selected).map_or(&[], |sequence| &sequence.steps)
+    }
+}
@@ -1,21 +1,33 @@
 //! Provides a settings screen to define connection parameters and connect to the device
 
-use crate::{styles::{self, ui_button}, messages::*, networking::DeviceConnection};
-use iced::{Container, Text, text_input, button, Length, alignment::Alignment, TextInput, Color, Column};
-use std::{ops::Add, net::{SocketAddr}};
+use crate::{styles::{self, ui_button}, messages::*};
+use instrument_core::{devices, networking::{self, ConnectionSettings, Identity, SharedConnection, TransportKind}};
+use iced::{Command, Container, Text, text_input, button, pick_list, Length, alignment::Alignment, TextInput, PickList, Color, Column};
 
 /// Textfield and button states held by the settings screen
 #[derive(Default)]
 pub struct SettingsScreen {
+    /// Selected transport and its settings from the device config
+    connection_settings: ConnectionSettings,
+    transport_list_state: pick_list::State<TransportKind>,
     ip_address_state: text_input::State,
     ip_address: String,
     port_state: text_input::State,
     port: String,
     save_button_state: button::State,
-    address: Option<SocketAddr>,
+    /// Address as expected by the selected transport, [`None`] if the entered values are invalid
+    address: Option<String>,
     status_text: String,
     connection_status_text: String,
-    device_connection: DeviceConnection,
+    /// Whether a connection attempt is currently running in the background
+    connecting: bool,
+    /// Whether the last connection attempt succeeded
+    connected: bool,
+    /// Identity the connected device reported, if it answered `*IDN?`
+    identity: Option<Identity>,
+    /// Index of the device profile this screen belongs to
+    profile: usize,
+    device_connection: SharedConnection,
 }
 
 impl SettingsScreen {
@@ -28,14 +40,38 @@ impl SettingsScreen {
         }
     }
 
+    /// Creates a new settings screen for device profile number `profile`
+    /// The transport and address are pre-filled according to the device config.
+    pub fn from(device: &devices::Device, profile: usize) -> Self {
+        let (ip_address, port) = device.connection.transport.split_address(&device.address);
+        let mut settings_screen = Self {
+            profile,
+            connection_settings: device.connection.clone(),
+            ip_address,
+            port,
+            ..Self::default()
+        };
+        if !device.address.trim().is_empty() {
+            settings_screen.check_address();
+        }
+        settings_screen
+    }
+
     /// Display a settings screen according to what data has been entered by the user
     /// This also takes into account whether the IP and port are parsable and provides information
     /// about the current status of the connection.
-    pub fn view(&mut self) -> Container<Message> {
-        // Make a TextInput for the IP address
+    pub fn view(&mut self) -> Container<'_, Message> {
+        // Make a PickList for the transport
+        let transport_list =
+            PickList::new(&mut self.transport_list_state,
+                          &TransportKind::ALL[..],
+                          Some(self.connection_settings.transport),
+                          |kind| { Message::Settings(SettingsMessage::TransportSelected(kind)) })
+                .width(Length::Units(150));
+        // Make a TextInput for the IP address, or the serial port
         let mut ti_ip_address =
             TextInput::new(&mut self.ip_address_state,
-                           "IP Address",
+                           self.connection_settings.transport.host_placeholder(),
                            &self.ip_address,
                            |ip| { Message::Settings(SettingsMessage::IpEntered(ip)) })
                 .width(Length::Units(150))
@@ -56,21 +92,27 @@ impl SettingsScreen {
                       styles::Button::Submit)
                 .width(Length::Units(150));
 
-        // If we have a valid address, make the form submittable via save button or enter key
-        if let Some(addr) = self.address {
-            save_button = save_button.on_press(Message::Settings(SettingsMessage::Connect(addr)));
-            ti_ip_address = ti_ip_address.on_submit(Message::Settings(SettingsMessage::Connect(addr)));
-            ti_port = ti_port.on_submit(Message::Settings(SettingsMessage::Connect(addr)));
+        // If we have a valid address and are not already connecting, make the form submittable via save button or enter key
+        if let (Some(addr), false) = (&self.address, self.connecting) {
+            let connect = Message::Settings(SettingsMessage::Connect(self.connection_settings.clone(), addr.clone()));
+            save_button = save_button.on_press(connect.clone());
+            ti_ip_address = ti_ip_address.on_submit(connect.clone());
+            ti_port = ti_port.on_submit(connect);
         }
 
         // Put everything together in a container and add some info text
+        // The port is only shown for transports that use one
+        let mut column = Column::new()
+            .align_items(Alignment::Center)
+            .push(Text::new(&self.connection_status_text).color(Color::BLACK).width(Length::Shrink))
+            .spacing(20)
+            .push(transport_list)
+            .push(ti_ip_address);
+        if self.connection_settings.transport.default_port().is_some() {
+            column = column.push(ti_port);
+        }
         Container::new(
-            Column::new()
-                .align_items(Alignment::Center)
-                .push(Text::new(&self.connection_status_text).color(Color::BLACK).width(Length::Shrink))
-                .spacing(20)
-                .push(ti_ip_address)
-                .push(ti_port)
+            column
                 .push(Text::new(&self.status_text).color(Color::BLACK).width(Length::Shrink))
                 .push(save_button)
         )
@@ -81,42 +123,118 @@ impl SettingsScreen {
     }
 
     /// Update the fields according to generated [`SettingsMessage`]s
-    pub fn update(&mut self, message: SettingsMessage) {
+    pub fn update(&mut self, message: SettingsMessage) -> Command<Message> {
+        let mut command = Command::none();
         match message {
+            SettingsMessage::TransportSelected(kind) => {
+                // Suggest the usual port of the new transport unless the user already typed a different one
+                let current_default = self.connection_settings.transport.default_port().map(|p| p.to_string());
+                if self.port.is_empty() || Some(&self.port) == current_default.as_ref() {
+                    self.port = kind.default_port().map(|p| p.to_string()).unwrap_or_default();
+                }
+                self.connection_settings.transport = kind;
+            }
             SettingsMessage::IpEntered(ip) => self.ip_address = ip,
             SettingsMessage::PortEntered(port) => self.port = port,
-            SettingsMessage::Connect(addr) => {
-                self.connection_status_text = match self.device_connection.connect(&addr) {
-                    Ok(_) => { format!("Connected to {}", addr) }
+            SettingsMessage::Connect(settings, addr) => {
+                self.connecting = true;
+                self.connection_status_text = format!("Connecting to {}...", addr);
+                let profile = self.profile;
+                command = Command::perform(
+                    networking::connect_and_identify(self.device_connection.clone(), settings, addr),
+                    move |result| Message::Settings(SettingsMessage::Connected(profile, result)),
+                );
+            }
+            SettingsMessage::Connected(_, result) => {
+                self.connecting = false;
+                self.connected = result.is_ok();
+                self.identity = None;
+                self.connection_status_text = match result {
+                    Ok(peer) => {
+                        match peer.identity {
+                            Ok(identity) => {
+                                let status = format!("Connected to {}\n{}", peer.description, identity);
+                                self.identity = Some(identity);
+                                status
+                            }
+                            Err(e) => { format!("Connected to {}\nUnknown device: {}", peer.description, e) }
+                        }
+                    }
                     Err(e) => { format!("Connection failed: Error: {}", e) }
                 }
             }
         }
+        self.check_address();
+        command
+    }
 
-        // Check whether the entered IP is valid or not
-        if let Ok(addr) = crate::networking::parse_ip(&self.combine_ip_and_port()) {
-            self.address = Some(addr);
-            self.status_text = "IP OK".into();
-        } else {
-            self.address = None; // TODO: Check whether it is smart to "delete" the address here or not
-            self.status_text = "IP/port not parsable".into();
+    /// Connect to the entered address, if it is valid and there is no connection yet
+    pub fn connect(&mut self) -> Command<Message> {
+        if self.is_busy() {
+            return Command::none();
+        }
+        match self.address.clone() {
+            Some(addr) => self.update(SettingsMessage::Connect(self.connection_settings.clone(), addr)),
+            None => Command::none(),
         }
     }
 
-    /// Utility function to create an address in the form of `IP:PORT`
-    fn combine_ip_and_port(&self) -> String {
-        self.ip_address.clone().add(":").add(&self.port)
+    /// Check whether the entered address is valid for the selected transport or not
+    fn check_address(&mut self) {
+        match self.connection_settings.transport.make_address(&self.ip_address, &self.port) {
+            Ok(addr) => {
+                self.address = Some(addr);
+                self.status_text = "IP OK".into();
+            }
+            Err(_) => {
+                self.address = None; // TODO: Check whether it is smart to "delete" the address here or not
+                self.status_text = "IP/port not parsable".into();
+            }
+        }
     }
 
-    /// Return the [`SocketAddr`] held by this screen wrapped in an option
+    /// Return the address held by this screen wrapped in an option
     /// Returns [`None`] if there is no valid address entered
-    #[allow(dead_code)]
-    pub fn get_address(&self) -> Option<SocketAddr> {
-        self.address
+    pub fn get_address(&self) -> Option<&str> {
+        self.address.as_deref()
+    }
+
+    /// Describes whether the device is connected, e.g. "Connected to 10.10.2.8:5555 (raw socket)"
+    pub fn connection_status(&self) -> &str {
+        &self.connection_status_text
+    }
+
+    /// Make this screen belong to device profile number `profile`, after the profiles have been loaded again
+    pub fn set_profile(&mut self, profile: usize) {
+        self.profile = profile;
+    }
+
+    /// Identity reported by the connected device, [`None`] if not connected or it did not answer `*IDN?`
+    pub fn identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    /// Whether the device is connected or a connection attempt is running
+    pub fn is_busy(&self) -> bool {
+        self.connected || self.connecting
+    }
+
+    /// Give the connection, the address it was made to and the identity of the device to `other`
+    /// `other` hands its own connection over in turn, so both screens stay consistent.
+    pub fn hand_over(&mut self, other: &mut SettingsScreen) {
+        std::mem::swap(&mut self.device_connection, &mut other.device_connection);
+        std::mem::swap(&mut self.connection_settings, &mut other.connection_settings);
+        std::mem::swap(&mut self.ip_address, &mut other.ip_address);
+        std::mem::swap(&mut self.port, &mut other.port);
+        std::mem::swap(&mut self.connected, &mut other.connected);
+        std::mem::swap(&mut self.identity, &mut other.identity);
+        std::mem::swap(&mut self.connection_status_text, &mut other.connection_status_text);
+        self.check_address();
+        other.check_address();
     }
 
-    /// Return a mutable reference to the [`DeviceConnection`]
-    pub fn device_connection(&mut self) -> &mut DeviceConnection {
-        &mut self.device_connection
+    /// Return a handle to the [`SharedConnection`] that can be passed to background tasks
+    pub fn device_connection(&self) -> SharedConnection {
+        self.device_connection.clone()
     }
 }
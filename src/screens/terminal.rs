@@ -0,0 +1,147 @@
+//! Provides a terminal to type SCPI commands directly, with history and completion from the config
+
+use crate::{styles::{self, ui_button}, history::History, messages::{*, TerminalMessage::*}};
+use instrument_core::{devices::{self, completion::{Completer, Completion}}, networking::{Reply, Request, Response}};
+use iced::{button, scrollable, text_input, alignment::Alignment, Color, Column, Container, Length, Row, Scrollable, Text, TextInput};
+
+/// Highest number of lines kept in the scrollback
+const MAX_LINES: usize = 1000;
+
+/// Scrollback, input line and the states of the widgets of the terminal screen
+#[derive(Default)]
+pub struct TerminalScreen {
+    /// Command being typed
+    input: String,
+    input_state: text_input::State,
+    /// Sent commands and received replies, each with a timestamp and paired with whether it reports an error
+    scrollback: Vec<(String, bool)>,
+    scroll_state: scrollable::State,
+    send_button_state: button::State,
+    clear_button_state: button::State,
+    /// Completes commands from the config
+    completer: Completer,
+    /// Whether a command is currently in flight and its reply is pending
+    pending: bool,
+}
+
+impl TerminalScreen {
+    /// Creates a new terminal completing the commands of `config`
+    pub fn from(config: &devices::Configuration) -> Self {
+        Self {
+            completer: Completer::from(config),
+            ..Default::default()
+        }
+    }
+
+    /// Complete the commands of `config` after it has been loaded again
+    pub fn reload(&mut self, config: &devices::Configuration) {
+        self.completer = Completer::from(config);
+    }
+
+    /// Display the scrollback above the input line
+    pub fn view(&mut self) -> Container<'_, Message> {
+        let scrollback = self.scrollback.iter().fold(
+            Scrollable::new(&mut self.scroll_state).spacing(2).height(Length::Units(330)).width(Length::Fill),
+            |scrollback, (line, error)| {
+                let color = if *error { Color::from_rgb(0.8, 0.0, 0.0) } else { Color::BLACK };
+                scrollback.push(Text::new(line).size(16).color(color))
+            },
+        );
+
+        let mut input = TextInput::new(&mut self.input_state, "Type an SCPI command, e.g. *IDN?", &self.input, |input| Message::Terminal(InputChanged(input)))
+            .padding(5)
+            .style(styles::Textbox::Freetext);
+        let mut send_button = ui_button(&mut self.send_button_state, "Send".into(), styles::Button::Submit);
+        if !self.pending && !self.input.trim().is_empty() {
+            input = input.on_submit(Message::Terminal(Submit));
+            send_button = send_button.on_press(Message::Terminal(Submit));
+        }
+
+        Container::new(
+            Column::new()
+                .spacing(10)
+                .push(scrollback)
+                .push(
+                    Row::new()
+                        .spacing(10)
+                        .align_items(Alignment::Center)
+                        .push(input)
+                        .push(send_button)
+                        .push(ui_button(&mut self.clear_button_state, "Clear".into(), styles::Button::Control)
+                            .on_press(Message::Terminal(Clear)))
+                )
+                .push(Text::new("Up/Down: history, Tab: complete").size(14))
+        )
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(10)
+    }
+
+    /// Update the input and scrollback according to generated [`TerminalMessage`]s, walking through `history`
+    /// Sending is done by [`TerminalScreen::submit`], so the application root can run it in the background.
+    pub fn update(&mut self, message: TerminalMessage, history: &mut History) {
+        match message {
+            InputChanged(input) => self.input = input,
+            HistoryOlder => {
+                if let Some(command) = history.older() {
+                    self.input = command.to_string();
+                }
+            }
+            HistoryNewer => {
+                if let Some(command) = history.newer() {
+                    self.input = command.to_string();
+                }
+            }
+            Complete => match self.completer.complete(&self.input) {
+                Completion::Extended(input) => self.input = input,
+                Completion::Ambiguous(candidates) => self.append(format!("   {}", candidates.join("   ")), false),
+                Completion::None => {}
+            },
+            Clear => self.scrollback.clear(),
+            Submit => return,
+        }
+        self.input_state.move_cursor_to_end();
+    }
+
+    /// Take the typed command to send it and add it to `history`, returns [`None`] if there is nothing to send or a
+    /// reply is pending
+    pub fn submit(&mut self, history: &mut History) -> Option<Request> {
+        let command = self.input.trim().to_string();
+        if command.is_empty() || self.pending {
+            return None;
+        }
+        history.push(&command);
+        self.input.clear();
+        self.pending = true;
+        self.append(format!("> {}", command), false);
+        let reply_lines = if devices::command::is_query(&command) { 1 } else { 0 };
+        Some(Request { command: format!("{}\n", command), reply_lines, error_query: None })
+    }
+
+    /// Show the [`Reply`] to the last command, or the error that occurred while sending it
+    pub fn set_reply(&mut self, result: Result<Reply, String>) {
+        self.pending = false;
+        match result {
+            Ok(reply) => match reply.response {
+                Some(Response::Text(text)) => text.lines().for_each(|line| self.append(format!("< {}", line), false)),
+                Some(Response::Block(data)) => self.append(format!("< Binary reply: {} bytes", data.len()), false),
+                None => {}
+            },
+            Err(e) => self.append(format!("! {}", e), true),
+        }
+    }
+
+    /// Put the cursor into the input line
+    pub fn focus(&mut self) {
+        self.input_state.focus();
+        self.input_state.move_cursor_to_end();
+    }
+
+    /// Add a line with the current time to the scrollback and scroll to it, `error` shows it in red
+    fn append(&mut self, line: String, error: bool) {
+        self.scrollback.push((format!("{} {}", chrono::Local::now().format("%H:%M:%S%.3f"), line), error));
+        let excess = self.scrollback.len().saturating_sub(MAX_LINES);
+        self.scrollback.drain(..excess);
+        self.scroll_state.snap_to(1.0);
+    }
+}
@@ -9,12 +9,16 @@ use crate::styles;
 pub struct ToolBar {
     settings_button_state: button::State,
     command_button_state: button::State,
+    terminal_button_state: button::State,
+    editor_button_state: button::State,
+    sequences_button_state: button::State,
+    scripts_button_state: button::State,
 }
 
 impl ToolBar {
 
     /// Define the button styles according to what [`ScreenType`] is displayed
-    pub fn view(&mut self, content: &ScreenType) -> Container<Message> {
+    pub fn view(&mut self, content: &ScreenType) -> Container<'_, Message> {
 
         Container::new(Column::new().push(
             Row::new()
@@ -23,18 +27,32 @@ impl ToolBar {
                 .push(
                     Button::new(&mut self.command_button_state, Text::new("Command"))
                         .on_press(Message::ChangeView(ScreenType::Command))
-                        .style(match content {
-                            ScreenType::Settings => {styles::Button::Control}
-                            ScreenType::Command => {styles::Button::ActiveControl}
-                        }),
+                        .style(style(content, ScreenType::Command)),
                 )
                 .push(
                     Button::new(&mut self.settings_button_state, Text::new("Settings"))
                         .on_press(Message::ChangeView(ScreenType::Settings))
-                        .style(match content {
-                            ScreenType::Settings => {styles::Button::ActiveControl}
-                            ScreenType::Command => {styles::Button::Control}
-                        }),
+                        .style(style(content, ScreenType::Settings)),
+                )
+                .push(
+                    Button::new(&mut self.terminal_button_state, Text::new("Terminal"))
+                        .on_press(Message::ChangeView(ScreenType::Terminal))
+                        .style(style(content, ScreenType::Terminal)),
+                )
+                .push(
+                    Button::new(&mut self.sequences_button_state, Text::new("Sequences"))
+                        .on_press(Message::ChangeView(ScreenType::Sequences))
+                        .style(style(content, ScreenType::Sequences)),
+                )
+                .push(
+                    Button::new(&mut self.scripts_button_state, Text::new("Scripts"))
+                        .on_press(Message::ChangeView(ScreenType::Scripts))
+                        .style(style(content, ScreenType::Scripts)),
+                )
+                .push(
+                    Button::new(&mut self.editor_button_state, Text::new("Editor"))
+                        .on_press(Message::ChangeView(ScreenType::Editor))
+                        .style(style(content, ScreenType::Editor)),
                 )
         )
             .push(Rule::horizontal(20))
@@ -53,4 +71,9 @@ impl ToolBar {
         /* maybe do some toolbar updating here */
     }
      */
-}
\ No newline at end of file
+}
+
+/// Style of the button for `screen`, highlighted if it is the displayed one
+fn style(content: &ScreenType, screen: ScreenType) -> styles::Button {
+    if *content == screen { styles::Button::ActiveControl } else { styles::Button::Control }
+}
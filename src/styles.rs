@@ -18,7 +18,6 @@ pub enum Button {
     /// Submit button, used to save or send a form
     Submit,
     /// Cancel button, used to discard changes or stop an operation
-    #[allow(dead_code)]
     Cancel,
 }
 
@@ -27,7 +26,7 @@ pub enum Button {
 /// - `state`: the button's [`State`] that is held by its parent
 /// - `label`: a [`String`] specifying the text on the button
 /// - `style`: a variant of a [`Button`] that defines its style
-pub fn ui_button(state: &mut State, label: String, style: Button) -> iced::Button<Message> {
+pub fn ui_button(state: &mut State, label: String, style: Button) -> iced::Button<'_, Message> {
     iced::Button::new(
         state,
         Text::new(label)
@@ -85,7 +84,6 @@ impl Button {
             border_color: Color::BLACK,
             border_radius: 4.0,
             border_width: 1.0,
-            ..button::Style::default() // .. means: other fields from default
         }
     }
 }
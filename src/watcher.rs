@@ -0,0 +1,161 @@
+//! Watches the config files for changes so they can be loaded again while the app is running
+use crate::messages::Message;
+use iced::{futures::{channel::mpsc, StreamExt}, Subscription};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{path::{Path, PathBuf}, sync::mpsc::RecvTimeoutError, thread, time::Duration};
+
+/// Time to wait for further changes before reporting one, editors often write a file in several steps
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// State of the subscription returned by [`config_changes`]
+enum State {
+    /// The watcher has not been started yet, holds the config path and the include files
+    Starting(String, Vec<String>),
+    /// The watcher is running and reports changes through the receiver
+    Watching(RecommendedWatcher, mpsc::UnboundedReceiver<()>),
+    /// Watching failed, nothing will be reported
+    Stopped,
+}
+
+/// Subscription that sends [`Message::ConfigChanged`] whenever a config file at `path` or one of the `includes` changes
+/// The directories holding the files are watched, so files saved by writing a new file and renaming it are noticed.
+/// Watching starts over when the include files change.
+pub fn config_changes(path: String, includes: Vec<String>) -> Subscription<Message> {
+    let id = (path.clone(), includes.clone());
+    iced_native::subscription::unfold(id, State::Starting(path, includes), |state| async move {
+        match state {
+            State::Starting(path, includes) => {
+                let (sender, receiver) = mpsc::unbounded();
+                match watch(&path, &includes, move || sender.unbounded_send(()).is_ok()) {
+                    Ok(watcher) => (None, State::Watching(watcher, receiver)),
+                    Err(e) => (Some(Message::ConfigWatchFailed(e.to_string())), State::Stopped),
+                }
+            }
+            State::Watching(watcher, mut receiver) => match receiver.next().await {
+                Some(()) => (Some(Message::ConfigChanged), State::Watching(watcher, receiver)),
+                None => (None, State::Stopped),
+            },
+            State::Stopped => iced::futures::future::pending().await,
+        }
+    })
+}
+
+/// Watch the config file or directory `path` and the `includes` and call `on_change` once the files stopped changing
+/// A directory given as `path` is watched with its subdirectories and any `.toml` file in it counts, otherwise only
+/// changes of the config file and the include files do.
+/// Watching ends when the returned watcher is dropped or `on_change` returns `false`.
+pub fn watch(path: &str, includes: &[String], on_change: impl Fn() -> bool + Send + 'static) -> notify::Result<RecommendedWatcher> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let mut watched: Vec<(PathBuf, RecursiveMode)> = Vec::new();
+    let mut files: Vec<PathBuf> = includes.iter().map(|include| file_of(include)).collect();
+    let profiles = Path::new(path).is_dir().then(|| canonical(Path::new(path)));
+    match &profiles {
+        Some(directory) => watched.push((directory.clone(), RecursiveMode::Recursive)),
+        None => {
+            watched.push((directory_of(path), RecursiveMode::NonRecursive));
+            // The extension of the config file may have been left out
+            files.push(file_of(path));
+            files.push(file_of(&format!("{}.toml", path)));
+        }
+    }
+    for include in includes {
+        let directory = directory_of(include);
+        let covered = watched.iter().any(|(other, mode)| match mode {
+            RecursiveMode::Recursive => directory.starts_with(other),
+            RecursiveMode::NonRecursive => directory == *other,
+        });
+        // The directory of an include file that has been removed may be gone, it is not watched then
+        if !covered && directory.is_dir() {
+            watched.push((directory, RecursiveMode::NonRecursive));
+        }
+    }
+    for (directory, mode) in &watched {
+        watcher.watch(directory, *mode)?;
+    }
+
+    thread::spawn(move || {
+        while let Ok(event) = receiver.recv() {
+            if !event.is_ok_and(|event: notify::Event| is_config_change(&event, profiles.as_deref(), &files)) {
+                continue;
+            }
+            loop {
+                match receiver.recv_timeout(SETTLE_TIME) {
+                    Ok(_) => continue,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            if !on_change() {
+                return;
+            }
+        }
+    });
+    Ok(watcher)
+}
+
+/// Directory holding the file `path`, resolved so that directories can be compared
+fn directory_of(path: &str) -> PathBuf {
+    let directory = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    canonical(directory)
+}
+
+/// The file `path` in its resolved directory, comparable to the paths of events
+fn file_of(path: &str) -> PathBuf {
+    directory_of(path).join(Path::new(path).file_name().unwrap_or_default())
+}
+
+/// `path` with links and `..` resolved, or as it is if it does not exist
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Whether `event` means one of the `files` or a config file below the directory of profiles has been written,
+/// created or removed
+fn is_config_change(event: &notify::Event, profiles: Option<&Path>, files: &[PathBuf]) -> bool {
+    let is_config = |path: &PathBuf| {
+        files.contains(path)
+            || profiles.is_some_and(|profiles| path.starts_with(profiles) && path.extension().is_some_and(|extension| extension == "toml"))
+    };
+    matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)) && event.paths.iter().any(is_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_are_reported() {
+        let directory = std::env::temp_dir().join(format!("instrument-app-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("config.toml");
+        std::fs::write(&file, "[device]\n").unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let other = directory.join("other");
+        let common = directory.join("common");
+        std::fs::create_dir_all(&other).unwrap();
+        std::fs::create_dir_all(&common).unwrap();
+        let includes = vec![common.join("set.toml").to_string_lossy().into_owned()];
+        let watcher = watch(file.to_str().unwrap(), &includes, move || sender.send(()).is_ok()).unwrap();
+        // Other files, other configs and subdirectories without include files do not count as a change
+        std::fs::write(directory.join("notes.txt"), "not a config").unwrap();
+        std::fs::write(directory.join("bench.toml"), "[[devices]]\n").unwrap();
+        std::fs::write(common.join("other.toml"), "[[commands]]\n").unwrap();
+        std::fs::write(other.join("other.toml"), "[device]\n").unwrap();
+        assert!(receiver.recv_timeout(SETTLE_TIME * 3).is_err());
+
+        std::fs::write(&includes[0], "[[commands]]\n").unwrap();
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert!(receiver.recv_timeout(SETTLE_TIME * 3).is_err());
+
+        std::fs::write(&file, "[device]\nchannels = 2\n").unwrap();
+        std::fs::write(&file, "[device]\nchannels = 3\n").unwrap();
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        // Both writes are reported as one change
+        assert!(receiver.recv_timeout(SETTLE_TIME * 3).is_err());
+
+        drop(watcher);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}